@@ -6,25 +6,25 @@
 
 #define IS_DIGITAL_PIN(x) ((x) >= 2 && (x) <= 13)
 #define IS_ANALOG_PIN(x) ((x) >= A0 && (x) < A7)
-#define IS_PWM_PIN(x) (x == 3 || x == 5 || x == 6 || x == 9 || x == 10 || x == 11)
+#define IS_PWM_PIN(x) ((x) == 3 || (x) == 5 || (x) == 6 || (x) == 9 || (x) == 10 || (x) == 11)
 
 // only analog pins can use analogRead()
 #define IS_VALID_PIN_FOR_ANALOG_READ(x) IS_ANALOG_PIN(x)
 
 // both analog and digital pins can use digitalRead()
-#define IS_VALID_PIN_FOR_DIGITAL_READ(x) IS_DIGITAL_PIN(x) || IS_ANALOG_PIN(x)
+#define IS_VALID_PIN_FOR_DIGITAL_READ(x) (IS_DIGITAL_PIN(x) || IS_ANALOG_PIN(x))
 
 // both analog and digital pins can use digitalWrite()
-#define IS_VALID_PIN_FOR_DIGITAL_WRITE(x) IS_DIGITAL_PIN(x) || IS_ANALOG_PIN(x)
+#define IS_VALID_PIN_FOR_DIGITAL_WRITE(x) (IS_DIGITAL_PIN(x) || IS_ANALOG_PIN(x))
 
 // on most arduinos, only some pins can do analogWrite() (PWM pins)
 #define IS_VALID_PIN_FOR_ANALOG_WRITE(x) IS_PWM_PIN(x)
 
 // invert the value from analogRead()
-#define INVERT_ANALOG_READ_VALUE(x) (1024 - x)
+#define INVERT_ANALOG_READ_VALUE(x) (1024 - (x))
 
 // invert the value supplied to analogWrite()
-#define INVERT_ANALOG_WRITE_VALUE(x) (255 - x)
+#define INVERT_ANALOG_WRITE_VALUE(x) (255 - (x))
 
 //
 // SDSP
@@ -49,6 +49,7 @@ void sdsp_serial_write(uint8_t data)
 #define TYPE_WRITE 0x02
 #define TYPE_TOGGLE 0x03
 #define TYPE_IIC_WRITE 0x04
+#define TYPE_ECHO 0x05
 #define TYPE_ERROR 0x7f
 
 #define MAKE_REQUEST_TYPE(x) (x & ~(0 << 7))
@@ -275,6 +276,13 @@ void handle_packet(uint8_t pkg_buffer[], uint16_t pkg_len, uint8_t from)
         sdsp_write_packet(response, 2, OWN_DEVICE_ID, from);
         return;
     }
+    case MAKE_REQUEST_TYPE(TYPE_ECHO):
+    {
+        // send the data back unchanged, reusing the packet buffer
+        pkg_buffer[0] = MAKE_RESPONSE_TYPE(TYPE_ECHO);
+        sdsp_write_packet(pkg_buffer, pkg_len, OWN_DEVICE_ID, from);
+        return;
+    }
     default:
         send_error_response(ERR_INVALID_TYPE, from);
         return;
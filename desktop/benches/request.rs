@@ -0,0 +1,70 @@
+use agpio::gpio::{iic::write::IICWriteRequest, read::ReadRequest, write::WriteRequest, Request};
+use agpio::sdsp;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+//
+// Host-side overhead of a single request, without any serial I/O
+//
+
+/// encode a request into a SDSP frame, using the same buffers for every iteration
+fn encode_request<R: Request>(request: &R, body_buf: &mut Vec<u8>, frame_buf: &mut Vec<u8>) {
+    body_buf.clear();
+    request.encode(body_buf);
+    sdsp::encode_packet(0xAA, 0xCA, body_buf, frame_buf);
+}
+
+/// decode a response frame and parse it as the response to `request`
+fn decode_response<R: Request>(
+    request: &R,
+    decoder: &mut sdsp::Decoder,
+    frame: &[u8],
+) -> R::Response {
+    let mut result = None;
+    for &byte in frame {
+        result = decoder.push(byte);
+    }
+    assert_eq!(result, Some(Ok(())));
+    request.parse_response(decoder.packet().body).unwrap()
+}
+
+fn bench_crc(c: &mut Criterion) {
+    let data = [0x5Au8; 32];
+    c.bench_function("crc16 32 bytes", |b| {
+        b.iter(|| sdsp::crc16(black_box(&data)))
+    });
+}
+
+fn bench_frames(c: &mut Criterion) {
+    let mut body_buf = Vec::new();
+    let mut frame_buf = Vec::new();
+    let mut decoder = sdsp::Decoder::new();
+
+    let read = ReadRequest::new(13, true, false, false, false, false);
+    c.bench_function("encode read request", |b| {
+        b.iter(|| encode_request(black_box(&read), &mut body_buf, &mut frame_buf))
+    });
+
+    let iic = IICWriteRequest::new(0x27, vec![0x55; 16], true);
+    c.bench_function("encode i2c write request (16 bytes)", |b| {
+        b.iter(|| encode_request(black_box(&iic), &mut body_buf, &mut frame_buf))
+    });
+
+    let mut response_frame = Vec::new();
+    sdsp::encode_packet(0xCA, 0xAA, &[0x81, 0x00, 0x01], &mut response_frame);
+    c.bench_function("decode read response", |b| {
+        b.iter(|| decode_response(&read, &mut decoder, black_box(&response_frame)))
+    });
+
+    // full host-side round trip: encode request, decode and parse response
+    let write = WriteRequest::new(13, 1, false, false);
+    sdsp::encode_packet(0xCA, 0xAA, &[0x82], &mut response_frame);
+    c.bench_function("write request round trip", |b| {
+        b.iter(|| {
+            encode_request(black_box(&write), &mut body_buf, &mut frame_buf);
+            decode_response(&write, &mut decoder, black_box(&response_frame))
+        })
+    });
+}
+
+criterion_group!(benches, bench_crc, bench_frames);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+//
+// Fault injection
+//
+
+/// faults injected into the traffic between the client and the virtual controller
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// probability that a single byte (in either direction) is dropped
+    pub drop_rate: f64,
+
+    /// probability that the checksum of a response frame is corrupted
+    pub corrupt_rate: f64,
+
+    /// delay before responses are sent
+    pub response_delay: Duration,
+}
+
+pub struct FaultInjector {
+    faults: Faults,
+    rng: XorShift,
+}
+
+impl FaultInjector {
+    pub fn new(faults: Faults, seed: u64) -> FaultInjector {
+        FaultInjector {
+            faults,
+            rng: XorShift::new(seed),
+        }
+    }
+
+    pub fn response_delay(&self) -> Duration {
+        self.faults.response_delay
+    }
+
+    /// drop bytes from `data` at the configured rate. returns the number of dropped bytes
+    pub fn drop_bytes(&mut self, data: &mut Vec<u8>) -> usize {
+        if self.faults.drop_rate <= 0.0 {
+            return 0;
+        }
+
+        let len = data.len();
+        let rate = self.faults.drop_rate;
+        data.retain(|_| !self.rng.chance(rate));
+        len - data.len()
+    }
+
+    /// corrupt the checksum of response frames at the configured rate.
+    /// returns the number of corrupted frames
+    pub fn corrupt_frames(&mut self, frames: &mut [u8]) -> usize {
+        if self.faults.corrupt_rate <= 0.0 {
+            return 0;
+        }
+
+        // frames written by the controller are well-formed, so they can be split by their length field
+        let mut corrupted = 0;
+        let mut start = 0;
+        while start + 5 <= frames.len() {
+            let body_len = u16::from_be_bytes([frames[start + 3], frames[start + 4]]) as usize;
+            let end = start + body_len + agpio::sdsp::PKG_OVERHEAD;
+            if end > frames.len() {
+                break;
+            }
+
+            if self.rng.chance(self.faults.corrupt_rate) {
+                frames[end - 2] ^= 0xFF;
+                corrupted += 1;
+            }
+            start = end;
+        }
+        corrupted
+    }
+}
+
+/// small, seedable PRNG, so fault patterns are reproducible
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// returns true with probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
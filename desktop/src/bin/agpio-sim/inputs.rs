@@ -0,0 +1,77 @@
+use agpio::config::parse_pin;
+use agpio::duration::parse_duration;
+use agpio::sim::controller::VirtualController;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+//
+// Scriptable pin inputs
+//
+// one command per line, `#` starts a comment:
+//   set <pin> high|low|1|0|float   drive a pin from outside, or let it float
+//   analog <pin> <0-1023>          set the value analogRead() returns
+//   sleep <duration>               wait, e.g. `sleep 250ms` or `sleep 2s`
+// pins are given as numbers (`13`) or analog names (`A0`).
+//
+
+#[derive(PartialEq, Debug)]
+enum Command {
+    Set { pin: u8, level: Option<bool> },
+    Analog { pin: u8, value: u16 },
+    Sleep(Duration),
+}
+
+/// run the input commands read from `reader` against the controller, until the input ends
+pub fn run(reader: impl BufRead, source: &str, controller: Arc<Mutex<VirtualController>>) {
+    for (number, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}: read failed: {}", source, e);
+                return;
+            }
+        };
+
+        match parse_line(&line) {
+            Ok(None) => {}
+            Ok(Some(Command::Set { pin, level })) => {
+                controller.lock().unwrap().set_input(pin, level);
+            }
+            Ok(Some(Command::Analog { pin, value })) => {
+                controller.lock().unwrap().set_analog_input(pin, value);
+            }
+            Ok(Some(Command::Sleep(duration))) => std::thread::sleep(duration),
+            Err(e) => eprintln!("{}:{}: {}", source, number + 1, e),
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Result<Option<Command>, String> {
+    let line = line.split('#').next().unwrap_or("");
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(None),
+        ["set", pin, level] => Ok(Some(Command::Set {
+            pin: parse_pin(pin, None)?,
+            level: parse_level(level)?,
+        })),
+        ["analog", pin, value] => Ok(Some(Command::Analog {
+            pin: parse_pin(pin, None)?,
+            value: value
+                .parse()
+                .map_err(|_| format!("invalid analog value '{}'", value))?,
+        })),
+        ["sleep", duration] => Ok(Some(Command::Sleep(parse_duration(duration)?))),
+        _ => Err(format!("invalid command '{}'", line.trim())),
+    }
+}
+
+fn parse_level(level: &str) -> Result<Option<bool>, String> {
+    match level.to_ascii_lowercase().as_str() {
+        "high" | "1" => Ok(Some(true)),
+        "low" | "0" => Ok(Some(false)),
+        "float" => Ok(None),
+        _ => Err(format!("invalid level '{}'", level)),
+    }
+}
@@ -0,0 +1,19 @@
+#[cfg(target_os = "linux")]
+mod faults;
+#[cfg(target_os = "linux")]
+mod inputs;
+#[cfg(target_os = "linux")]
+mod pty;
+#[cfg(target_os = "linux")]
+mod server;
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    server::main();
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        eprintln!("agpio-sim: pseudo-terminals are only supported on Linux");
+        std::process::exit(128);
+    }
+}
@@ -0,0 +1,124 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+//
+// Pseudo-terminal the simulator serves the protocol on
+//
+
+/// result of waiting for data on the pty
+#[derive(PartialEq, Debug)]
+pub enum PollResult {
+    /// data is available to read
+    Readable,
+
+    /// a client has the pty open, but sent nothing
+    Idle,
+
+    /// no client has the pty open
+    Hangup,
+}
+
+pub struct Pty {
+    master: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    /// open a new pseudo-terminal pair, with the client side in raw mode
+    pub fn open() -> io::Result<Pty> {
+        // SAFETY: plain libc calls, the returned descriptor is checked and owned by `master`
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            master
+        };
+
+        let mut name = [0 as libc::c_char; 128];
+        // SAFETY: `name` is large enough for any pts path, and ptsname_r terminates it
+        let path = unsafe {
+            if libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+        };
+
+        let pty = Pty { master, path };
+        pty.make_raw()?;
+        Ok(pty)
+    }
+
+    /// path of the client side, e.g. `/dev/pts/3`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// wait up to `timeout` for data from the client
+    pub fn poll(&self, timeout: Duration) -> io::Result<PollResult> {
+        let mut fds = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // SAFETY: `fds` is a single, valid pollfd
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(PollResult::Idle),
+                _ => Err(err),
+            };
+        }
+
+        Ok(if fds.revents & libc::POLLHUP != 0 {
+            PollResult::Hangup
+        } else if fds.revents & libc::POLLIN != 0 {
+            PollResult::Readable
+        } else {
+            PollResult::Idle
+        })
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.master.read(buf)
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.master.write_all(data)?;
+        self.master.flush()
+    }
+
+    /// put the client side into raw mode, so clients that do not configure the port see every byte unchanged.
+    /// the settings stay with the pty while the master is open.
+    fn make_raw(&self) -> io::Result<()> {
+        let client = File::options()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&self.path)?;
+
+        // SAFETY: `termios` is fully initialized by tcgetattr before it is used
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(client.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(client.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::faults::{FaultInjector, Faults};
+use crate::inputs;
+use crate::pty::{PollResult, Pty};
+use agpio::config::parse_u8;
+use agpio::gpio::describe::describe;
+use agpio::sdsp;
+use agpio::sim::{
+    controller::{Event, VirtualController, DEFAULT_DEVICE_ID},
+    i2c::SimulatedI2CBus,
+};
+use clap::Parser;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//
+// Clap argument structures
+//
+#[derive(Parser, Debug)]
+#[command(author, version, about = "serve the agpio command protocol on a pseudo-terminal", long_about = None)]
+struct Args {
+    /// the device ID of the simulated controller
+    #[arg(short, long, default_value_t = DEFAULT_DEVICE_ID, value_parser = parse_u8)]
+    id: u8,
+
+    /// create a symlink to the pty at this path
+    #[arg(short, long)]
+    link: Option<PathBuf>,
+
+    /// read pin input commands from this file ('-' for stdin)
+    #[arg(long)]
+    inputs: Option<PathBuf>,
+
+    /// write the command log to this file instead of stderr
+    #[arg(long)]
+    log: Option<PathBuf>,
+
+    /// attach an I2C device with this address. may be repeated
+    #[arg(long = "i2c-device", value_parser = parse_u8)]
+    i2c_devices: Vec<u8>,
+
+    /// reset the controller when a client opens the port, like the auto-reset of an Arduino
+    #[arg(long)]
+    reset_on_open: bool,
+
+    /// ignore everything received for this many milliseconds after a (re-)start
+    #[arg(long, default_value_t = 0)]
+    boot_delay: u64,
+
+    /// delay every response by this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    delay: u64,
+
+    /// probability (0.0 - 1.0) that a byte is dropped, in either direction
+    #[arg(long, default_value_t = 0.0)]
+    drop_rate: f64,
+
+    /// probability (0.0 - 1.0) that the checksum of a response frame is corrupted
+    #[arg(long, default_value_t = 0.0)]
+    corrupt_rate: f64,
+
+    /// seed for the fault injection
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+pub fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("agpio-sim: {}", e);
+        std::process::exit(128);
+    }
+}
+
+fn run(args: Args) -> io::Result<()> {
+    // set up the log
+    let log = Log::new(match &args.log {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(io::stderr()),
+    });
+
+    // set up the virtual controller
+    let bus = SimulatedI2CBus::new();
+    for address in &args.i2c_devices {
+        bus.add_device(*address);
+    }
+    let mut controller = VirtualController::new(args.id).with_i2c_bus(bus);
+    let observer_log = log.clone();
+    controller.set_observer(move |event| observer_log.event(event));
+    let controller = Arc::new(Mutex::new(controller));
+
+    // open the pty
+    let mut pty = Pty::open()?;
+    println!("{}", pty.path().display());
+    if let Some(link) = &args.link {
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink(pty.path(), link)?;
+    }
+
+    // feed pin inputs
+    if let Some(path) = &args.inputs {
+        let controller = controller.clone();
+        let source = path.display().to_string();
+        let reader: Box<dyn io::BufRead + Send> = if source == "-" {
+            Box::new(io::BufReader::new(io::stdin()))
+        } else {
+            Box::new(io::BufReader::new(std::fs::File::open(path)?))
+        };
+        std::thread::spawn(move || inputs::run(reader, &source, controller));
+    }
+
+    serve(&args, &mut pty, &controller, &log)
+}
+
+/// serve the protocol on the pty, forever
+fn serve(
+    args: &Args,
+    pty: &mut Pty,
+    controller: &Mutex<VirtualController>,
+    log: &Log,
+) -> io::Result<()> {
+    let mut faults = FaultInjector::new(
+        Faults {
+            drop_rate: args.drop_rate,
+            corrupt_rate: args.corrupt_rate,
+            response_delay: Duration::from_millis(args.delay),
+        },
+        args.seed,
+    );
+    let boot_delay = Duration::from_millis(args.boot_delay);
+
+    let mut connected = false;
+    let mut booted_at = Instant::now() + boot_delay;
+    let mut buf = [0u8; 256];
+    let mut received = Vec::new();
+    let mut responses = Vec::new();
+    loop {
+        // wait for data, and track clients opening and closing the port
+        let poll = pty.poll(Duration::from_millis(10))?;
+        if poll == PollResult::Hangup {
+            if connected {
+                connected = false;
+                log.line(format_args!("client disconnected"));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        if !connected {
+            connected = true;
+            if args.reset_on_open {
+                controller.lock().unwrap().reset();
+                booted_at = Instant::now() + boot_delay;
+                log.line(format_args!("client connected, controller reset"));
+            } else {
+                log.line(format_args!("client connected"));
+            }
+        }
+        if poll == PollResult::Idle {
+            continue;
+        }
+
+        // read what the client sent
+        let n = match pty.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => continue,
+            Err(e) => return Err(e),
+        };
+        if Instant::now() < booted_at {
+            log.line(format_args!("booting, discarded {} bytes", n));
+            continue;
+        }
+        received.clear();
+        received.extend_from_slice(&buf[..n]);
+        let dropped = faults.drop_bytes(&mut received);
+        if dropped > 0 {
+            log.line(format_args!("fault: dropped {} received bytes", dropped));
+        }
+
+        // let the controller handle it
+        responses.clear();
+        controller
+            .lock()
+            .unwrap()
+            .receive(&received, &mut responses);
+        if responses.is_empty() {
+            continue;
+        }
+
+        // send the responses
+        let corrupted = faults.corrupt_frames(&mut responses);
+        if corrupted > 0 {
+            log.line(format_args!(
+                "fault: corrupted {} response frames",
+                corrupted
+            ));
+        }
+        let dropped = faults.drop_bytes(&mut responses);
+        if dropped > 0 {
+            log.line(format_args!("fault: dropped {} response bytes", dropped));
+        }
+        std::thread::sleep(faults.response_delay());
+        pty.write_all(&responses)?;
+    }
+}
+
+//
+// Command log
+//
+#[derive(Clone)]
+struct Log {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    start: Instant,
+}
+
+impl Log {
+    fn new(out: Box<dyn Write + Send>) -> Log {
+        Log {
+            out: Arc::new(Mutex::new(out)),
+            start: Instant::now(),
+        }
+    }
+
+    fn line(&self, message: std::fmt::Arguments) {
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(
+            out,
+            "[{:>10.3}s] {}",
+            self.start.elapsed().as_secs_f64(),
+            message
+        );
+        let _ = out.flush();
+    }
+
+    fn event(&self, event: &Event) {
+        match event {
+            Event::Command {
+                from,
+                body,
+                responses,
+            } => self.line(format_args!(
+                "{:#04x}: {} => {}",
+                from,
+                describe(body),
+                describe_responses(responses)
+            )),
+            Event::Rejected {
+                from,
+                reason,
+                responses,
+            } => self.line(format_args!(
+                "{:#04x}: rejected packet ({}) => {}",
+                from,
+                reason,
+                describe_responses(responses)
+            )),
+        }
+    }
+}
+
+fn describe_responses(frames: &[u8]) -> String {
+    let mut decoder = sdsp::Decoder::new();
+    let mut descriptions = Vec::new();
+    for &byte in frames {
+        if let Some(Ok(())) = decoder.push(byte) {
+            descriptions.push(describe(decoder.packet().body));
+        }
+    }
+
+    if descriptions.is_empty() {
+        "no response".to_string()
+    } else {
+        descriptions.join(", ")
+    }
+}
@@ -0,0 +1,13 @@
+#[cfg(unix)]
+mod server;
+
+fn main() {
+    #[cfg(unix)]
+    server::main();
+
+    #[cfg(not(unix))]
+    {
+        eprintln!("agpiod: Unix sockets are only supported on Unix");
+        std::process::exit(128);
+    }
+}
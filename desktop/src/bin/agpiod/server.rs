@@ -0,0 +1,129 @@
+use agpio::config::{Config, DeviceConfig};
+use agpio::daemon;
+use agpio::gpio::{policy::Policy, HostController};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+//
+// Clap argument structures
+//
+#[derive(Parser, Debug)]
+#[command(author, version, about = "own the serial ports of the configured devices, and share them with every agpio client", long_about = None)]
+struct Args {
+    /// the config file with the devices to serve. defaults to ~/.config/agpio/config.toml
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// the socket to listen on. defaults to $AGPIOD_SOCKET, or agpiod.sock in $XDG_RUNTIME_DIR
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// log clients and requests to stderr. repeat (-vv) to also log every frame
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+pub fn main() {
+    let args = Args::parse();
+    init_logging(args.verbose);
+    if let Err(e) = run(args) {
+        eprintln!("agpiod: {}", e);
+        std::process::exit(128);
+    }
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let path = args
+        .config
+        .or_else(Config::default_path)
+        .ok_or_else(|| io::Error::other("no config file given, and no default location"))?;
+    let config = Config::load(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    if config.devices.is_empty() {
+        return Err(io::Error::other(format!(
+            "{}: no devices configured",
+            path.display()
+        )));
+    }
+
+    // open every port once, so clients never pay for opening and resetting the board
+    let mut devices = BTreeMap::new();
+    for (name, device) in &config.devices {
+        let host = connect(device)
+            .map_err(|e| io::Error::new(e.kind(), format!("device '{}': {}", name, e)))?;
+        devices.insert(name.clone(), host);
+    }
+
+    let socket = args.socket.unwrap_or_else(daemon::socket_path);
+    let listener = bind(&socket)?;
+    info!(socket = %socket.display(), devices = ?devices.keys(), "listening");
+    println!("{}", socket.display());
+    daemon::serve(listener, devices)
+}
+
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+/// open the port of a device, and create its host controller
+fn connect(device: &DeviceConfig) -> io::Result<HostController> {
+    let port = device
+        .find_port()?
+        .ok_or_else(|| io::Error::other("neither port nor usb_serial is configured"))?;
+    let port = serialport::new(&port, device.baud.unwrap_or(115200))
+        .timeout(Duration::from_millis(100))
+        .open()?;
+
+    let mut host = HostController::new(
+        Box::new(port),
+        device.own_id.unwrap_or(0xAA),
+        Some(Duration::from_millis(100)),
+        device.retries,
+    );
+    if let Some(path) = &device.policy {
+        let policy = Policy::load(path, device.board)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        host.set_policy(policy);
+    }
+    Ok(host)
+}
+
+/// listen on `path`, replacing the socket of a daemon that is no longer running.
+/// anything else at `path` is left alone
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        Ok(_) => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("already running on {}", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    UnixListener::bind(path)
+}
@@ -0,0 +1,43 @@
+pub mod bench;
+pub mod codec;
+pub mod connection;
+pub mod custom;
+pub mod error;
+pub mod hexdump;
+pub mod http;
+pub mod linktest;
+pub mod live;
+pub mod monitor;
+pub mod mqtt;
+pub mod rpc;
+pub mod script;
+pub mod shell;
+pub mod stats;
+
+pub use agpio::config::parse_u8;
+
+/// what a request prints on success
+pub enum Reply {
+    /// a digital pin value, which is also used as exit code
+    Value(u16),
+
+    /// an analog pin value, which would not fit into an exit code
+    Analog(u16),
+
+    /// any other output
+    Text(String),
+}
+
+/// format bytes as space-separated hex, e.g. `7b aa ca`
+pub fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+/// names of the analog pins, starting at A0
+pub const ANALOG_PIN_NAMES: [&str; 8] = ["A0", "A1", "A2", "A3", "A4", "A5", "A6", "A7"];
+
+/// parse a pin given as number, or as analog pin name like `A0`
+pub fn parse_pin(value: &str) -> Result<u8, String> {
+    agpio::config::parse_pin(value, None)
+}
@@ -0,0 +1,169 @@
+use super::connection::Connection;
+use agpio::gpio::{
+    iic::write::IICWriteRequest, read::ReadRequest, toggle::ToggleRequest, write::WriteRequest,
+    Request,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+//
+// Protocol benchmark
+//
+
+/// pins and addresses the benchmark uses
+pub struct BenchTarget {
+    pub target_id: u8,
+
+    /// pin for digital writes, toggles and digital reads
+    pub pin: u8,
+
+    /// pin for analog writes, must support PWM
+    pub pwm_pin: u8,
+
+    /// pin for analog reads
+    pub analog_pin: u8,
+
+    /// address for i2c writes. a missing device still answers with a NACK result.
+    pub i2c_address: u8,
+}
+
+/// latency of one operation over all iterations
+#[derive(PartialEq, Debug, Serialize)]
+pub struct BenchResult {
+    pub operation: &'static str,
+    pub ops: usize,
+    pub errors: usize,
+    pub min_us: f64,
+    pub avg_us: f64,
+    pub p50_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+    pub ops_per_sec: f64,
+}
+
+/// run every operation `iterations` times on one connection
+pub fn run(
+    connection: &mut Connection,
+    target: &BenchTarget,
+    iterations: usize,
+) -> Vec<BenchResult> {
+    let id = target.target_id;
+    vec![
+        measure("digital write", iterations, |i| {
+            send(
+                connection,
+                id,
+                &WriteRequest::new(target.pin, (i % 2) as u16, false, false),
+            )
+        }),
+        measure("toggle", iterations, |_| {
+            send(connection, id, &ToggleRequest::new(target.pin))
+        }),
+        measure("analog write", iterations, |i| {
+            let value = (i % 256) as u16;
+            send(
+                connection,
+                id,
+                &WriteRequest::new(target.pwm_pin, value, true, false),
+            )
+        }),
+        measure("digital read", iterations, |_| {
+            let request = ReadRequest::new(target.pin, false, false, false, false, true);
+            send(connection, id, &request)
+        }),
+        measure("analog read", iterations, |_| {
+            let request = ReadRequest::new(target.analog_pin, false, false, true, false, false);
+            send(connection, id, &request)
+        }),
+        measure("i2c write", iterations, |i| {
+            let request = IICWriteRequest::new(target.i2c_address, vec![i as u8], true);
+            send(connection, id, &request)
+        }),
+    ]
+}
+
+/// print the results as a table
+pub fn print_table(results: &[BenchResult]) {
+    println!(
+        "{:<14} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "operation", "ops", "errors", "min", "avg", "p50", "p99", "max", "ops/s"
+    );
+    for r in results {
+        println!(
+            "{:<14} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9.1}",
+            r.operation,
+            r.ops,
+            r.errors,
+            format_us(r.min_us),
+            format_us(r.avg_us),
+            format_us(r.p50_us),
+            format_us(r.p99_us),
+            format_us(r.max_us),
+            r.ops_per_sec
+        );
+    }
+}
+
+/// print the results as JSON, for tracking regressions
+pub fn print_json(results: &[BenchResult]) {
+    println!("{}", serde_json::to_string_pretty(results).unwrap());
+}
+
+fn send<R: Request>(connection: &mut Connection, target_id: u8, request: &R) -> bool {
+    connection.send(request, target_id).is_ok()
+}
+
+fn measure(
+    operation: &'static str,
+    iterations: usize,
+    mut op: impl FnMut(usize) -> bool,
+) -> BenchResult {
+    let mut latencies = Vec::with_capacity(iterations);
+    let mut errors = 0;
+    let start = Instant::now();
+    for i in 0..iterations {
+        let op_start = Instant::now();
+        if !op(i) {
+            errors += 1;
+        }
+        latencies.push(op_start.elapsed());
+    }
+    let total = start.elapsed();
+
+    latencies.sort();
+    let us = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+    let percentile = |p: f64| {
+        if latencies.is_empty() {
+            return 0.0;
+        }
+        let rank = ((p / 100.0 * latencies.len() as f64).ceil() as usize).clamp(1, latencies.len());
+        us(latencies[rank - 1])
+    };
+    BenchResult {
+        operation,
+        ops: iterations,
+        errors,
+        min_us: latencies.first().copied().map(us).unwrap_or_default(),
+        avg_us: if iterations > 0 {
+            us(total) / iterations as f64
+        } else {
+            0.0
+        },
+        p50_us: percentile(50.0),
+        p99_us: percentile(99.0),
+        max_us: latencies.last().copied().map(us).unwrap_or_default(),
+        ops_per_sec: if total.is_zero() {
+            0.0
+        } else {
+            iterations as f64 / total.as_secs_f64()
+        },
+    }
+}
+
+fn format_us(us: f64) -> String {
+    if us >= 1000.0 {
+        format!("{:.2}ms", us / 1000.0)
+    } else {
+        format!("{:.0}µs", us)
+    }
+}
@@ -0,0 +1,138 @@
+use super::{
+    custom::{describe, explain},
+    hex,
+};
+use agpio::gpio::{custom::CommandSpec, Encode};
+use agpio::sdsp::{self, PKG_END_BYTE, PKG_OVERHEAD, PKG_START_BYTE};
+
+//
+// Offline frame encoding and decoding
+//
+
+/// print the frame `request` is sent as
+pub fn print_request(sender_id: u8, receiver_id: u8, request: &dyn Encode) {
+    let mut body = Vec::new();
+    request.encode(&mut body);
+    let mut frame = Vec::new();
+    sdsp::encode_packet(sender_id, receiver_id, &body, &mut frame);
+    println!("{}", hex(&frame));
+}
+
+/// parse hex bytes given on the command line.
+/// bytes may be separated by spaces or commas, and may be prefixed with `0x`.
+pub fn parse_hex(args: &[String]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let words = args.iter().flat_map(|arg| arg.split([' ', ',']));
+    for word in words.filter(|word| !word.is_empty()) {
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(format!("invalid hex '{}'", word));
+        }
+        if digits.len() % 2 != 0 {
+            return Err(format!("invalid hex '{}': odd number of digits", word));
+        }
+        for i in (0..digits.len()).step_by(2) {
+            // only ASCII hex digits are left, so slicing and parsing cannot fail
+            bytes.push(u8::from_str_radix(&digits[i..i + 2], 16).unwrap());
+        }
+    }
+    Ok(bytes)
+}
+
+/// print every field of a frame with the bytes it is made of.
+/// returns false if the frame is invalid.
+pub fn explain_frame(frame: &[u8], specs: &[CommandSpec]) -> bool {
+    if frame.len() < PKG_OVERHEAD {
+        println!(
+            "frame too short: {} bytes, a frame has at least {}",
+            frame.len(),
+            PKG_OVERHEAD
+        );
+        return false;
+    }
+    let mut valid = true;
+
+    // prologue
+    let start_ok = frame[0] == PKG_START_BYTE;
+    valid &= start_ok;
+    print_field(&frame[0..1], "start byte", check(start_ok, PKG_START_BYTE));
+    print_field(&frame[1..2], "sender id", format!("{:#04x}", frame[1]));
+    print_field(&frame[2..3], "receiver id", describe_id(frame[2]));
+
+    let body_len = u16::from_be_bytes([frame[3], frame[4]]) as usize;
+    let actual_body_len = frame.len() - PKG_OVERHEAD;
+    let length_ok = body_len == actual_body_len;
+    valid &= length_ok;
+    let length = if length_ok {
+        format!("{} bytes", body_len)
+    } else {
+        format!(
+            "{} bytes, INVALID: the frame has {} body bytes",
+            body_len, actual_body_len
+        )
+    };
+    print_field(&frame[3..5], "length", length);
+
+    // body
+    let body = &frame[5..5 + actual_body_len];
+    for field in explain(specs, body) {
+        let name = format!("  {}", field.name);
+        print_field(&body[field.range], &name, field.value);
+    }
+
+    // epilogue
+    let epilogue = &frame[frame.len() - 3..];
+    let mut expected = frame.to_vec();
+    let len = expected.len();
+    expected[len - 3..len - 1].fill(0);
+    let crc = sdsp::crc16(&expected);
+    let checksum_ok = u16::from_be_bytes([epilogue[0], epilogue[1]]) == crc;
+    valid &= checksum_ok;
+    let checksum = if checksum_ok {
+        "valid".to_string()
+    } else {
+        format!("INVALID, expected {}", hex(&crc.to_be_bytes()))
+    };
+    print_field(&epilogue[..2], "checksum", checksum);
+
+    let end_ok = epilogue[2] == PKG_END_BYTE;
+    valid &= end_ok;
+    print_field(&epilogue[2..], "end byte", check(end_ok, PKG_END_BYTE));
+
+    if valid {
+        println!(
+            "=> {:#04x} -> {:#04x}: {}",
+            frame[1],
+            frame[2],
+            describe(specs, body)
+        );
+    }
+    valid
+}
+
+fn print_field(bytes: &[u8], name: &str, value: String) {
+    if value.is_empty() {
+        println!("{:<14} {}", hex(bytes), name);
+    } else {
+        println!("{:<14} {}: {}", hex(bytes), name, value);
+    }
+}
+
+fn check(ok: bool, expected: u8) -> String {
+    if ok {
+        String::new()
+    } else {
+        format!("INVALID, expected {:02x}", expected)
+    }
+}
+
+fn describe_id(id: u8) -> String {
+    if id == sdsp::BROADCAST_ID {
+        format!("{:#04x} (broadcast)", id)
+    } else {
+        format!("{:#04x}", id)
+    }
+}
@@ -0,0 +1,39 @@
+use agpio::gpio::{stats::Stats, Error, HostController, Request};
+
+//
+// Connection
+//
+
+/// where requests are sent
+pub enum Connection {
+    /// the serial port, opened by this process
+    Port(HostController),
+
+    /// a running agpiod, and the name of the device it sends the requests to
+    #[cfg(unix)]
+    Daemon(agpio::daemon::Client, String),
+}
+
+impl Connection {
+    /// send a request to the controller with id `recipient_id`
+    pub fn send<R: Request + ?Sized>(
+        &mut self,
+        request: &R,
+        recipient_id: u8,
+    ) -> Result<R::Response, Error> {
+        match self {
+            Connection::Port(host) => host.send(request, recipient_id),
+            #[cfg(unix)]
+            Connection::Daemon(client, device) => client.send(device, request, recipient_id),
+        }
+    }
+
+    /// snapshot of the request counters and latencies of this connection
+    pub fn stats(&self) -> Stats {
+        match self {
+            Connection::Port(host) => host.stats(),
+            #[cfg(unix)]
+            Connection::Daemon(client, _) => client.stats(),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use super::{codec::parse_hex, error::ErrorCategory};
+use agpio::gpio::{
+    custom::{CommandSpec, CustomRequest, FieldKind, FieldSpec, Value},
+    describe::{self, Field},
+};
+use clap::{Arg, ArgAction, ArgMatches};
+use std::path::PathBuf;
+
+//
+// Command line integration of user-defined commands
+//
+
+/// load the commands of the spec file given with `--commands`, if any.
+///
+/// the file has to be found before the command line is parsed, since the
+/// commands it defines become subcommands of the parser.
+pub fn load_specs() -> Vec<CommandSpec> {
+    let Some(path) = spec_file_arg() else {
+        return Vec::new();
+    };
+    CommandSpec::load(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to load commands {}: {}", path.display(), e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    })
+}
+
+/// add a subcommand for every user-defined command, both as a request and under `encode`, if present
+pub fn add_subcommands(command: clap::Command, specs: &[CommandSpec]) -> clap::Command {
+    for spec in specs {
+        if command.find_subcommand(&spec.name).is_some() {
+            eprintln!(
+                "Failed to load commands: '{}' is already a built-in command",
+                spec.name
+            );
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        }
+    }
+
+    let command = command.subcommands(specs.iter().map(subcommand));
+    if command.find_subcommand("encode").is_none() {
+        return command;
+    }
+    command.mut_subcommand("encode", |encode| {
+        encode.subcommands(specs.iter().map(subcommand))
+    })
+}
+
+/// build the request of a user-defined subcommand from its parsed arguments
+pub fn request(spec: &CommandSpec, matches: &ArgMatches) -> CustomRequest {
+    let mut values = Vec::new();
+    for field in &spec.request {
+        let value = match field.kind {
+            FieldKind::Flags => Value::Flags(
+                field
+                    .flags
+                    .keys()
+                    .filter(|flag| matches.get_flag(&flag_id(field, flag)))
+                    .cloned()
+                    .collect(),
+            ),
+            FieldKind::Bytes => {
+                let hex: Vec<String> = matches
+                    .get_many::<String>(&field.name)
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                Value::Bytes(parse_hex(&hex).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(ErrorCategory::InvalidInput.exit_code());
+                }))
+            }
+            _ => Value::Int(*matches.get_one::<i64>(&field.name).unwrap()),
+        };
+        values.push(value);
+    }
+
+    spec.request(&values).unwrap_or_else(|e| {
+        eprintln!("invalid {} request: {}", spec.name, e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    })
+}
+
+/// format the values of a response to a user-defined request.
+/// a single value is printed as is, multiple values as `name: value` lines.
+pub fn format_values(values: &[(String, Value)]) -> String {
+    match values {
+        [] => "ok".to_string(),
+        [(_, value)] => value.to_string(),
+        values => {
+            let lines: Vec<String> = values
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect();
+            lines.join("\n")
+        }
+    }
+}
+
+/// like `describe::describe`, but also knows the user-defined commands
+pub fn describe(specs: &[CommandSpec], packet_body: &[u8]) -> String {
+    match find(specs, packet_body) {
+        Some(spec) => spec.describe(packet_body),
+        None => describe::describe(packet_body),
+    }
+}
+
+/// like `describe::explain`, but also knows the user-defined commands
+pub fn explain(specs: &[CommandSpec], packet_body: &[u8]) -> Vec<Field> {
+    match find(specs, packet_body) {
+        Some(spec) => spec.explain(packet_body),
+        None => describe::explain(packet_body),
+    }
+}
+
+fn find<'a>(specs: &'a [CommandSpec], packet_body: &[u8]) -> Option<&'a CommandSpec> {
+    let packet_type = *packet_body.first()?;
+    specs.iter().find(|spec| spec.is_packet_type(packet_type))
+}
+
+fn spec_file_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--commands" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--commands=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn subcommand(spec: &CommandSpec) -> clap::Command {
+    let about = spec
+        .about
+        .clone()
+        .unwrap_or_else(|| format!("user-defined command {:#04x}", spec.type_id));
+    let mut command = clap::Command::new(spec.name.clone()).about(about);
+
+    for field in &spec.request {
+        match field.kind {
+            FieldKind::Flags => {
+                for (flag, bit) in &field.flags {
+                    command = command.arg(
+                        Arg::new(flag_id(field, flag))
+                            .long(flag.clone())
+                            .action(ArgAction::SetTrue)
+                            .help(format!("set bit {} of {}", bit, field.name)),
+                    );
+                }
+            }
+            FieldKind::Bytes => {
+                command = command.arg(
+                    Arg::new(field.name.clone())
+                        .num_args(0..)
+                        .value_name("HEX")
+                        .help(field_help(field)),
+                );
+            }
+            _ => {
+                command = command.arg(
+                    Arg::new(field.name.clone())
+                        .required(true)
+                        .allow_negative_numbers(true)
+                        .value_parser(parse_int)
+                        .help(field_help(field)),
+                );
+            }
+        }
+    }
+    command
+}
+
+fn field_help(field: &FieldSpec) -> String {
+    let kind = format!("{:?}", field.kind).to_lowercase();
+    match &field.help {
+        Some(help) => format!("{} ({})", help, kind),
+        None => kind,
+    }
+}
+
+fn flag_id(field: &FieldSpec, flag: &str) -> String {
+    format!("{}.{}", field.name, flag)
+}
+
+fn parse_int(value: &str) -> Result<i64, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a valid number", value))
+}
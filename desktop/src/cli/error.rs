@@ -0,0 +1,180 @@
+use agpio::gpio::{
+    describe::describe_error_code, error::ERR_INVALID_PIN, iic::write::IICResultCode,
+    policy::Violation, Error,
+};
+use agpio::sdsp::ReadError;
+use serde::Serialize;
+
+//
+// Error reporting
+//
+
+/// what kind of error happened. every category has its own exit code
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// invalid arguments, or a file that cannot be read
+    InvalidInput,
+
+    /// the serial port cannot be opened
+    Port,
+
+    /// the remote controller did not answer
+    Timeout,
+
+    /// the response was corrupted or not a valid packet
+    Communication,
+
+    /// the remote controller answered with an error code
+    Remote,
+
+    /// the remote controller rejected the pin number for the operation
+    InvalidPin,
+
+    /// the remote controller reported a failed i2c transmission
+    #[serde(rename = "i2c")]
+    I2C,
+
+    /// the host controller failed
+    Host,
+
+    /// the response does not belong to the request
+    ResponseMismatch,
+
+    /// the request was refused by the pin access policy, and not sent
+    Policy,
+}
+
+impl ErrorCategory {
+    /// the exit code of the CLI. 0 and 1 are used for digital values and 2 for usage errors, and
+    /// 128 and above are the codes of signals in shells, so errors use 10 to 19
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorCategory::InvalidInput => 10,
+            ErrorCategory::Port => 11,
+            ErrorCategory::Timeout => 12,
+            ErrorCategory::Communication => 13,
+            ErrorCategory::Remote => 14,
+            ErrorCategory::InvalidPin => 15,
+            ErrorCategory::I2C => 16,
+            ErrorCategory::Host => 17,
+            ErrorCategory::ResponseMismatch => 18,
+            ErrorCategory::Policy => 19,
+        }
+    }
+}
+
+/// an error as reported to other programs
+#[derive(PartialEq, Debug, Serialize)]
+pub struct ErrorInfo {
+    pub category: ErrorCategory,
+
+    /// the error code reported by the remote controller, or the i2c result code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u8>,
+
+    pub message: String,
+}
+
+impl ErrorInfo {
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> ErrorInfo {
+        ErrorInfo {
+            category,
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    /// a request that could not be understood
+    pub fn invalid_input(message: impl Into<String>) -> ErrorInfo {
+        ErrorInfo::new(ErrorCategory::InvalidInput, message)
+    }
+
+    pub fn gpio(error: &Error) -> ErrorInfo {
+        let (category, code) = match error {
+            Error::SDSPError {
+                kind: ReadError::Timeout,
+            } => (ErrorCategory::Timeout, None),
+            Error::SDSPError { .. } => (ErrorCategory::Communication, None),
+            Error::RemoteError { code } => (ErrorCategory::Remote, Some(*code)),
+            Error::HostError { code } => (ErrorCategory::Host, Some(*code)),
+            Error::InvalidPin => (ErrorCategory::InvalidPin, Some(ERR_INVALID_PIN)),
+            Error::ResponseMismatch => (ErrorCategory::ResponseMismatch, None),
+            Error::PolicyViolation { .. } => (ErrorCategory::Policy, None),
+        };
+        ErrorInfo {
+            category,
+            code,
+            message: gpio_error_message(error),
+        }
+    }
+
+    /// a failed i2c transmission
+    pub fn i2c(result_code: &IICResultCode) -> ErrorInfo {
+        ErrorInfo {
+            category: ErrorCategory::I2C,
+            code: Some(result_code.code()),
+            message: i2c_error_message(result_code),
+        }
+    }
+}
+
+impl From<Error> for ErrorInfo {
+    fn from(error: Error) -> ErrorInfo {
+        ErrorInfo::gpio(&error)
+    }
+}
+
+pub fn gpio_error_message(error: &Error) -> String {
+    match error {
+        Error::SDSPError { kind } => {
+            format!(
+                "communication failed with remote controller (SDSP error: {:?})",
+                kind
+            )
+        }
+        Error::RemoteError { code } => {
+            format!(
+                "remote controller returned error code {:#04x} ({})",
+                code,
+                describe_error_code(*code)
+            )
+        }
+        Error::HostError { code } => {
+            format!("host controller returned error code {:#04x}", code)
+        }
+        Error::InvalidPin => "the pin number is invalid for the requested operation".to_string(),
+        Error::ResponseMismatch => "the response from the remote controller did not match the expected response. this could be caused by a communication issue or a incompatible controller".to_string(),
+        Error::PolicyViolation { pin, violation } => {
+            format!("refused by policy: {}", violation_message(*pin, violation))
+        }
+    }
+}
+
+fn violation_message(pin: u8, violation: &Violation) -> String {
+    match violation {
+        Violation::Forbidden => format!("pin {} must not be used", pin),
+        Violation::InputOnly => format!("pin {} is input-only", pin),
+        Violation::OutputOnly => format!(
+            "pin {} is output-only, and reading it would make it an input. use --direct to read its output",
+            pin
+        ),
+        Violation::OutOfRange { value, min, max } => format!(
+            "value {} is out of range for pin {}, allowed are {} - {}",
+            value, pin, min, max
+        ),
+    }
+}
+
+pub fn i2c_error_message(result_code: &IICResultCode) -> String {
+    let reason = match result_code {
+        IICResultCode::Success => "no error".to_string(),
+        IICResultCode::DataTooLong => "data too long".to_string(),
+        IICResultCode::NACKOnAddress => "NACK on address".to_string(),
+        IICResultCode::NACKOnData => "NACK on data".to_string(),
+        IICResultCode::Other => "other error".to_string(),
+        IICResultCode::Timeout => "timeout".to_string(),
+        IICResultCode::Unknown { result_code } => format!("unknown error {:#04x}", result_code),
+    };
+    format!("i2c write failed: {} reported by Wire", reason)
+}
@@ -0,0 +1,56 @@
+use agpio::transport::{Direction, FrameTap};
+use std::io::Write;
+use std::time::SystemTime;
+
+//
+// Hex dump of frames on stderr
+//
+
+/// bytes per hex dump line
+const LINE_LEN: usize = 16;
+
+/// frame tap that prints a hex dump of every frame to stderr, e.g.
+///
+/// ```text
+/// >> sent 13 bytes
+/// 0000  7b aa ff 00 05 02 0d 00  01 00 07 7d 7d           |{..........}}|
+/// ```
+pub struct HexDump;
+
+impl FrameTap for HexDump {
+    fn frame(&mut self, direction: Direction, _timestamp: SystemTime, frame: &[u8]) {
+        let mut err = std::io::stderr().lock();
+        let _ = match direction {
+            Direction::Sent => writeln!(err, ">> sent {} bytes", frame.len()),
+            Direction::Received => writeln!(err, "<< received {} bytes", frame.len()),
+        };
+        for (i, line) in frame.chunks(LINE_LEN).enumerate() {
+            let _ = writeln!(err, "{}", format_line(i * LINE_LEN, line));
+        }
+    }
+}
+
+fn format_line(offset: usize, bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for i in 0..LINE_LEN {
+        if i == LINE_LEN / 2 {
+            hex.push(' ');
+        }
+        match bytes.get(i) {
+            Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+            None => hex.push_str("   "),
+        }
+    }
+
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!("{:04x}  {} |{}|", offset, hex, ascii)
+}
@@ -0,0 +1,410 @@
+mod socket;
+
+use super::{
+    connection::Connection,
+    error::{ErrorCategory, ErrorInfo},
+    live::Live,
+    parse_u8,
+    rpc::Op,
+};
+use agpio::config::{Config, PinConfig};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Map, Value};
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::debug;
+
+//
+// HTTP REST API
+//
+// GET  /devices/{id}/pins/{pin}             read, with the flags as query, e.g. ?analog=true
+// PUT  /devices/{id}/pins/{pin}             write {"value":1,"analog":false,"inverted":false}
+// POST /devices/{id}/pins/{pin}/toggle      toggle
+// POST /devices/{id}/i2c/{address}/write    i2c write {"data":[1,2],"stop":true}
+//
+// `id` is the ID of the target controller, `pin` a pin number, analog pin name or named pin.
+// responses are `{"ok":true,"device":202,"pin":13,"value":1}`,
+// or `{"ok":false,"error":{"category":"timeout","message":"..."}}` with a matching status code.
+//
+// GET  /                                    the dashboard of the configured pins
+// GET  /pins                                the configured pins and their last known values
+//
+
+/// how many HTTP requests are handled at the same time. requests to the controller still wait for each other
+const WORKERS: usize = 4;
+
+/// the dashboard, served as is
+const DASHBOARD: &str = include_str!("http/dashboard.html");
+
+/// the REST API, with all requests sent over one connection
+pub struct Api {
+    connection: Mutex<Connection>,
+
+    /// named pins
+    config: Config,
+
+    /// the device from the config file the connection sends to
+    device: Option<String>,
+
+    /// bearer token every request has to present, if set
+    token: Option<String>,
+
+    /// the values of the configured pins, for the dashboard and the WebSocket
+    live: Live,
+
+    /// the port of the WebSocket, once it is listening
+    socket_port: OnceLock<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WriteBody {
+    value: u16,
+    #[serde(default)]
+    analog: bool,
+    #[serde(default)]
+    inverted: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2CWriteBody {
+    #[serde(default)]
+    data: Vec<u8>,
+    #[serde(default)]
+    stop: bool,
+}
+
+/// an error response, with its status code
+type Failure = (u16, ErrorInfo);
+
+impl Api {
+    pub fn new(
+        connection: Connection,
+        config: Config,
+        device: Option<String>,
+        target_id: u8,
+        token: Option<String>,
+    ) -> Api {
+        let live = Live::new(&config, device.as_deref(), target_id);
+        Api {
+            connection: Mutex::new(connection),
+            config,
+            device,
+            token,
+            live,
+            socket_port: OnceLock::new(),
+        }
+    }
+
+    /// listen on `address` and answer requests forever, with a WebSocket on `socket_address`
+    /// if given, and reading the configured pins every `poll`.
+    /// prints the URLs once they are listening
+    pub fn serve(
+        self: &Arc<Api>,
+        address: &str,
+        socket_address: Option<&str>,
+        poll: Duration,
+    ) -> io::Result<()> {
+        let server = Server::http(address).map_err(io::Error::other)?;
+        match server.server_addr().to_ip() {
+            Some(address) => println!("http://{}", address),
+            None => println!("{}", address),
+        }
+
+        if let Some(socket_address) = socket_address {
+            let listener = TcpListener::bind(socket_address)?;
+            let local = listener.local_addr()?;
+            let _ = self.socket_port.set(local.port());
+            println!("ws://{}", local);
+
+            let api = self.clone();
+            std::thread::spawn(move || api.accept_sockets(listener));
+        }
+        if !poll.is_zero() && !self.live.snapshot().is_empty() {
+            let api = self.clone();
+            std::thread::spawn(move || loop {
+                let connection = api.connection.lock();
+                api.live
+                    .poll(&mut connection.unwrap_or_else(PoisonError::into_inner));
+                std::thread::sleep(poll);
+            });
+        }
+
+        std::thread::scope(|scope| {
+            for _ in 0..WORKERS {
+                scope.spawn(|| {
+                    for request in server.incoming_requests() {
+                        self.respond(request);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// send the request of an operation, and record the value of configured pins
+    fn send(&self, id: u8, op: Op) -> Result<Map<String, Value>, ErrorInfo> {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.live.execute(&mut connection, id, op)
+    }
+
+    fn respond(&self, mut request: Request) {
+        // the dashboard is the only page, and holds no data
+        if request.method() == &Method::Get && request.url() == "/" {
+            let response = Response::from_string(DASHBOARD)
+                .with_header(header("Content-Type", "text/html; charset=utf-8"));
+            if let Err(e) = request.respond(response) {
+                debug!(error = %e, "failed to send response");
+            }
+            return;
+        }
+
+        let (status, body) = match self.handle(&mut request) {
+            Ok(fields) => {
+                let mut body = Map::new();
+                body.insert("ok".to_string(), Value::Bool(true));
+                body.extend(fields);
+                (200, Value::Object(body))
+            }
+            Err((status, error)) => (status, json!({ "ok": false, "error": error })),
+        };
+        debug!(method = %request.method(), url = request.url(), status, "request");
+
+        let mut response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"));
+        if status == 401 {
+            response.add_header(header("WWW-Authenticate", "Bearer"));
+        }
+        if let Err(e) = request.respond(response) {
+            debug!(error = %e, "failed to send response");
+        }
+    }
+
+    /// the fields of the response to a request
+    fn handle(&self, request: &mut Request) -> Result<Map<String, Value>, Failure> {
+        self.authorize(request)?;
+
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let mut fields = Map::new();
+        let (id, op) = match (request.method(), segments.as_slice()) {
+            (Method::Get, ["pins"]) => {
+                let mut response = Map::new();
+                response.insert("device".to_string(), self.live.target_id.into());
+                response.insert("ws".to_string(), self.socket_port.get().copied().into());
+                response.insert("pins".to_string(), json!(self.live.snapshot()));
+                return Ok(response);
+            }
+            (Method::Get, ["devices", id, "pins", pin]) => {
+                let (pin, named) = self.pin(pin, &mut fields)?;
+                let flags = ReadFlags::parse(query, named)?;
+                let op = Op::Read {
+                    pin,
+                    analog: flags.analog,
+                    inverted: flags.inverted,
+                    pullup: flags.pullup,
+                    pulldown: flags.pulldown,
+                    direct: flags.direct,
+                };
+                (id, op)
+            }
+            (Method::Put, ["devices", id, "pins", pin]) => {
+                let body: WriteBody = read_json(request)?;
+                let op = self.write(pin, body.value, body.analog, body.inverted, &mut fields)?;
+                (id, op)
+            }
+            (Method::Post, ["devices", id, "pins", pin, "toggle"]) => {
+                let (pin, _) = self.pin(pin, &mut fields)?;
+                (id, Op::Toggle { pin })
+            }
+            (Method::Post, ["devices", id, "i2c", address, "write"]) => {
+                let address = parse_u8(address).map_err(invalid_input)?;
+                fields.insert("address".to_string(), address.into());
+                let body: I2CWriteBody = read_json(request)?;
+                let op = Op::I2cWrite {
+                    address,
+                    data: body.data,
+                    stop: body.stop,
+                };
+                (id, op)
+            }
+            (
+                _,
+                ["devices", _, "pins", _]
+                | ["devices", _, "pins", _, "toggle"]
+                | ["devices", _, "i2c", _, "write"],
+            ) => {
+                let message = format!("{} is not allowed on {}", request.method(), path);
+                return Err((405, ErrorInfo::invalid_input(message)));
+            }
+            _ => {
+                let message = format!("{} does not exist", path);
+                return Err((404, ErrorInfo::invalid_input(message)));
+            }
+        };
+
+        let id = parse_u8(id).map_err(invalid_input)?;
+        let mut response = Map::new();
+        response.insert("device".to_string(), id.into());
+        response.extend(fields);
+
+        response.extend(self.send(id, op).map_err(failed)?);
+        Ok(response)
+    }
+
+    /// the write of a value to a pin of the path, with the defaults of a named pin
+    fn write(
+        &self,
+        pin: &str,
+        value: u16,
+        analog: bool,
+        inverted: bool,
+        fields: &mut Map<String, Value>,
+    ) -> Result<Op, Failure> {
+        let (pin, named) = self.pin(pin, fields)?;
+        fields.insert("value".to_string(), value.into());
+        Ok(Op::Write {
+            pin,
+            value,
+            analog: analog || named.is_some_and(|named| named.analog),
+            inverted: inverted || named.is_some_and(|named| named.inverted),
+        })
+    }
+
+    /// check the bearer token of a request
+    fn authorize(&self, request: &Request) -> Result<(), Failure> {
+        let presented = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| header.value.as_str().strip_prefix("Bearer "));
+        if self.accepts(presented) {
+            Ok(())
+        } else {
+            Err((
+                401,
+                ErrorInfo::invalid_input("missing or wrong bearer token"),
+            ))
+        }
+    }
+
+    /// whether a presented token grants access
+    fn accepts(&self, presented: Option<&str>) -> bool {
+        match &self.token {
+            Some(token) => presented == Some(token.as_str()),
+            None => true,
+        }
+    }
+
+    /// resolve a pin of the path, and add it to the response
+    fn pin(
+        &self,
+        value: &str,
+        fields: &mut Map<String, Value>,
+    ) -> Result<(u8, Option<&PinConfig>), Failure> {
+        let (pin, named) = self
+            .config
+            .resolve_pin(value, self.device.as_deref())
+            .map_err(invalid_input)?;
+        fields.insert("pin".to_string(), pin.into());
+        if named.is_some() {
+            fields.insert("name".to_string(), value.into());
+        }
+        Ok((pin, named))
+    }
+}
+
+/// the flags of a read, given as query like `analog=true&direct`
+#[derive(Default)]
+struct ReadFlags {
+    analog: bool,
+    inverted: bool,
+    pullup: bool,
+    pulldown: bool,
+    direct: bool,
+}
+
+impl ReadFlags {
+    /// parse the query of a read, starting with the flags of a named pin
+    fn parse(query: &str, named: Option<&PinConfig>) -> Result<ReadFlags, Failure> {
+        let mut flags = match named {
+            Some(named) => ReadFlags {
+                analog: named.analog,
+                inverted: named.inverted,
+                pullup: named.pullup,
+                pulldown: named.pulldown,
+                direct: false,
+            },
+            None => ReadFlags::default(),
+        };
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, "true"));
+            let flag = match name {
+                "analog" => &mut flags.analog,
+                "inverted" => &mut flags.inverted,
+                "pullup" => &mut flags.pullup,
+                "pulldown" => &mut flags.pulldown,
+                "direct" => &mut flags.direct,
+                _ => return Err(invalid_input(format!("unknown parameter '{}'", name))),
+            };
+            *flag = match value {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => {
+                    return Err(invalid_input(format!(
+                        "invalid value '{}' for '{}', use true or false",
+                        value, name
+                    )))
+                }
+            };
+        }
+        Ok(flags)
+    }
+}
+
+/// parse the JSON body of a request. an empty body is an empty object
+fn read_json<T: DeserializeOwned>(request: &mut Request) -> Result<T, Failure> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| invalid_input(e.to_string()))?;
+    if body.trim().is_empty() {
+        body = "{}".to_string();
+    }
+    serde_json::from_str(&body).map_err(|e| invalid_input(e.to_string()))
+}
+
+fn invalid_input(message: impl Into<String>) -> Failure {
+    (400, ErrorInfo::invalid_input(message))
+}
+
+/// a failed request to the controller, with the status code of its category
+fn failed(error: ErrorInfo) -> Failure {
+    let status = match error.category {
+        ErrorCategory::InvalidInput | ErrorCategory::InvalidPin => 400,
+        ErrorCategory::Policy => 403,
+        ErrorCategory::Timeout => 504,
+        ErrorCategory::Communication
+        | ErrorCategory::Remote
+        | ErrorCategory::I2C
+        | ErrorCategory::ResponseMismatch => 502,
+        ErrorCategory::Port | ErrorCategory::Host => 500,
+    };
+    (status, error)
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
@@ -0,0 +1,201 @@
+use super::{failed, Failure};
+use crate::cli::rpc::Op;
+use agpio::config::PinNumber;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::{http::StatusCode, Message, WebSocket};
+
+//
+// WebSocket
+//
+// on connect, the socket sends the configured pins and their last known values:
+//
+//   {"event":"pins","pins":[{"name":"led","pin":13,"widget":"toggle","value":1}]}
+//
+// then every change of a value, from polling or from any request of any client:
+//
+//   {"event":"value","name":"led","pin":13,"value":0}
+//
+// and answers commands of the client, with the `id` of the command if given:
+//
+//   {"op":"write","pin":"led","value":1,"id":1}
+//   {"op":"toggle","pin":13}
+//   {"event":"result","id":1,"ok":true,"pin":13,"name":"led","value":1}
+//
+// if a token is set, it is given as `?token=...` or as bearer token.
+//
+
+/// how long to wait for a command before forwarding changes
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// a command of a client
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Command {
+    Write {
+        pin: PinNumber,
+        value: u16,
+        #[serde(default)]
+        id: Value,
+    },
+    Toggle {
+        pin: PinNumber,
+        #[serde(default)]
+        id: Value,
+    },
+}
+
+impl super::Api {
+    /// accept WebSocket clients on `listener` forever, every client on its own thread
+    pub(super) fn accept_sockets(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(error = %e, "failed to accept WebSocket client");
+                    continue;
+                }
+            };
+            let api = self.clone();
+            std::thread::spawn(move || {
+                debug!("WebSocket client connected");
+                if let Err(e) = api.serve_socket(stream) {
+                    debug!(error = %e, "WebSocket client failed");
+                }
+                debug!("WebSocket client disconnected");
+            });
+        }
+    }
+
+    // the error response of the handshake is given by tungstenite
+    #[allow(clippy::result_large_err)]
+    fn serve_socket(&self, stream: TcpStream) -> io::Result<()> {
+        let handshake = |request: &Request, response: Response| {
+            if self.authorize_socket(request) {
+                return Ok(response);
+            }
+            let mut error = ErrorResponse::new(Some("missing or wrong token".to_string()));
+            *error.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(error)
+        };
+        let mut socket = tungstenite::accept_hdr(stream, handshake)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        socket.get_ref().set_read_timeout(Some(READ_TIMEOUT))?;
+
+        // subscribe first, so no change between the snapshot and the subscription is missed
+        let changes = self.live.subscribe();
+        let pins = json!({ "event": "pins", "pins": self.live.snapshot() });
+        send(&mut socket, pins)?;
+
+        loop {
+            while let Ok(change) = changes.try_recv() {
+                let event = json!({
+                    "event": "value",
+                    "name": change.name,
+                    "pin": change.pin,
+                    "value": change.value,
+                });
+                send(&mut socket, event)?;
+            }
+
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    let result = self.command(&text);
+                    send(&mut socket, result)?;
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+    }
+
+    /// check the token of a client, given in the query or as bearer token
+    fn authorize_socket(&self, request: &Request) -> bool {
+        let query = request
+            .uri()
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="));
+        let bearer = request
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        self.accepts(query.or(bearer))
+    }
+
+    /// execute a command, and describe its result
+    fn command(&self, text: &str) -> Value {
+        let command: Command = match serde_json::from_str(text) {
+            Ok(command) => command,
+            Err(e) => {
+                let (_, error) = super::invalid_input(e.to_string());
+                return json!({ "event": "result", "ok": false, "error": error });
+            }
+        };
+
+        let mut fields = Map::new();
+        let (id, result) = match command {
+            Command::Write { pin, value, id } => {
+                let op = self.write(&pin_name(pin), value, false, false, &mut fields);
+                (id, self.execute(op))
+            }
+            Command::Toggle { pin, id } => {
+                let op = self
+                    .pin(&pin_name(pin), &mut fields)
+                    .map(|(pin, _)| Op::Toggle { pin });
+                (id, self.execute(op))
+            }
+        };
+
+        let mut body = Map::new();
+        body.insert("event".to_string(), "result".into());
+        if !id.is_null() {
+            body.insert("id".to_string(), id);
+        }
+        match result {
+            Ok(response) => {
+                body.insert("ok".to_string(), true.into());
+                body.extend(fields);
+                body.extend(response);
+            }
+            Err((_, error)) => {
+                body.insert("ok".to_string(), false.into());
+                body.insert("error".to_string(), json!(error));
+            }
+        }
+        Value::Object(body)
+    }
+
+    /// send an operation to the controller the configured pins belong to
+    fn execute(&self, op: Result<Op, Failure>) -> Result<Map<String, Value>, Failure> {
+        self.send(self.live.target_id, op?).map_err(failed)
+    }
+}
+
+fn pin_name(pin: PinNumber) -> String {
+    match pin {
+        PinNumber::Number(number) => number.to_string(),
+        PinNumber::Name(name) => name,
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, value: Value) -> io::Result<()> {
+    socket
+        .send(Message::Text(value.to_string()))
+        .map_err(io::Error::other)
+}
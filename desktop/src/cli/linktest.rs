@@ -0,0 +1,167 @@
+use super::connection::Connection;
+use agpio::gpio::{
+    echo::EchoRequest,
+    error::{ERR_INVALID_TYPE, ERR_MALFORMED_PACKET},
+    Error,
+};
+use agpio::sdsp::{ReadError, PKG_END_BYTE, PKG_START_BYTE};
+
+//
+// Link quality test
+//
+
+/// outcome of all echo requests with one body length
+#[derive(PartialEq, Debug, Default)]
+pub struct SizeResult {
+    /// packet body length, including the type byte
+    pub body_len: usize,
+    pub sent: usize,
+
+    /// echoes that came back unchanged
+    pub intact: usize,
+
+    /// echoes that came back with different data, or other unexpected responses
+    pub corrupted: usize,
+
+    /// responses that failed the checksum on the host
+    pub crc_errors: usize,
+
+    /// requests without a response
+    pub timeouts: usize,
+
+    /// requests the remote controller reported as malformed,
+    /// i.e. that failed the checksum on the remote controller or did not fit its buffer
+    pub rejected: usize,
+}
+
+impl SizeResult {
+    fn is_reliable(&self) -> bool {
+        self.intact == self.sent
+    }
+}
+
+/// send `iterations` echo requests per body length, from 1 byte (just the type) up to
+/// `max_body_len`. stops at the first body length where no echo came back intact.
+///
+/// the payloads alternate between random bytes, bodies full of start or end bytes,
+/// and random mixes of both, to catch framing problems.
+/// the connection should not retry, so every failure is counted.
+/// fails if the remote controller does not know echo requests.
+pub fn run(
+    connection: &mut Connection,
+    target_id: u8,
+    iterations: usize,
+    max_body_len: usize,
+    seed: u64,
+) -> Result<Vec<SizeResult>, Error> {
+    let mut rng = XorShift(seed.max(1));
+    let mut results = Vec::new();
+    for body_len in 1..=max_body_len {
+        let mut result = SizeResult {
+            body_len,
+            ..SizeResult::default()
+        };
+
+        for i in 0..iterations {
+            let data = payload(&mut rng, i, body_len - 1);
+            result.sent += 1;
+            match connection.send(&EchoRequest::new(data.clone()), target_id) {
+                Ok(response) if response.data == data => result.intact += 1,
+                Err(Error::SDSPError {
+                    kind: ReadError::ChecksumMismatch,
+                }) => result.crc_errors += 1,
+                Err(Error::SDSPError {
+                    kind: ReadError::Timeout,
+                }) => result.timeouts += 1,
+                Err(Error::RemoteError {
+                    code: ERR_MALFORMED_PACKET,
+                }) => result.rejected += 1,
+                Err(
+                    error @ Error::RemoteError {
+                        code: ERR_INVALID_TYPE,
+                    },
+                ) => return Err(error),
+                _ => result.corrupted += 1,
+            }
+        }
+
+        let done = result.intact == 0;
+        results.push(result);
+        if done {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+/// print a table of all body lengths and a summary
+pub fn print_report(results: &[SizeResult]) {
+    println!(
+        "{:>4} {:>6} {:>6} {:>7} {:>4} {:>7} {:>8}",
+        "body", "sent", "intact", "corrupt", "crc", "timeout", "rejected"
+    );
+    for r in results {
+        println!(
+            "{:>4} {:>6} {:>6} {:>7} {:>4} {:>7} {:>8}",
+            r.body_len, r.sent, r.intact, r.corrupted, r.crc_errors, r.timeouts, r.rejected
+        );
+    }
+    if let Some(last) = results.last().filter(|r| r.intact == 0) {
+        println!(
+            "stopped at {} bytes: no echo came back intact",
+            last.body_len
+        );
+    }
+    println!();
+
+    let sent: usize = results.iter().map(|r| r.sent).sum();
+    let intact: usize = results.iter().map(|r| r.intact).sum();
+    let crc_errors: usize = results.iter().map(|r| r.crc_errors).sum();
+    println!("frames sent: {}", sent);
+    println!("frame error rate: {:.2}%", percent(sent - intact, sent));
+    println!("crc failure rate: {:.2}%", percent(crc_errors, sent));
+    match largest_reliable_body_len(results) {
+        Some(len) => println!("largest reliable body size: {} bytes", len),
+        None => println!("largest reliable body size: none, even the smallest body had errors"),
+    }
+}
+
+/// the largest body length up to which every echo came back intact
+pub fn largest_reliable_body_len(results: &[SizeResult]) -> Option<usize> {
+    results
+        .iter()
+        .take_while(|r| r.is_reliable())
+        .last()
+        .map(|r| r.body_len)
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+fn payload(rng: &mut XorShift, iteration: usize, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|_| match iteration % 4 {
+            0 => rng.next() as u8,
+            1 => PKG_START_BYTE,
+            2 => PKG_END_BYTE,
+            _ => [PKG_START_BYTE, PKG_END_BYTE, rng.next() as u8][rng.next() as usize % 3],
+        })
+        .collect()
+}
+
+/// xorshift64 generator, good enough for test payloads and reproducible from the seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
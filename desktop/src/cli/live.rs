@@ -0,0 +1,189 @@
+use super::connection::Connection;
+use super::error::ErrorInfo;
+use super::rpc::{execute, Op};
+use agpio::config::{Config, PinConfig};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+use tracing::debug;
+
+//
+// Live pin states
+//
+
+/// how a configured pin is shown on the dashboard
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Widget {
+    /// a digital output
+    Toggle,
+
+    /// an analog (PWM) output
+    Slider,
+
+    /// a digital input
+    Indicator,
+
+    /// an analog input
+    Gauge,
+}
+
+/// a configured pin, and its last known value
+#[derive(Debug, Clone, Serialize)]
+pub struct LivePin {
+    pub name: String,
+    pub pin: u8,
+    pub widget: Widget,
+
+    /// `None` until the pin was read or written
+    pub value: Option<u16>,
+
+    #[serde(skip)]
+    pub config: PinConfig,
+}
+
+impl LivePin {
+    /// the read of the pin when polling, with the flags of its config.
+    /// `None` for analog outputs, which cannot be read back
+    fn read(&self) -> Option<Op> {
+        if self.widget == Widget::Slider {
+            return None;
+        }
+        Some(Op::Read {
+            pin: self.pin,
+            analog: self.config.analog,
+            inverted: self.config.inverted,
+            pullup: self.config.pullup,
+            pulldown: self.config.pulldown,
+            direct: self.config.output,
+        })
+    }
+}
+
+/// a new value of a configured pin
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub name: String,
+    pub pin: u8,
+    pub value: u16,
+}
+
+/// the values of the configured pins, and the subscribers to their changes
+pub struct Live {
+    /// the controller the configured pins belong to
+    pub target_id: u8,
+
+    pins: Mutex<Vec<LivePin>>,
+    subscribers: Mutex<Vec<Sender<Change>>>,
+}
+
+impl Live {
+    /// the configured pins of `device`, and pins without a device, on the controller `target_id`
+    pub fn new(config: &Config, device: Option<&str>, target_id: u8) -> Live {
+        let pins = config
+            .pins
+            .iter()
+            .filter(|(_, pin)| pin.device.is_none() || pin.device.as_deref() == device)
+            .filter_map(|(name, pin)| {
+                let widget = match (pin.output, pin.analog) {
+                    (true, false) => Widget::Toggle,
+                    (true, true) => Widget::Slider,
+                    (false, false) => Widget::Indicator,
+                    (false, true) => Widget::Gauge,
+                };
+                Some(LivePin {
+                    name: name.clone(),
+                    pin: config.pin_number(name).ok()?,
+                    widget,
+                    value: None,
+                    config: pin.clone(),
+                })
+            })
+            .collect();
+        Live {
+            target_id,
+            pins: Mutex::new(pins),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<LivePin> {
+        self.pins
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// read the configured pins, except analog outputs which cannot be read back.
+    /// outputs are read directly, so they stay outputs
+    pub fn poll(&self, connection: &mut Connection) {
+        for live in self.snapshot() {
+            let Some(op) = live.read() else {
+                continue;
+            };
+            if let Err(error) = self.execute(connection, self.target_id, op) {
+                debug!(pin = live.name, error = error.message, "failed to poll pin");
+            }
+        }
+    }
+
+    /// execute an operation, and record the value it read or wrote if it was sent to the
+    /// controller of the configured pins
+    pub(super) fn execute(
+        &self,
+        connection: &mut Connection,
+        target_id: u8,
+        op: Op,
+    ) -> Result<Map<String, Value>, ErrorInfo> {
+        let (pin, written) = match &op {
+            Op::Read { pin, .. } | Op::Toggle { pin } => (Some(*pin), None),
+            Op::Write { pin, value, .. } => (Some(*pin), Some(*value)),
+            _ => (None, None),
+        };
+
+        let fields = execute(connection, target_id, op)?;
+        let value = written.or(fields
+            .get("value")
+            .and_then(|value| value.as_u64())
+            .map(|value| value as u16));
+        if let (Some(pin), Some(value), true) = (pin, value, target_id == self.target_id) {
+            self.update(pin, value);
+        }
+        Ok(fields)
+    }
+
+    /// record the value of a pin, and notify the subscribers if it changed
+    pub fn update(&self, pin: u8, value: u16) {
+        let mut pins = self.pins.lock().unwrap_or_else(PoisonError::into_inner);
+        for live in pins.iter_mut() {
+            if live.pin != pin || live.value == Some(value) {
+                continue;
+            }
+            live.value = Some(value);
+            self.publish(Change {
+                name: live.name.clone(),
+                pin,
+                value,
+            });
+        }
+    }
+
+    /// receive every change of a value
+    pub fn subscribe(&self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    fn publish(&self, change: Change) {
+        // subscribers that are gone are dropped
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+}
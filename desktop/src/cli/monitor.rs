@@ -0,0 +1,101 @@
+use super::{custom::describe, hex};
+use agpio::gpio::custom::CommandSpec;
+use agpio::sdsp::{
+    self,
+    scanner::{ScanEvent, Scanner},
+};
+use agpio::transport::Transport;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+//
+// Passive bus monitor
+//
+
+const COLOR_RED: &str = "\x1b[31m";
+const COLOR_YELLOW: &str = "\x1b[33m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// print every frame seen on the bus until reading from the port fails.
+/// nothing is ever written to the port.
+/// a silence longer than `gap` ends a partially received frame and is marked in the output.
+pub fn run(port: &mut dyn Transport, gap: Duration, specs: &[CommandSpec]) -> io::Result<()> {
+    let stdout = io::stdout();
+    let color = stdout.is_terminal();
+    let mut out = stdout.lock();
+
+    let mut scanner = Scanner::new();
+    let mut buf = [0u8; 256];
+    let start = Instant::now();
+    let mut last_seen: Option<Instant> = None;
+    loop {
+        let n = port.read(&mut buf, gap)?;
+        let now = Instant::now();
+        if n == 0 {
+            // the bus is idle, so a partial frame will not be completed
+            if scanner.is_pending() {
+                scanner.flush();
+            }
+        } else {
+            scanner.push(&buf[..n]);
+        }
+
+        while let Some(event) = scanner.next_event() {
+            // highlight gaps between frames
+            let since_last = last_seen.map(|last| now.duration_since(last));
+            if let Some(since_last) = since_last.filter(|d| *d > gap) {
+                let line = format!("--- idle for {:.3}s ---", since_last.as_secs_f64());
+                print_line(&mut out, color, COLOR_YELLOW, &line)?;
+            }
+            last_seen = Some(now);
+
+            let timestamp = format!(
+                "[{:>10.6}s] +{:>7.1}ms",
+                now.duration_since(start).as_secs_f64(),
+                since_last.unwrap_or_default().as_secs_f64() * 1000.0
+            );
+            match event {
+                ScanEvent::Frame(frame) => {
+                    let packet = sdsp::decode_packet(&frame).expect("scanner yields valid frames");
+                    writeln!(
+                        out,
+                        "{} {:#04x} -> {:#04x}: {}",
+                        timestamp,
+                        packet.sender_id,
+                        packet.receiver_id,
+                        describe(specs, packet.body)
+                    )?;
+                }
+                ScanEvent::Corrupt { frame, error } => {
+                    let line = format!(
+                        "{} {:#04x} -> {:#04x}: CORRUPT FRAME ({:?}) {}",
+                        timestamp,
+                        frame[1],
+                        frame[2],
+                        error,
+                        hex(&frame)
+                    );
+                    print_line(&mut out, color, COLOR_RED, &line)?;
+                }
+                ScanEvent::Garbage(bytes) => {
+                    let line = format!(
+                        "{} GARBAGE ({} bytes) {}",
+                        timestamp,
+                        bytes.len(),
+                        hex(&bytes)
+                    );
+                    print_line(&mut out, color, COLOR_RED, &line)?;
+                }
+            }
+        }
+        out.flush()?;
+    }
+}
+
+fn print_line(out: &mut impl Write, color: bool, code: &str, line: &str) -> io::Result<()> {
+    if color {
+        writeln!(out, "{}{}{}", code, line, COLOR_RESET)
+    } else {
+        writeln!(out, "{}", line)
+    }
+}
@@ -0,0 +1,328 @@
+use super::{connection::Connection, error::ErrorInfo, live::Live, live::Widget, rpc::Op};
+use agpio::config::Config;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+//
+// MQTT bridge
+//
+// the configured pins are published to, and written from, topics of the device:
+//
+//   agpio/<device>/<pin>/state   the value of a configured pin, retained. published when it changes
+//   agpio/<device>/<pin>/set     write `1`, `0`, `ON`, `OFF` or a PWM value, or `TOGGLE` the pin
+//   agpio/<device>/status        `online`, or `offline` once the bridge is gone
+//
+// `<device>` is the name of the device in the config file, or the ID of the target controller.
+// `<pin>` of a set topic is a named pin, a pin number or an analog pin name.
+//
+// with discovery, the configured pins are announced to Home Assistant:
+// outputs as switch, analog outputs as light, inputs as binary_sensor and analog inputs as sensor.
+//
+
+/// how many requests to the broker may be queued
+const QUEUE: usize = 64;
+
+/// how long to wait before connecting to the broker again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// the broker, and how to log in
+pub struct Broker {
+    /// host of the broker, with an optional port, e.g. `localhost` or `mqtt://broker:1883`
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// what the connection to the broker reports to the bridge
+enum Message {
+    Connected,
+    Publish { topic: String, payload: String },
+}
+
+/// bridge between the configured pins of one connection and an MQTT broker
+pub struct Bridge {
+    connection: Connection,
+
+    /// named pins
+    config: Config,
+
+    /// the device from the config file the connection sends to
+    device: Option<String>,
+
+    /// the values of the configured pins
+    live: Live,
+
+    /// the device in topics and Home Assistant: its name in the config file, or the ID of the
+    /// target controller
+    id: String,
+
+    /// prefix of the topics of the device, e.g. `agpio/printer`
+    topic: String,
+
+    /// prefix of the Home Assistant discovery topics, if announced
+    discovery: Option<String>,
+}
+
+impl Bridge {
+    pub fn new(
+        connection: Connection,
+        config: Config,
+        device: Option<String>,
+        target_id: u8,
+        prefix: &str,
+        discovery: Option<String>,
+    ) -> Bridge {
+        let live = Live::new(&config, device.as_deref(), target_id);
+        let id = match &device {
+            Some(device) => device.clone(),
+            None => target_id.to_string(),
+        };
+        Bridge {
+            connection,
+            config,
+            device,
+            live,
+            topic: format!("{}/{}", prefix, id),
+            id,
+            discovery,
+        }
+    }
+
+    /// connect to the broker, and bridge forever, reading the configured pins every `poll`.
+    /// lost connections are retried
+    pub fn run(mut self, broker: &Broker, poll: Duration) -> Result<(), String> {
+        let (client, mut connection) = Client::new(self.options(broker)?, QUEUE);
+
+        // the connection is driven on its own thread, so publishing never waits for the broker
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            for event in connection.iter() {
+                let message = match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => Message::Connected,
+                    Ok(Event::Incoming(Packet::Publish(publish))) => Message::Publish {
+                        topic: publish.topic,
+                        payload: String::from_utf8_lossy(&publish.payload).into_owned(),
+                    },
+                    Ok(_) => continue,
+                    Err(e) => {
+                        warn!(error = %e, "connection to the broker failed");
+                        std::thread::sleep(RECONNECT_DELAY);
+                        continue;
+                    }
+                };
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let changes = self.live.subscribe();
+        let mut next_poll = Instant::now();
+        loop {
+            while let Ok(change) = changes.try_recv() {
+                self.publish_state(&client, &change.name, change.value);
+            }
+
+            let message = if poll.is_zero() {
+                messages.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                messages.recv_timeout(next_poll.saturating_duration_since(Instant::now()))
+            };
+            match message {
+                Ok(Message::Connected) => self.announce(&client),
+                Ok(Message::Publish { topic, payload }) => self.set(&topic, &payload),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("the connection to the broker stopped".to_string())
+                }
+            }
+
+            // also after messages, so a steady stream of them does not stop polling
+            if !poll.is_zero() && Instant::now() >= next_poll {
+                self.live.poll(&mut self.connection);
+                next_poll = Instant::now() + poll;
+            }
+        }
+    }
+
+    fn options(&self, broker: &Broker) -> Result<MqttOptions, String> {
+        let address = broker
+            .address
+            .strip_prefix("mqtt://")
+            .unwrap_or(&broker.address);
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}' of the broker", port))?;
+                (host, port)
+            }
+            None => (address, 1883),
+        };
+
+        let client_id = format!("agpio-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            self.status_topic(),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &broker.username {
+            options.set_credentials(username, broker.password.clone().unwrap_or_default());
+        }
+        Ok(options)
+    }
+
+    /// subscribe to the set topics, and publish the status, discovery configs and known values.
+    /// done on every connect, since the broker forgets subscriptions of clean sessions
+    fn announce(&self, client: &Client) {
+        info!(topic = self.topic, "connected to the broker");
+        let set = format!("{}/+/set", self.topic);
+        if let Err(e) = client.try_subscribe(set, QoS::AtLeastOnce) {
+            warn!(error = %e, "failed to subscribe");
+        }
+        self.publish(client, &self.status_topic(), "online".to_string());
+
+        if let Some(discovery) = &self.discovery {
+            for live in self.live.snapshot() {
+                let (component, config) = self.discovery_config(&live.name, live.widget);
+                let topic = format!(
+                    "{}/{}/{}/config",
+                    discovery,
+                    component,
+                    self.unique_id(&live.name)
+                );
+                self.publish(client, &topic, config.to_string());
+            }
+        }
+
+        for live in self.live.snapshot() {
+            if let Some(value) = live.value {
+                self.publish_state(client, &live.name, value);
+            }
+        }
+    }
+
+    /// the Home Assistant component and discovery config of a configured pin
+    fn discovery_config(&self, name: &str, widget: Widget) -> (&'static str, Value) {
+        let state = format!("{}/{}/state", self.topic, name);
+        let set = format!("{}/{}/set", self.topic, name);
+        let mut config = json!({
+            "name": name,
+            "unique_id": self.unique_id(name),
+            "availability_topic": self.status_topic(),
+            "state_topic": state,
+            "device": {
+                "identifiers": [format!("agpio_{}", self.id)],
+                "name": format!("agpio {}", self.id),
+            },
+        });
+
+        let (component, specific) = match widget {
+            Widget::Toggle => (
+                "switch",
+                json!({ "command_topic": set, "payload_on": "1", "payload_off": "0" }),
+            ),
+            Widget::Slider => (
+                "light",
+                json!({
+                    "command_topic": set,
+                    "state_value_template": "{{ 'ON' if value | int > 0 else 'OFF' }}",
+                    "brightness_command_topic": set,
+                    "brightness_state_topic": state,
+                    "brightness_scale": 255,
+                    "on_command_type": "brightness",
+                }),
+            ),
+            Widget::Indicator => (
+                "binary_sensor",
+                json!({ "payload_on": "1", "payload_off": "0" }),
+            ),
+            Widget::Gauge => ("sensor", json!({ "state_class": "measurement" })),
+        };
+        if let (Some(config), Value::Object(specific)) = (config.as_object_mut(), specific) {
+            config.extend(specific);
+        }
+        (component, config)
+    }
+
+    /// write or toggle the pin of a set topic
+    fn set(&mut self, topic: &str, payload: &str) {
+        let Some(pin) = topic
+            .strip_prefix(&format!("{}/", self.topic))
+            .and_then(|topic| topic.strip_suffix("/set"))
+        else {
+            debug!(topic, "ignoring message");
+            return;
+        };
+
+        if let Err(error) = self.execute_set(pin, payload.trim()) {
+            warn!(topic, payload, error = error.message, "failed to set pin");
+        }
+    }
+
+    fn execute_set(&mut self, pin: &str, payload: &str) -> Result<(), ErrorInfo> {
+        let (pin, named) = self
+            .config
+            .resolve_pin(pin, self.device.as_deref())
+            .map_err(ErrorInfo::invalid_input)?;
+        let analog = named.is_some_and(|named| named.analog);
+        let inverted = named.is_some_and(|named| named.inverted);
+
+        let value = match payload.to_ascii_uppercase().as_str() {
+            "TOGGLE" => {
+                self.live.execute(
+                    &mut self.connection,
+                    self.live.target_id,
+                    Op::Toggle { pin },
+                )?;
+                return Ok(());
+            }
+            "ON" if analog => 255,
+            "ON" => 1,
+            "OFF" => 0,
+            value => value.parse().map_err(|_| {
+                ErrorInfo::invalid_input(format!(
+                    "invalid value '{}', use a number, ON, OFF or TOGGLE",
+                    payload
+                ))
+            })?,
+        };
+        let op = Op::Write {
+            pin,
+            value,
+            analog,
+            inverted,
+        };
+        self.live
+            .execute(&mut self.connection, self.live.target_id, op)?;
+        Ok(())
+    }
+
+    fn publish_state(&self, client: &Client, name: &str, value: u16) {
+        let topic = format!("{}/{}/state", self.topic, name);
+        self.publish(client, &topic, value.to_string());
+    }
+
+    /// publish a retained message, dropping it if too many are queued
+    fn publish(&self, client: &Client, topic: &str, payload: String) {
+        debug!(topic, payload, "publish");
+        if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+            warn!(topic, error = %e, "failed to publish");
+        }
+    }
+
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.topic)
+    }
+
+    /// the ID of a pin in Home Assistant, e.g. `agpio_printer_led`
+    fn unique_id(&self, name: &str) -> String {
+        format!("agpio_{}_{}", self.id, name)
+    }
+}
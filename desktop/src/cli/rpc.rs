@@ -0,0 +1,200 @@
+use super::{connection::Connection, error::ErrorInfo, parse_pin};
+use agpio::gpio::{
+    iic::write::{IICResultCode, IICWriteRequest},
+    raw::RawRequest,
+    read::ReadRequest,
+    toggle::ToggleRequest,
+    write::WriteRequest,
+};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead, Write};
+
+//
+// JSON-lines RPC
+//
+
+/// the operation of a request line, e.g. `{"id":1,"op":"write","pin":13,"value":1}`.
+/// besides the operation, a line has an `id` that is copied to the response as is,
+/// and optionally a `device` to send the request to instead of the target given on the command line.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub(super) enum Op {
+    Read {
+        #[serde(deserialize_with = "pin")]
+        pin: u8,
+        #[serde(default)]
+        analog: bool,
+        #[serde(default)]
+        inverted: bool,
+        #[serde(default)]
+        pullup: bool,
+        #[serde(default)]
+        pulldown: bool,
+        #[serde(default)]
+        direct: bool,
+    },
+    Write {
+        #[serde(deserialize_with = "pin")]
+        pin: u8,
+        value: u16,
+        #[serde(default)]
+        analog: bool,
+        #[serde(default)]
+        inverted: bool,
+    },
+    Toggle {
+        #[serde(deserialize_with = "pin")]
+        pin: u8,
+    },
+    I2cWrite {
+        address: u8,
+        #[serde(default)]
+        data: Vec<u8>,
+        #[serde(default)]
+        stop: bool,
+    },
+    Raw {
+        #[serde(rename = "type")]
+        type_id: u8,
+        #[serde(default)]
+        body: Vec<u8>,
+    },
+}
+
+/// a pin given as number, or as analog pin name like `"A0"`
+fn pin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Pin {
+        Number(u8),
+        Name(String),
+    }
+
+    match Pin::deserialize(deserializer)? {
+        Pin::Number(pin) => Ok(pin),
+        Pin::Name(name) => parse_pin(&name).map_err(D::Error::custom),
+    }
+}
+
+/// answer every request line read from `input` with one response line on `output`,
+/// until the end of input.
+///
+/// responses are `{"id":1,"ok":true,"value":1}`, with the fields depending on the operation,
+/// or `{"id":1,"ok":false,"error":{"category":"timeout","message":"..."}}`.
+pub fn serve(
+    connection: &mut Connection,
+    target_id: u8,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = respond(connection, target_id, &line);
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// the response line to one request line
+fn respond(connection: &mut Connection, target_id: u8, line: &str) -> Value {
+    // take the id first, so even invalid requests are answered with their id
+    let mut request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, ErrorInfo::invalid_input(e.to_string())),
+    };
+    let Some(fields) = request.as_object_mut() else {
+        return error_response(
+            Value::Null,
+            ErrorInfo::invalid_input("expected a JSON object"),
+        );
+    };
+    let id = fields.remove("id").unwrap_or(Value::Null);
+    let device = fields.remove("device").unwrap_or(Value::Null);
+
+    let result = serde_json::from_value::<Option<u8>>(device)
+        .and_then(|device| Ok((device, serde_json::from_value::<Op>(request)?)))
+        .map_err(|e| ErrorInfo::invalid_input(e.to_string()))
+        .and_then(|(device, op)| execute(connection, device.unwrap_or(target_id), op));
+    match result {
+        Ok(fields) => {
+            let mut response = Map::new();
+            response.insert("id".to_string(), id);
+            response.insert("ok".to_string(), Value::Bool(true));
+            response.extend(fields);
+            Value::Object(response)
+        }
+        Err(error) => error_response(id, error),
+    }
+}
+
+fn error_response(id: Value, error: ErrorInfo) -> Value {
+    json!({ "id": id, "ok": false, "error": error })
+}
+
+/// send the request of an operation, and return the fields of the response
+pub(super) fn execute(
+    connection: &mut Connection,
+    target_id: u8,
+    op: Op,
+) -> Result<Map<String, Value>, ErrorInfo> {
+    let mut fields = Map::new();
+    match op {
+        Op::Read {
+            pin,
+            analog,
+            inverted,
+            pullup,
+            pulldown,
+            direct,
+        } => {
+            let request = ReadRequest::new(pin, pullup, pulldown, analog, inverted, direct);
+            let response = connection
+                .send(&request, target_id)
+                .map_err(|e| ErrorInfo::gpio(&e))?;
+            fields.insert("value".to_string(), response.value.into());
+        }
+        Op::Write {
+            pin,
+            value,
+            analog,
+            inverted,
+        } => {
+            let request = WriteRequest::new(pin, value, analog, inverted);
+            connection
+                .send(&request, target_id)
+                .map_err(|e| ErrorInfo::gpio(&e))?;
+        }
+        Op::Toggle { pin } => {
+            let response = connection
+                .send(&ToggleRequest::new(pin), target_id)
+                .map_err(|e| ErrorInfo::gpio(&e))?;
+            fields.insert("value".to_string(), response.new_value.into());
+        }
+        Op::I2cWrite {
+            address,
+            data,
+            stop,
+        } => {
+            let request = IICWriteRequest::new(address, data, stop);
+            let response = connection
+                .send(&request, target_id)
+                .map_err(|e| ErrorInfo::gpio(&e))?;
+            if response.result_code != IICResultCode::Success {
+                return Err(ErrorInfo::i2c(&response.result_code));
+            }
+        }
+        Op::Raw { type_id, body } => {
+            let response = connection
+                .send(&RawRequest::new(type_id, body), target_id)
+                .map_err(|e| ErrorInfo::gpio(&e))?;
+            fields.insert("body".to_string(), response.body.into());
+        }
+    }
+    Ok(fields)
+}
@@ -0,0 +1,405 @@
+use super::{error::ErrorInfo, Reply};
+use agpio::duration::parse_duration;
+use clap::{ArgMatches, Command};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+//
+// Script files
+//
+
+/// a parsed script.
+///
+/// every line is a request, using the same arguments as the request subcommands, or one of:
+/// - `sleep <duration>`, e.g. `sleep 250ms` or `sleep 2s`
+/// - `repeat <count> {`, repeating the lines up to the matching `}`
+/// - `$name = <request>`, storing the value a request returns (read or toggle)
+/// - `if <value> <op> <value> {`, with `==`, `!=`, `<`, `<=`, `>` or `>=`,
+///   optionally followed by `} else {`
+///
+/// `$name` can be used in place of any argument or value. lines starting with `#` are ignored.
+#[derive(PartialEq, Debug)]
+pub struct Script {
+    statements: Vec<Statement>,
+}
+
+/// error in a script, with the line number it happened on
+#[derive(PartialEq, Debug)]
+pub struct ScriptError {
+    /// line number, starting at 1
+    pub line: usize,
+    pub error: ErrorInfo,
+}
+
+impl ScriptError {
+    /// a line that cannot be parsed or run
+    fn invalid_input(line: usize, message: impl Into<String>) -> ScriptError {
+        ScriptError {
+            line,
+            error: ErrorInfo::invalid_input(message),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error.message)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Statement {
+    Request {
+        line: usize,
+        words: Vec<String>,
+
+        /// variable to store the value in, instead of printing it
+        capture: Option<String>,
+    },
+    Sleep(Duration),
+    Repeat {
+        count: u64,
+        body: Vec<Statement>,
+    },
+    If {
+        line: usize,
+        condition: Condition,
+        then: Vec<Statement>,
+        otherwise: Vec<Statement>,
+    },
+}
+
+#[derive(PartialEq, Debug)]
+struct Condition {
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+}
+
+#[derive(PartialEq, Debug)]
+enum Operand {
+    Variable(String),
+    Number(i64),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// how a block of statements ended
+enum BlockEnd {
+    /// a `}` line
+    Close,
+
+    /// a `} else {` line
+    Else,
+
+    /// the end of the script
+    Eof,
+}
+
+impl Script {
+    /// parse a script, checking every request against `grammar`
+    pub fn parse(source: &str, grammar: &mut Command) -> Result<Script, ScriptError> {
+        let mut parser = Parser {
+            lines: source
+                .lines()
+                .enumerate()
+                .map(|(i, line)| (i + 1, line.split_whitespace().collect::<Vec<_>>()))
+                .filter(|(_, words)| words.first().is_some_and(|word| !word.starts_with('#')))
+                .collect(),
+            next: 0,
+            grammar,
+        };
+        let (statements, end) = parser.block()?;
+        match end {
+            BlockEnd::Eof => Ok(Script { statements }),
+            _ => Err(parser.error("`}` without a block to close")),
+        }
+    }
+
+    /// run the script, passing every request to `execute`, together with whether its reply
+    /// should be printed (it is not when stored in a variable).
+    /// stops at the first request that fails.
+    pub fn run(
+        &self,
+        grammar: &mut Command,
+        execute: impl FnMut(&ArgMatches, bool) -> Result<Reply, ErrorInfo>,
+    ) -> Result<(), ScriptError> {
+        Interpreter {
+            grammar,
+            execute,
+            variables: HashMap::new(),
+        }
+        .block(&self.statements)
+    }
+}
+
+//
+// Parser
+//
+
+struct Parser<'a> {
+    /// line number and words of every line that is not empty or a comment
+    lines: Vec<(usize, Vec<&'a str>)>,
+    next: usize,
+    grammar: &'a mut Command,
+}
+
+impl Parser<'_> {
+    /// parse statements up to the end of the current block
+    fn block(&mut self) -> Result<(Vec<Statement>, BlockEnd), ScriptError> {
+        let mut statements = Vec::new();
+        while let Some((line, words)) = self.lines.get(self.next).cloned() {
+            self.next += 1;
+            let statement = match words.as_slice() {
+                ["}"] => return Ok((statements, BlockEnd::Close)),
+                ["}", "else", "{"] => return Ok((statements, BlockEnd::Else)),
+                ["sleep", duration] => Statement::Sleep(
+                    parse_duration(duration).map_err(|message| self.error(message))?,
+                ),
+                ["sleep", ..] => return Err(self.error("expected `sleep <duration>`")),
+                ["repeat", count, "{"] => Statement::Repeat {
+                    count: count
+                        .parse()
+                        .map_err(|_| self.error(format!("invalid repeat count '{}'", count)))?,
+                    body: self.nested_block(line, false)?.0,
+                },
+                ["repeat", ..] => return Err(self.error("expected `repeat <count> {`")),
+                ["if", left, comparison, right, "{"] => {
+                    let condition = Condition {
+                        left: self.operand(left)?,
+                        comparison: parse_comparison(comparison)
+                            .map_err(|message| self.error(message))?,
+                        right: self.operand(right)?,
+                    };
+                    let (then, end) = self.nested_block(line, true)?;
+                    let otherwise = match end {
+                        BlockEnd::Else => self.nested_block(line, false)?.0,
+                        _ => Vec::new(),
+                    };
+                    Statement::If {
+                        line,
+                        condition,
+                        then,
+                        otherwise,
+                    }
+                }
+                ["if", ..] => return Err(self.error("expected `if <value> <op> <value> {`")),
+                [variable, "=", request @ ..] => Statement::Request {
+                    line,
+                    words: self.request(request)?,
+                    capture: Some(self.variable(variable)?),
+                },
+                request => Statement::Request {
+                    line,
+                    words: self.request(request)?,
+                    capture: None,
+                },
+            };
+            statements.push(statement);
+        }
+        Ok((statements, BlockEnd::Eof))
+    }
+
+    /// parse the block opened on line `line`, which has to be closed
+    fn nested_block(
+        &mut self,
+        line: usize,
+        allow_else: bool,
+    ) -> Result<(Vec<Statement>, BlockEnd), ScriptError> {
+        let (statements, end) = self.block()?;
+        match end {
+            BlockEnd::Close => Ok((statements, end)),
+            BlockEnd::Else if allow_else => Ok((statements, end)),
+            BlockEnd::Else => Err(self.error("`else` without `if`")),
+            BlockEnd::Eof => Err(ScriptError::invalid_input(
+                line,
+                "block is never closed with `}`",
+            )),
+        }
+    }
+
+    /// check a request against the grammar, with a placeholder value for every variable
+    fn request(&mut self, words: &[&str]) -> Result<Vec<String>, ScriptError> {
+        if words.is_empty() {
+            return Err(self.error("expected a request after `=`"));
+        }
+        let mut placeholders = Vec::new();
+        for word in words {
+            if word.starts_with('$') {
+                self.variable(word)?;
+                placeholders.push("0");
+            } else {
+                placeholders.push(word);
+            }
+        }
+        self.grammar
+            .try_get_matches_from_mut(placeholders)
+            .map_err(|e| self.error(clap_error_message(&e)))?;
+        Ok(words.iter().map(|word| word.to_string()).collect())
+    }
+
+    fn operand(&self, word: &str) -> Result<Operand, ScriptError> {
+        if word.starts_with('$') {
+            return Ok(Operand::Variable(self.variable(word)?));
+        }
+        word.parse()
+            .map(Operand::Number)
+            .map_err(|_| self.error(format!("'{}' is neither a number nor a variable", word)))
+    }
+
+    /// check the name of a `$variable`, and return it without the `$`
+    fn variable(&self, word: &str) -> Result<String, ScriptError> {
+        let name = word
+            .strip_prefix('$')
+            .filter(|name| {
+                name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+            .ok_or_else(|| self.error(format!("invalid variable name '{}'", word)))?;
+        Ok(name.to_string())
+    }
+
+    /// error on the line parsed last
+    fn error(&self, message: impl Into<String>) -> ScriptError {
+        ScriptError::invalid_input(self.lines[self.next - 1].0, message)
+    }
+}
+
+/// parse the operator of a condition, e.g. `==` or `<=`
+fn parse_comparison(value: &str) -> Result<Comparison, String> {
+    match value {
+        "==" => Ok(Comparison::Equal),
+        "!=" => Ok(Comparison::NotEqual),
+        "<" => Ok(Comparison::Less),
+        "<=" => Ok(Comparison::LessOrEqual),
+        ">" => Ok(Comparison::Greater),
+        ">=" => Ok(Comparison::GreaterOrEqual),
+        _ => Err(format!(
+            "invalid comparison '{}', use ==, !=, <, <=, > or >=",
+            value
+        )),
+    }
+}
+
+/// the first line of a clap error, without the `error: ` prefix
+fn clap_error_message(error: &clap::Error) -> String {
+    let rendered = error.to_string();
+    let first_line = rendered.lines().next().unwrap_or_default();
+    first_line
+        .strip_prefix("error: ")
+        .unwrap_or(first_line)
+        .to_string()
+}
+
+//
+// Interpreter
+//
+
+struct Interpreter<'a, F> {
+    grammar: &'a mut Command,
+    execute: F,
+    variables: HashMap<String, i64>,
+}
+
+impl<F: FnMut(&ArgMatches, bool) -> Result<Reply, ErrorInfo>> Interpreter<'_, F> {
+    fn block(&mut self, statements: &[Statement]) -> Result<(), ScriptError> {
+        for statement in statements {
+            match statement {
+                Statement::Request {
+                    line,
+                    words,
+                    capture,
+                } => self.request(*line, words, capture.as_deref())?,
+                Statement::Sleep(duration) => std::thread::sleep(*duration),
+                Statement::Repeat { count, body } => {
+                    for _ in 0..*count {
+                        self.block(body)?;
+                    }
+                }
+                Statement::If {
+                    line,
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    if self.evaluate(*line, condition)? {
+                        self.block(then)?;
+                    } else {
+                        self.block(otherwise)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn request(
+        &mut self,
+        line: usize,
+        words: &[String],
+        capture: Option<&str>,
+    ) -> Result<(), ScriptError> {
+        let error = |message: String| ScriptError::invalid_input(line, message);
+
+        // substitute variables
+        let mut arguments = Vec::new();
+        for word in words {
+            match word.strip_prefix('$') {
+                Some(name) => arguments.push(self.value(line, name)?.to_string()),
+                None => arguments.push(word.clone()),
+            }
+        }
+
+        let matches = self
+            .grammar
+            .try_get_matches_from_mut(arguments)
+            .map_err(|e| error(clap_error_message(&e)))?;
+        let reply = (self.execute)(&matches, capture.is_none())
+            .map_err(|error| ScriptError { line, error })?;
+        match (reply, capture) {
+            (Reply::Value(value) | Reply::Analog(value), Some(name)) => {
+                self.variables.insert(name.to_string(), value.into());
+            }
+            (Reply::Text(_), Some(_)) => {
+                return Err(error(format!("`{}` does not return a value", words[0])));
+            }
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, line: usize, condition: &Condition) -> Result<bool, ScriptError> {
+        let left = self.operand(line, &condition.left)?;
+        let right = self.operand(line, &condition.right)?;
+        Ok(match condition.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        })
+    }
+
+    fn operand(&self, line: usize, operand: &Operand) -> Result<i64, ScriptError> {
+        match operand {
+            Operand::Variable(name) => self.value(line, name),
+            Operand::Number(number) => Ok(*number),
+        }
+    }
+
+    fn value(&self, line: usize, name: &str) -> Result<i64, ScriptError> {
+        self.variables.get(name).copied().ok_or_else(|| {
+            ScriptError::invalid_input(line, format!("variable '${}' is not set", name))
+        })
+    }
+}
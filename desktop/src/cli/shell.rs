@@ -0,0 +1,176 @@
+use super::ANALOG_PIN_NAMES;
+use clap::{ArgMatches, Command};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, CompletionType, Config, Context, Editor, Helper,
+};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+//
+// Interactive shell
+//
+
+/// words that end the shell
+const EXIT_WORDS: [&str; 2] = ["exit", "quit"];
+
+/// read lines until `exit` or the end of input, and call `execute` with every line
+/// that parses as a subcommand of `grammar`.
+/// `pins` are the named pins offered by the completion, besides the pin numbers.
+/// parse errors and help are printed, and do not end the shell.
+/// lines starting with `#` are ignored.
+pub fn run(
+    mut grammar: Command,
+    pins: Vec<String>,
+    mut execute: impl FnMut(&ArgMatches),
+) -> rustyline::Result<()> {
+    // build once, so the completion sees the generated help subcommands and flags
+    grammar.build();
+
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::with_config(config)?;
+    editor.set_helper(Some(ShellHelper {
+        grammar: grammar.clone(),
+        pins,
+    }));
+
+    // only keep a history of interactive sessions, not of piped input
+    let history = history_path().filter(|_| std::io::stdin().is_terminal());
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline("agpio> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            None => continue,
+            Some(word) if word.starts_with('#') => continue,
+            Some(word) if EXIT_WORDS.contains(word) => break,
+            Some(_) => {}
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        match grammar.try_get_matches_from_mut(&words) {
+            Ok(matches) => execute(&matches),
+            Err(e) => {
+                let _ = e.print();
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+/// `~/.agpio_history`
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".agpio_history"))
+}
+
+/// completion of subcommands, flags and pin names
+struct ShellHelper {
+    grammar: Command,
+
+    /// named pins from the config file
+    pins: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        Ok((
+            start,
+            complete(&self.grammar, &self.pins, &words, &line[start..]),
+        ))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// candidates for the word `partial`, following the complete `words` before it
+fn complete(grammar: &Command, pins: &[String], words: &[&str], partial: &str) -> Vec<String> {
+    // find the subcommand and the positional argument the word belongs to
+    let mut command = grammar;
+    let mut position = 0;
+    for word in words.iter().filter(|word| !word.starts_with('-')) {
+        match command.find_subcommand(word) {
+            Some(subcommand) => {
+                command = subcommand;
+                position = 0;
+            }
+            None => position += 1,
+        }
+    }
+
+    let candidates: Vec<String> = if partial.starts_with('-') {
+        command
+            .get_arguments()
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{}", long))
+            .collect()
+    } else {
+        let mut names: Vec<String> = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect();
+        if command
+            .get_positionals()
+            .nth(position)
+            .is_some_and(|arg| arg.get_id() == "pin")
+        {
+            names.extend(pin_names());
+            names.extend(pins.iter().cloned());
+        }
+        if words.is_empty() {
+            names.extend(EXIT_WORDS.map(String::from));
+        }
+        names
+    };
+
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(partial))
+        .map(|candidate| candidate + " ")
+        .collect()
+}
+
+/// the pins that can be used, D0 and D1 are used for serial
+fn pin_names() -> impl Iterator<Item = String> {
+    (2..=13)
+        .map(|pin: u8| pin.to_string())
+        .chain(ANALOG_PIN_NAMES.map(String::from))
+}
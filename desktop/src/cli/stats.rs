@@ -0,0 +1,45 @@
+use agpio::gpio::{
+    describe::packet_type_name,
+    stats::{Counters, Stats},
+};
+
+//
+// Link statistics report
+//
+
+/// format the link statistics of a host controller, one line per device and command type
+pub fn format_stats(stats: &Stats) -> String {
+    let mut lines = vec![format!("all requests: {}", format_counters(&stats.total))];
+    for (id, counters) in &stats.devices {
+        lines.push(format!("device {:#04x}: {}", id, format_counters(counters)));
+    }
+    for (type_id, counters) in &stats.types {
+        let name = packet_type_name(*type_id).unwrap_or("user-defined");
+        lines.push(format!(
+            "type {:#04x} ({}): {}",
+            type_id,
+            name,
+            format_counters(counters)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_counters(c: &Counters) -> String {
+    let mut line = format!(
+        "{} requests, {} ok, {} failed, {} retries, {} timeouts, {} crc errors, {} mismatches",
+        c.requests, c.successes, c.failures, c.retries, c.timeouts, c.crc_errors, c.mismatches
+    );
+    if let (Some(min), Some(p50), Some(p99), Some(max)) = (
+        c.latency.min(),
+        c.latency.percentile(50.0),
+        c.latency.percentile(99.0),
+        c.latency.max(),
+    ) {
+        line += &format!(
+            "\n    latency: min {:.1?}, p50 {:.1?}, p99 {:.1?}, max {:.1?}",
+            min, p50, p99, max
+        );
+    }
+    line
+}
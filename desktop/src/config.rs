@@ -0,0 +1,314 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//
+// Configuration file
+//
+// devices and pins are given names in a TOML file:
+//
+//   default_device = "printer"
+//
+//   [devices.printer]
+//   port = "/dev/ttyUSB0"     # or usb_serial = "A50285BI"
+//   baud = 115200
+//   own_id = 0xAA
+//   target_id = 0xCA
+//   board = "uno"
+//   policy = "printer-policy.toml"
+//
+//   [pins.bed_light]
+//   device = "printer"
+//   pin = 9                   # or "A0"
+//   analog = true
+//   inverted = true
+//   output = true             # read with direct by the dashboard and MQTT, so it stays an output
+//
+// all device settings are optional, and are overridden by the command line.
+// the flags of a pin are used as defaults for every request to it.
+//
+
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// the device to use if neither a port nor a named pin selects one
+    #[serde(default)]
+    pub default_device: Option<String>,
+
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceConfig>,
+
+    #[serde(default)]
+    pub pins: BTreeMap<String, PinConfig>,
+}
+
+/// a controller and how to connect to it
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// the serial port, e.g. `/dev/ttyUSB0` or `COM3`
+    #[serde(default)]
+    pub port: Option<String>,
+
+    /// the serial number of the USB serial adapter, to find the port when it changes
+    #[serde(default)]
+    pub usb_serial: Option<String>,
+
+    #[serde(default)]
+    pub baud: Option<u32>,
+
+    /// the address of the host controller
+    #[serde(default)]
+    pub own_id: Option<u8>,
+
+    /// the address of the remote controller
+    #[serde(default)]
+    pub target_id: Option<u8>,
+
+    #[serde(default)]
+    pub retries: Option<i32>,
+
+    #[serde(default)]
+    pub board: Option<Board>,
+
+    /// the pin access policy file of the device, relative to the configuration file
+    #[serde(default)]
+    pub policy: Option<PathBuf>,
+}
+
+/// the Arduino board of a device, which decides the pin numbers of the analog pins
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Board {
+    /// A0 - A5 are pins 14 - 19
+    Uno,
+
+    /// A0 - A7 are pins 14 - 21
+    Nano,
+
+    /// A0 - A15 are pins 54 - 69
+    Mega,
+}
+
+/// a named pin, with default flags for requests to it
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinConfig {
+    /// the device the pin belongs to
+    #[serde(default)]
+    pub device: Option<String>,
+
+    pub pin: PinNumber,
+
+    #[serde(default)]
+    pub analog: bool,
+
+    #[serde(default)]
+    pub inverted: bool,
+
+    #[serde(default)]
+    pub pullup: bool,
+
+    #[serde(default)]
+    pub pulldown: bool,
+
+    /// the pin is an output. the dashboard and the MQTT bridge read it with `direct`, and offer to write it
+    #[serde(default)]
+    pub output: bool,
+}
+
+/// a pin given as number, or as analog pin name like `A0`
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PinNumber {
+    Number(u8),
+    Name(String),
+}
+
+impl Config {
+    /// `agpio/config.toml` in the user's configuration directory,
+    /// e.g. `~/.config/agpio/config.toml` or `%APPDATA%\agpio\config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        dir.map(|dir| dir.join("agpio").join("config.toml"))
+    }
+
+    /// load and validate a configuration file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Config> {
+        let path = path.as_ref();
+        let mut config = Config::parse(&std::fs::read_to_string(path)?)?;
+
+        // make the paths of policy files relative to the configuration file
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for device in config.devices.values_mut() {
+            if let Some(policy) = &mut device.policy {
+                *policy = dir.join(&*policy);
+            }
+        }
+        Ok(config)
+    }
+
+    /// parse and validate a configuration file
+    pub fn parse(text: &str) -> io::Result<Config> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let config: Config = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+        if let Some(device) = &config.default_device {
+            if !config.devices.contains_key(device) {
+                return Err(invalid(format!(
+                    "default device '{}' is not defined",
+                    device
+                )));
+            }
+        }
+        for (name, device) in &config.devices {
+            if device.port.is_some() && device.usb_serial.is_some() {
+                return Err(invalid(format!(
+                    "device '{}': only one of port and usb_serial can be given",
+                    name
+                )));
+            }
+        }
+        for (name, pin) in &config.pins {
+            if parse_pin(name, None).is_ok() {
+                return Err(invalid(format!(
+                    "pin '{}': the name is already a pin number",
+                    name
+                )));
+            }
+            if let Some(device) = &pin.device {
+                if !config.devices.contains_key(device) {
+                    return Err(invalid(format!(
+                        "pin '{}': device '{}' is not defined",
+                        name, device
+                    )));
+                }
+            }
+            config
+                .pin_number(name)
+                .map_err(|msg| invalid(format!("pin '{}': {}", name, msg)))?;
+        }
+        Ok(config)
+    }
+
+    /// the board of a device, if configured
+    pub fn board(&self, device: Option<&str>) -> Option<Board> {
+        device
+            .and_then(|device| self.devices.get(device))
+            .and_then(|device| device.board)
+    }
+
+    /// resolve a pin given as number, analog pin name or named pin, for requests to `device`.
+    /// named pins are returned with their configuration
+    pub fn resolve_pin(
+        &self,
+        value: &str,
+        device: Option<&str>,
+    ) -> Result<(u8, Option<&PinConfig>), String> {
+        let Some(pin) = self.pins.get(value) else {
+            return parse_pin(value, self.board(device))
+                .map(|number| (number, None))
+                .map_err(|e| format!("{}, or a named pin from the config file", e));
+        };
+
+        if let (Some(owner), Some(device)) = (&pin.device, device) {
+            if owner != device {
+                return Err(format!(
+                    "pin '{}' belongs to device '{}', not '{}'",
+                    value, owner, device
+                ));
+            }
+        }
+        Ok((self.pin_number(value)?, Some(pin)))
+    }
+
+    /// the pin number of a named pin, according to the board of its device
+    pub fn pin_number(&self, name: &str) -> Result<u8, String> {
+        let pin = self
+            .pins
+            .get(name)
+            .ok_or_else(|| format!("pin '{}' is not defined", name))?;
+        match &pin.pin {
+            PinNumber::Number(number) => Ok(*number),
+            PinNumber::Name(value) => parse_pin(value, self.board(pin.device.as_deref())),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// the serial port of the device, looking up the USB serial number if needed.
+    /// `None` if neither is configured
+    pub fn find_port(&self) -> io::Result<Option<String>> {
+        let Some(serial) = &self.usb_serial else {
+            return Ok(self.port.clone());
+        };
+        let ports = serialport::available_ports().map_err(io::Error::from)?;
+        ports
+            .into_iter()
+            .find(|port| match &port.port_type {
+                serialport::SerialPortType::UsbPort(usb) => {
+                    usb.serial_number.as_deref() == Some(serial.as_str())
+                }
+                _ => false,
+            })
+            .map(|port| Some(port.port_name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no USB serial adapter with serial number {}", serial),
+                )
+            })
+    }
+}
+
+impl Board {
+    /// pin number of A0, and the number of analog pins
+    fn analog_pins(&self) -> (u8, u8) {
+        match self {
+            Board::Uno => (14, 6),
+            Board::Nano => (14, 8),
+            Board::Mega => (54, 16),
+        }
+    }
+}
+
+/// parse a byte value given as decimal or as hex with a `0x` prefix
+pub fn parse_u8(value: &str) -> Result<u8, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a valid byte value", value))
+}
+
+/// parse a pin given as number, or as analog pin name like `A0`.
+/// without a board, A0 - A7 are pins 14 - 21, like on the Uno and Nano.
+pub fn parse_pin(value: &str, board: Option<Board>) -> Result<u8, String> {
+    if let Ok(number) = value.parse() {
+        return Ok(number);
+    }
+
+    let (a0, count) = board.unwrap_or(Board::Nano).analog_pins();
+    value
+        .strip_prefix(['A', 'a'])
+        .and_then(|index| index.parse::<u8>().ok())
+        .filter(|index| *index < count)
+        .map(|index| a0 + index)
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a valid pin, use a number or A0 - A{}",
+                value,
+                count - 1
+            )
+        })
+}
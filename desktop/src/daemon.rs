@@ -0,0 +1,251 @@
+use crate::gpio::{
+    as_response_type, policy::Policy, raw::RawRequest, stats, Error, HostController, Request,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+use tracing::{debug, info};
+
+//
+// Daemon
+//
+// agpiod owns the serial ports of the configured devices, and sends the requests of any number
+// of clients connected to its Unix socket, one at a time per device.
+//
+// the protocol is JSON lines. on connect, the daemon sends the names of its devices:
+//
+//   {"devices":["printer"]}
+//
+// and then answers every request line with one response line:
+//
+//   {"device":"printer","recipient_id":202,"body":[1,13,16]}
+//   {"body":[129,0,1]}  or  {"error":{"RemoteError":{"code":2}}}
+//
+// bodies are complete packet bodies, including the type byte.
+//
+
+/// host error code of a request to a device the daemon does not serve
+pub const ERR_UNKNOWN_DEVICE: u8 = 0x01;
+
+/// host error code of a request without a type byte
+pub const ERR_EMPTY_REQUEST: u8 = 0x02;
+
+/// host error code of a lost connection to the daemon
+pub const ERR_DAEMON_CONNECTION: u8 = 0x03;
+
+#[derive(Serialize, Deserialize)]
+struct Greeting {
+    devices: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DaemonRequest {
+    device: String,
+    recipient_id: u8,
+    body: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DaemonResponse {
+    Body(Vec<u8>),
+    Error(Error),
+}
+
+/// the socket of the daemon: `$AGPIOD_SOCKET`, or `agpiod.sock` in the runtime directory
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("AGPIOD_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("agpiod.sock"),
+        None => std::env::temp_dir().join(format!(
+            "agpiod-{}.sock",
+            std::env::var("USER").unwrap_or_default()
+        )),
+    }
+}
+
+//
+// Server
+//
+
+/// accept clients on `listener` forever, sending their requests to the host controller
+/// of the device they name. every client is served on its own thread.
+pub fn serve(listener: UnixListener, devices: BTreeMap<String, HostController>) -> io::Result<()> {
+    let devices: Arc<BTreeMap<String, Mutex<HostController>>> = Arc::new(
+        devices
+            .into_iter()
+            .map(|(name, host)| (name, Mutex::new(host)))
+            .collect(),
+    );
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let devices = devices.clone();
+        std::thread::spawn(move || {
+            debug!("client connected");
+            if let Err(e) = serve_client(stream, &devices) {
+                debug!(error = %e, "client failed");
+            }
+            debug!("client disconnected");
+        });
+    }
+    Ok(())
+}
+
+fn serve_client(
+    stream: UnixStream,
+    devices: &BTreeMap<String, Mutex<HostController>>,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let greeting = Greeting {
+        devices: devices.keys().cloned().collect(),
+    };
+    writeln!(writer, "{}", serde_json::to_string(&greeting)?)?;
+
+    for line in BufReader::new(stream).lines() {
+        let request: DaemonRequest = serde_json::from_str(&line?)?;
+        let response = match devices.get(&request.device) {
+            Some(host) => {
+                let mut host = host.lock().unwrap_or_else(PoisonError::into_inner);
+                send(&mut host, &request)
+            }
+            None => DaemonResponse::Error(Error::HostError {
+                code: ERR_UNKNOWN_DEVICE,
+            }),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+    Ok(())
+}
+
+/// send the packet body of a request as is, and return the complete response body
+fn send(host: &mut HostController, request: &DaemonRequest) -> DaemonResponse {
+    let Some((&type_id, body)) = request.body.split_first() else {
+        return DaemonResponse::Error(Error::HostError {
+            code: ERR_EMPTY_REQUEST,
+        });
+    };
+    match host.send(
+        &RawRequest::new(type_id, body.to_vec()),
+        request.recipient_id,
+    ) {
+        Ok(response) => {
+            let mut body = vec![as_response_type!(type_id)];
+            body.extend(response.body);
+            DaemonResponse::Body(body)
+        }
+        Err(error) => DaemonResponse::Error(error),
+    }
+}
+
+//
+// Client
+//
+
+/// connection to a running daemon, used like a `HostController`
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    devices: Vec<String>,
+    stats: stats::Stats,
+    policy: Option<Policy>,
+}
+
+impl Client {
+    /// connect to the daemon listening on `path`. fails if no daemon is running
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Client> {
+        let writer = UnixStream::connect(path)?;
+        let mut reader = BufReader::new(writer.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let greeting: Greeting = serde_json::from_str(&line)?;
+        info!(devices = ?greeting.devices, "connected to agpiod");
+        Ok(Client {
+            reader,
+            writer,
+            devices: greeting.devices,
+            stats: stats::Stats::default(),
+            policy: None,
+        })
+    }
+
+    /// the names of the devices the daemon serves
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    /// snapshot of the request counters and latencies of this client
+    pub fn stats(&self) -> stats::Stats {
+        self.stats.clone()
+    }
+
+    /// refuse requests that violate `policy` from now on, in addition to the policy of the daemon
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = Some(policy);
+    }
+
+    /// send a request to the controller with id `recipient_id` of the daemon's device `device`.
+    /// retries are done by the daemon.
+    pub fn send<R: Request + ?Sized>(
+        &mut self,
+        device: &str,
+        request: &R,
+        recipient_id: u8,
+    ) -> Result<R::Response, Error> {
+        let type_id = request.type_id();
+        let mut body = Vec::new();
+        request.encode(&mut body);
+        if let Some(policy) = &self.policy {
+            if let Err((pin, violation)) = policy.check(&body) {
+                return Err(Error::PolicyViolation { pin, violation });
+            }
+        }
+
+        self.stats
+            .record(recipient_id, type_id, |c| c.requests += 1);
+        let start = Instant::now();
+        let request_line = DaemonRequest {
+            device: device.to_string(),
+            recipient_id,
+            body,
+        };
+        let response = match self.exchange(&request_line) {
+            Ok(DaemonResponse::Body(body)) => request.parse_response(&body),
+            Ok(DaemonResponse::Error(error)) => Err(error),
+            Err(e) => {
+                debug!(error = %e, "lost connection to agpiod");
+                Err(Error::HostError {
+                    code: ERR_DAEMON_CONNECTION,
+                })
+            }
+        };
+
+        let latency = start.elapsed();
+        match &response {
+            Ok(_) => self.stats.record(recipient_id, type_id, |c| {
+                c.successes += 1;
+                c.latency.record(latency);
+            }),
+            Err(error) => self.stats.record(recipient_id, type_id, |c| {
+                c.record_error(error);
+                c.failures += 1;
+            }),
+        }
+        response
+    }
+
+    fn exchange(&mut self, request: &DaemonRequest) -> io::Result<DaemonResponse> {
+        writeln!(self.writer, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
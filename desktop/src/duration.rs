@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+//
+// Durations
+//
+// given with a unit, e.g. `250ms`, `2s` or `0.5s`, by scripts and the input files of the
+// simulator.
+//
+
+/// parse a duration with a unit, rejecting negative, NaN and out-of-range values
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let error = || format!("invalid duration '{}', use e.g. 250ms or 2s", value);
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1.0)
+    } else {
+        return Err(error());
+    };
+    let number: f64 = number.parse().map_err(|_| error())?;
+    Duration::try_from_secs_f64(number * scale).map_err(|_| error())
+}
@@ -1,19 +1,27 @@
+pub mod custom;
+pub mod describe;
+pub mod echo;
 pub mod error;
 pub mod iic;
+pub mod policy;
+pub mod raw;
 pub mod read;
+pub mod stats;
 pub mod toggle;
 pub mod write;
 
 use crate::sdsp;
-use serialport::SerialPort;
-use std::time::Duration;
+use crate::transport::{Direction, FrameTap, Transport};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, debug_span, info};
 
 //
 // Common GPIO functionality
 //
 
 /// common GPIO error types
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum Error {
     // error in SDSP layer
     SDSPError {
@@ -35,15 +43,12 @@ pub enum Error {
 
     /// remote controller response does not match the request
     ResponseMismatch,
-}
-
-/// common GPIO request type
-pub trait Request<ResponseType>: std::fmt::Debug {
-    /// get the SDSP packet body for this request
-    fn get_packet_body(&self) -> Vec<u8>;
 
-    /// attempt to parse the response packet body into a response type
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<ResponseType, Error>;
+    /// the request was refused by the pin access policy, and not sent
+    PolicyViolation {
+        pin: u8,
+        violation: policy::Violation,
+    },
 }
 
 macro_rules! as_request_type {
@@ -59,101 +64,316 @@ macro_rules! as_response_type {
 pub(crate) use as_request_type;
 pub(crate) use as_response_type;
 
+/// declared length of a packet body, excluding the leading type byte
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BodyLength {
+    /// the body is exactly this many bytes long
+    Fixed(usize),
+
+    /// the body is at least this many bytes long, followed by variable-length data
+    AtLeast(usize),
+}
+impl BodyLength {
+    /// check if a body of `len` bytes satisfies this declaration
+    pub fn matches(&self, len: usize) -> bool {
+        match *self {
+            BodyLength::Fixed(n) => len == n,
+            BodyLength::AtLeast(n) => len >= n,
+        }
+    }
+}
+
+/// a packet body that can be encoded for sending.
+/// implemented for every `Packet`, and by bodies whose type is only known at runtime
+pub trait Encode: std::fmt::Debug {
+    /// the packet type ID, sent as the first byte of the packet body
+    fn type_id(&self) -> u8;
+
+    /// length of the body written by `encode_body`, excluding the type byte
+    fn body_len(&self) -> usize;
+
+    /// append the body, excluding the type byte, to `out`
+    fn encode_body(&self, out: &mut Vec<u8>);
+
+    /// append the full packet body, including the type byte, to `out`
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.reserve(1 + self.body_len());
+        out.push(self.type_id());
+        self.encode_body(out);
+    }
+}
+
+/// a packet body that can be decoded from a received packet.
+/// implemented for every `Packet`, and by bodies of any type
+pub trait Decode: Sized {
+    /// declared body length, excluding the type byte
+    const BODY_LEN: BodyLength;
+
+    /// decode the body, excluding the type byte.
+    /// the length of `body` is already checked against `BODY_LEN`.
+    fn decode_body(body: &[u8]) -> Result<Self, Error>;
+}
+
+/// a packet body with a fixed type ID, encoded and decoded by the same type.
+/// implementing it is all a command needs for `Encode` and `Decode`
+pub trait Packet: Sized + std::fmt::Debug {
+    /// the packet type ID, sent as the first byte of the packet body
+    const TYPE_ID: u8;
+
+    /// declared body length, excluding the type byte
+    const BODY_LEN: BodyLength;
+
+    /// length of the body written by `encode_body`, excluding the type byte.
+    /// only bodies of variable length have to override it
+    fn body_len(&self) -> usize {
+        match Self::BODY_LEN {
+            BodyLength::Fixed(n) | BodyLength::AtLeast(n) => n,
+        }
+    }
+
+    /// append the body, excluding the type byte, to `out`
+    fn encode_body(&self, out: &mut Vec<u8>);
+
+    /// decode the body, excluding the type byte.
+    /// the length of `body` is already checked against `BODY_LEN`.
+    fn decode_body(body: &[u8]) -> Result<Self, Error>;
+}
+
+impl<T: Packet> Encode for T {
+    fn type_id(&self) -> u8 {
+        T::TYPE_ID
+    }
+
+    fn body_len(&self) -> usize {
+        Packet::body_len(self)
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        Packet::encode_body(self, out)
+    }
+}
+
+impl<T: Packet> Decode for T {
+    const BODY_LEN: BodyLength = <T as Packet>::BODY_LEN;
+
+    fn decode_body(body: &[u8]) -> Result<T, Error> {
+        <T as Packet>::decode_body(body)
+    }
+}
+
+/// decode a packet body with type `type_id`, checking the type byte and the declared body length
+pub fn decode<T: Decode>(type_id: u8, packet_body: &[u8]) -> Result<T, Error> {
+    match packet_body.split_first() {
+        Some((&body_type, body)) if body_type == type_id && T::BODY_LEN.matches(body.len()) => {
+            T::decode_body(body)
+        }
+        _ => Err(Error::ResponseMismatch),
+    }
+}
+
+/// common GPIO request type
+pub trait Request: Encode {
+    /// the response the remote controller answers this request with
+    type Response: Decode;
+
+    /// attempt to parse the response packet body into the response type
+    fn parse_response(&self, packet_body: &[u8]) -> Result<Self::Response, Error> {
+        decode(as_response_type!(self.type_id()), packet_body)
+    }
+}
+
 /// GPIO host controller implementation
 pub struct HostController {
-    port: Box<dyn SerialPort>,
+    port: Box<dyn Transport>,
     id: u8,
     read_timeout: Duration,
     max_retries: i32,
+
+    // reused between requests to avoid allocating per request
+    body_buf: Vec<u8>,
+    frame_buf: Vec<u8>,
+    decoder: sdsp::Decoder,
+
+    taps: Vec<Box<dyn FrameTap>>,
+    stats: stats::Stats,
+    policy: Option<policy::Policy>,
 }
 impl HostController {
     pub fn new(
-        port: Box<dyn SerialPort>,
+        port: Box<dyn Transport>,
         id: u8,
         read_timeout: Option<Duration>,
         max_retries: Option<i32>,
     ) -> HostController {
         HostController {
-            port: port,
+            port,
             id,
             read_timeout: read_timeout.unwrap_or(Duration::from_millis(100)),
             max_retries: max_retries.unwrap_or(2),
+            body_buf: Vec::with_capacity(64),
+            frame_buf: Vec::with_capacity(64),
+            decoder: sdsp::Decoder::new(),
+            taps: Vec::new(),
+            stats: stats::Stats::default(),
+            policy: None,
         }
     }
 
+    /// snapshot of the request counters and latencies since creation or the last `reset_stats()`
+    pub fn stats(&self) -> stats::Stats {
+        self.stats.clone()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = stats::Stats::default();
+    }
+
+    /// pass every frame sent and received from now on to `tap`
+    pub fn add_tap(&mut self, tap: Box<dyn FrameTap>) {
+        self.taps.push(tap);
+    }
+
+    /// refuse requests that violate `policy` from now on, without sending them
+    pub fn set_policy(&mut self, policy: policy::Policy) {
+        self.policy = Some(policy);
+    }
+
     /// send a request to the GPIO controller with id `recipient_id`, with automatic retries
-    pub fn send<ResponseType>(
+    pub fn send<R: Request + ?Sized>(
         &mut self,
-        request: &dyn Request<ResponseType>,
+        request: &R,
         recipient_id: u8,
-    ) -> Result<ResponseType, Error> {
-        let mut response: Result<ResponseType, Error>;
+    ) -> Result<R::Response, Error> {
+        let type_id = request.type_id();
+        let span = debug_span!("request", type_id, recipient_id);
+        let _enter = span.enter();
+
+        // check the request against the policy before anything is sent
+        if let Some(policy) = &self.policy {
+            self.body_buf.clear();
+            request.encode(&mut self.body_buf);
+            if let Err((pin, violation)) = policy.check(&self.body_buf) {
+                info!(pin, ?violation, "request refused by policy");
+                return Err(Error::PolicyViolation { pin, violation });
+            }
+        }
+        self.stats
+            .record(recipient_id, type_id, |c| c.requests += 1);
+
+        let mut response: Result<R::Response, Error>;
         let mut tries = 0;
         loop {
             // send the request and read the response
+            debug!(?request, attempt = tries + 1, "sending request");
             response = self.send_single(request, recipient_id);
 
             // retry only on certain errors
-            if let Err(error) = &response {
-                match error {
-                    Error::SDSPError { kind: _ } => {}
-                    Error::RemoteError { code: _ } => {}
-                    Error::HostError { code: _ } => {}
-                    Error::InvalidPin => {
-                        break;
-                    }
-                    Error::ResponseMismatch => {}
-                }
-            } else {
-                // no error
-                break;
-            }
+            let error = match &response {
+                Ok(_) | Err(Error::InvalidPin) => break,
+                Err(error) => error,
+            };
+            self.stats
+                .record(recipient_id, type_id, |c| c.record_error(error));
 
             // update retries
             tries += 1;
             if tries > self.max_retries {
+                debug!(?error, tries, "request failed, giving up");
                 break;
             }
 
             // wait a bit before retrying
+            info!(?error, retry = tries, "retrying request");
+            self.stats.record(recipient_id, type_id, |c| c.retries += 1);
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
-        return response;
+        match &response {
+            Ok(_) => self
+                .stats
+                .record(recipient_id, type_id, |c| c.successes += 1),
+            Err(_) => self
+                .stats
+                .record(recipient_id, type_id, |c| c.failures += 1),
+        }
+        response
     }
 
-    fn send_single<ResponseType>(
+    fn send_single<R: Request + ?Sized>(
         &mut self,
-        request: &dyn Request<ResponseType>,
+        request: &R,
         recipient_id: u8,
-    ) -> Result<ResponseType, Error> {
-        // build the packet
-        let pkg_body = request.get_packet_body();
-        let mut pkg = sdsp::Packet::new(self.id, recipient_id, pkg_body);
+    ) -> Result<R::Response, Error> {
+        // build the packet body
+        self.body_buf.clear();
+        request.encode(&mut self.body_buf);
+
+        // drop stale bytes, e.g. a late response to a previous request
+        let _ = self.port.clear_input();
 
         // send the packet using SDSP
-        let write_result = sdsp::write_packet(&mut self.port, &mut pkg);
-        if write_result.is_err() {
+        let write_result = sdsp::write_packet(
+            self.port.as_mut(),
+            &mut self.frame_buf,
+            self.id,
+            recipient_id,
+            &self.body_buf,
+        );
+        if let Err(e) = write_result {
+            debug!(error = %e, "failed to write frame");
             return Err(Error::RemoteError { code: 0xff });
         }
+        notify_taps(&mut self.taps, Direction::Sent, &self.frame_buf);
+        let sent_at = Instant::now();
 
         // read the response from the controller
-        let response_pkg = sdsp::read_packet(&mut self.port, self.id, self.read_timeout);
-        if let Err(kind) = response_pkg {
+        let read_result = sdsp::read_packet(
+            self.port.as_mut(),
+            &mut self.decoder,
+            self.id,
+            self.read_timeout,
+        )
+        .map(|_| ());
+
+        // a corrupt frame is still worth tapping, a partial one on timeout is not
+        if read_result != Err(sdsp::ReadError::Timeout) {
+            notify_taps(&mut self.taps, Direction::Received, self.decoder.frame());
+        }
+        if let Err(kind) = read_result {
             return Err(Error::SDSPError { kind });
         }
+        let latency = sent_at.elapsed();
+        self.stats.record(recipient_id, request.type_id(), |c| {
+            c.latency.record(latency)
+        });
+        let response_pkg_body = self.decoder.packet().body;
+        debug!(
+            ?latency,
+            body = %sdsp::Hex(response_pkg_body),
+            "response received"
+        );
 
         // parse the response
-        let response_pkg_body = response_pkg.unwrap().body;
-        let response = request.parse_response(&response_pkg_body);
+        let response = request.parse_response(response_pkg_body);
 
         // if response parsing failed, attempt to parse it as an error response
         if response.is_err() {
-            if let Ok(err) = error::ErrorRequest::new().parse_response(&response_pkg_body) {
-                return Err(err);
+            if let Ok(err) = error::ErrorResponse::parse(response_pkg_body) {
+                return Err(err.into());
             }
         }
 
-        return response;
+        response
+    }
+}
+
+fn notify_taps(taps: &mut [Box<dyn FrameTap>], direction: Direction, frame: &[u8]) {
+    if taps.is_empty() {
+        return;
+    }
+
+    let timestamp = SystemTime::now();
+    for tap in taps.iter_mut() {
+        tap.frame(direction, timestamp, frame);
     }
 }
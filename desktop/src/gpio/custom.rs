@@ -0,0 +1,499 @@
+use super::{
+    as_request_type, as_response_type, decode, describe::Field, raw::RawResponse, BodyLength,
+    Encode, Error, Request,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+//
+// User-defined commands
+//
+// custom firmware commands are described in a TOML file:
+//
+//   [[command]]
+//   name = "servo"
+//   about = "move a servo"
+//   type = 0x10
+//   request = [
+//       { name = "pin", type = "u8" },
+//       { name = "angle", type = "u16", endian = "little" },
+//       { name = "options", type = "flags", flags = { detach = 0, slow = 1 } },
+//   ]
+//   response = [
+//       { name = "position", type = "u16" },
+//   ]
+//
+// fields are encoded in order, big endian unless specified otherwise.
+// a `bytes` field takes all remaining bytes and must be the last field.
+//
+
+/// a user-defined command
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+    pub name: String,
+
+    #[serde(default)]
+    pub about: Option<String>,
+
+    /// the request type ID, without the response bit
+    #[serde(rename = "type")]
+    pub type_id: u8,
+
+    #[serde(default)]
+    pub request: Vec<FieldSpec>,
+
+    #[serde(default)]
+    pub response: Vec<FieldSpec>,
+}
+
+/// a field of a user-defined request or response
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+
+    #[serde(default)]
+    pub endian: Endian,
+
+    #[serde(default)]
+    pub help: Option<String>,
+
+    /// names and bit numbers of the flags in a `flags` field
+    #[serde(default)]
+    pub flags: BTreeMap<String, u8>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+
+    /// a single byte of named flag bits
+    Flags,
+
+    /// all remaining bytes
+    Bytes,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+/// the value of a field of a user-defined request or response.
+/// serializes as number, array of flag names, or array of bytes
+#[derive(PartialEq, Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+
+    /// the names of the flags that are set
+    Flags(Vec<String>),
+
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Flags(flags) if flags.is_empty() => write!(f, "none"),
+            Value::Flags(flags) => write!(f, "{}", flags.join(", ")),
+            Value::Bytes(bytes) => write!(f, "{:02x?}", bytes),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecFile {
+    #[serde(default, rename = "command")]
+    commands: Vec<CommandSpec>,
+}
+
+impl FieldKind {
+    /// the encoded width in bytes, or `None` for variable-length fields
+    pub fn width(&self) -> Option<usize> {
+        match self {
+            FieldKind::U8 | FieldKind::I8 | FieldKind::Flags => Some(1),
+            FieldKind::U16 | FieldKind::I16 => Some(2),
+            FieldKind::U32 | FieldKind::I32 => Some(4),
+            FieldKind::Bytes => None,
+        }
+    }
+
+    fn range(&self) -> Option<(i64, i64)> {
+        match self {
+            FieldKind::U8 => Some((0, u8::MAX as i64)),
+            FieldKind::U16 => Some((0, u16::MAX as i64)),
+            FieldKind::U32 => Some((0, u32::MAX as i64)),
+            FieldKind::I8 => Some((i8::MIN as i64, i8::MAX as i64)),
+            FieldKind::I16 => Some((i16::MIN as i64, i16::MAX as i64)),
+            FieldKind::I32 => Some((i32::MIN as i64, i32::MAX as i64)),
+            FieldKind::Flags | FieldKind::Bytes => None,
+        }
+    }
+
+    fn is_signed(&self) -> bool {
+        matches!(self, FieldKind::I8 | FieldKind::I16 | FieldKind::I32)
+    }
+}
+
+impl CommandSpec {
+    /// load all commands from a spec file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<CommandSpec>> {
+        CommandSpec::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// parse and validate all commands of a spec file
+    pub fn parse(text: &str) -> io::Result<Vec<CommandSpec>> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let file: SpecFile = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+        for (i, spec) in file.commands.iter().enumerate() {
+            spec.validate()
+                .map_err(|msg| invalid(format!("command '{}': {}", spec.name, msg)))?;
+
+            if file.commands[..i]
+                .iter()
+                .any(|other| other.name == spec.name)
+            {
+                return Err(invalid(format!("command '{}' is defined twice", spec.name)));
+            }
+            if let Some(other) = file.commands[..i]
+                .iter()
+                .find(|other| other.type_id == spec.type_id)
+            {
+                return Err(invalid(format!(
+                    "commands '{}' and '{}' use the same type {:#04x}",
+                    other.name, spec.name, spec.type_id
+                )));
+            }
+        }
+        Ok(file.commands)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.type_id & 0x80 != 0 {
+            return Err(format!(
+                "type {:#04x} has the response bit set",
+                self.type_id
+            ));
+        }
+        if super::describe::packet_type_name(self.type_id).is_some() {
+            return Err(format!(
+                "type {:#04x} is used by a built-in command",
+                self.type_id
+            ));
+        }
+        validate_fields(&self.request).map_err(|msg| format!("request {}", msg))?;
+        validate_fields(&self.response).map_err(|msg| format!("response {}", msg))
+    }
+
+    /// declared length of the request body, excluding the type byte
+    pub fn request_len(&self) -> BodyLength {
+        body_length(&self.request)
+    }
+
+    /// declared length of the response body, excluding the type byte
+    pub fn response_len(&self) -> BodyLength {
+        body_length(&self.response)
+    }
+
+    /// build a request from one value per request field, checking every value against its field
+    pub fn request(&self, values: &[Value]) -> Result<CustomRequest, String> {
+        if values.len() != self.request.len() {
+            return Err(format!(
+                "expected {} values, got {}",
+                self.request.len(),
+                values.len()
+            ));
+        }
+
+        let mut body = Vec::new();
+        for (field, value) in self.request.iter().zip(values) {
+            encode_field(field, value, &mut body)
+                .map_err(|msg| format!("{}: {}", field.name, msg))?;
+        }
+        Ok(CustomRequest {
+            name: self.name.clone(),
+            type_id: self.type_id,
+            body,
+            response_len: self.response_len(),
+        })
+    }
+
+    /// decode a request packet body, including the type byte
+    pub fn decode_request(&self, packet_body: &[u8]) -> Result<Vec<(String, Value)>, Error> {
+        decode_fields(
+            &self.request,
+            as_request_type!(self.type_id),
+            self.request_len(),
+            packet_body,
+        )
+    }
+
+    /// decode a response packet body, including the type byte
+    pub fn decode_response(&self, packet_body: &[u8]) -> Result<Vec<(String, Value)>, Error> {
+        decode_fields(
+            &self.response,
+            as_response_type!(self.type_id),
+            self.response_len(),
+            packet_body,
+        )
+    }
+
+    /// check if a packet type is the request or response type of this command
+    pub fn is_packet_type(&self, packet_type: u8) -> bool {
+        packet_type == as_request_type!(self.type_id)
+            || packet_type == as_response_type!(self.type_id)
+    }
+
+    /// decode the response to a request of this command
+    pub fn response_values(&self, response: &RawResponse) -> Result<Vec<(String, Value)>, Error> {
+        let mut packet_body = vec![as_response_type!(self.type_id)];
+        packet_body.extend_from_slice(&response.body);
+        self.decode_response(&packet_body)
+    }
+
+    /// describe a request or response packet body of this command in human-readable form,
+    /// e.g. `servo request: pin = 9, angle = 90`
+    pub fn describe(&self, packet_body: &[u8]) -> String {
+        let (kind, decoded) = match packet_body.first() {
+            Some(&t) if t == as_response_type!(self.type_id) => {
+                ("response", self.decode_response(packet_body))
+            }
+            _ => ("request", self.decode_request(packet_body)),
+        };
+        match decoded {
+            Ok(values) if values.is_empty() => format!("{} {}", self.name, kind),
+            Ok(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                format!("{} {}: {}", self.name, kind, values.join(", "))
+            }
+            Err(_) => format!("malformed {} {} {:02x?}", self.name, kind, packet_body),
+        }
+    }
+
+    /// explain every field of a request or response packet body of this command,
+    /// like `describe::explain` does for built-in commands
+    pub fn explain(&self, packet_body: &[u8]) -> Vec<Field> {
+        let Some(&packet_type) = packet_body.first() else {
+            return Vec::new();
+        };
+        let (kind, fields, decoded) = if packet_type == as_response_type!(self.type_id) {
+            (
+                "response",
+                &self.response,
+                self.decode_response(packet_body),
+            )
+        } else {
+            ("request", &self.request, self.decode_request(packet_body))
+        };
+
+        let mut explained = vec![Field::new("type", 0..1, format!("{} {}", self.name, kind))];
+        match decoded {
+            Ok(values) => {
+                let mut pos = 1;
+                for (field, (_, value)) in fields.iter().zip(values) {
+                    let width = field.kind.width().unwrap_or(packet_body.len() - pos);
+                    explained.push(Field::new(&field.name, pos..pos + width, value.to_string()));
+                    pos += width;
+                }
+            }
+            Err(_) => explained.push(Field::new(
+                "body",
+                1..packet_body.len(),
+                format!("malformed, expected {:?} bytes", body_length(fields)),
+            )),
+        }
+        explained
+    }
+}
+
+/// a request built from a `CommandSpec`
+#[derive(PartialEq, Debug)]
+pub struct CustomRequest {
+    pub name: String,
+    pub type_id: u8,
+    pub body: Vec<u8>,
+    response_len: BodyLength,
+}
+
+impl Encode for CustomRequest {
+    fn type_id(&self) -> u8 {
+        as_request_type!(self.type_id)
+    }
+
+    fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.body); // FIELDS
+    }
+}
+
+impl Request for CustomRequest {
+    type Response = RawResponse;
+
+    fn parse_response(&self, packet_body: &[u8]) -> Result<RawResponse, Error> {
+        // the declared length is only known at runtime, so check it here
+        let response: RawResponse = decode(as_response_type!(self.type_id), packet_body)?;
+        if !self.response_len.matches(response.body.len()) {
+            return Err(Error::ResponseMismatch);
+        }
+        Ok(response)
+    }
+}
+
+//
+// Internal Functions
+//
+
+fn validate_fields(fields: &[FieldSpec]) -> Result<(), String> {
+    let mut flags = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        if fields[..i].iter().any(|other| other.name == field.name) {
+            return Err(format!("field '{}' is defined twice", field.name));
+        }
+        if field.kind == FieldKind::Bytes && i + 1 != fields.len() {
+            return Err(format!(
+                "field '{}': bytes fields must be the last field",
+                field.name
+            ));
+        }
+        if field.kind == FieldKind::Flags {
+            if let Some((flag, bit)) = field.flags.iter().find(|(_, &bit)| bit > 7) {
+                return Err(format!(
+                    "field '{}': flag '{}' uses bit {}, but flags have bits 0 to 7",
+                    field.name, flag, bit
+                ));
+            }
+            // flags become command line options, so they must be unique per command
+            for flag in field.flags.keys() {
+                if flags.contains(&flag) {
+                    return Err(format!("flag '{}' is defined twice", flag));
+                }
+                flags.push(flag);
+            }
+        } else if !field.flags.is_empty() {
+            return Err(format!(
+                "field '{}': only flags fields can have flags",
+                field.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn body_length(fields: &[FieldSpec]) -> BodyLength {
+    let fixed = fields.iter().filter_map(|field| field.kind.width()).sum();
+    if fields.iter().any(|field| field.kind.width().is_none()) {
+        BodyLength::AtLeast(fixed)
+    } else {
+        BodyLength::Fixed(fixed)
+    }
+}
+
+fn encode_field(field: &FieldSpec, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    match (field.kind, value) {
+        (FieldKind::Flags, Value::Flags(names)) => {
+            let mut flags = 0u8;
+            for name in names {
+                let bit = field
+                    .flags
+                    .get(name)
+                    .ok_or_else(|| format!("unknown flag '{}'", name))?;
+                flags |= 1 << bit;
+            }
+            out.push(flags);
+        }
+        (FieldKind::Bytes, Value::Bytes(bytes)) => out.extend_from_slice(bytes),
+        (kind, Value::Int(value)) if kind.range().is_some() => {
+            let (min, max) = kind.range().unwrap();
+            if *value < min || *value > max {
+                return Err(format!("{} is out of range {}..={}", value, min, max));
+            }
+
+            let width = kind.width().unwrap();
+            let bytes = value.to_be_bytes();
+            let mut bytes = bytes[8 - width..].to_vec();
+            if field.endian == Endian::Little {
+                bytes.reverse();
+            }
+            out.extend_from_slice(&bytes);
+        }
+        (kind, value) => return Err(format!("{:?} is not a valid {:?} value", value, kind)),
+    }
+    Ok(())
+}
+
+fn decode_fields(
+    fields: &[FieldSpec],
+    type_id: u8,
+    body_len: BodyLength,
+    packet_body: &[u8],
+) -> Result<Vec<(String, Value)>, Error> {
+    // check the type and length like built-in packets
+    match packet_body.split_first() {
+        Some((&body_type, body)) if body_type == type_id && body_len.matches(body.len()) => {}
+        _ => return Err(Error::ResponseMismatch),
+    }
+
+    let mut values = Vec::new();
+    let mut pos = 1;
+    for field in fields {
+        let width = field.kind.width().unwrap_or(packet_body.len() - pos);
+        let bytes = &packet_body[pos..pos + width];
+        pos += width;
+
+        let value = match field.kind {
+            FieldKind::Flags => Value::Flags(
+                field
+                    .flags
+                    .iter()
+                    .filter(|(_, &bit)| bytes[0] & (1 << bit) != 0)
+                    .map(|(name, _)| name.clone())
+                    .collect(),
+            ),
+            FieldKind::Bytes => Value::Bytes(bytes.to_vec()),
+            kind => {
+                let mut be = bytes.to_vec();
+                if field.endian == Endian::Little {
+                    be.reverse();
+                }
+
+                // sign-extend signed values
+                let fill = if kind.is_signed() && be[0] & 0x80 != 0 {
+                    0xff
+                } else {
+                    0x00
+                };
+                let mut wide = [fill; 8];
+                wide[8 - width..].copy_from_slice(&be);
+                Value::Int(i64::from_be_bytes(wide))
+            }
+        };
+        values.push((field.name.clone(), value));
+    }
+    Ok(values)
+}
@@ -0,0 +1,349 @@
+use super::{
+    as_request_type, as_response_type, decode,
+    echo::{EchoRequest, EchoResponse, TYPE_ECHO},
+    error::{ErrorResponse, ERR_INVALID_PIN, ERR_INVALID_TYPE, ERR_MALFORMED_PACKET, TYPE_ERROR},
+    iic::write::{IICResultCode, IICWriteRequest, IICWriteResponse, TYPE_IIC_WRITE},
+    read::{ReadRequest, ReadResponse, TYPE_READ},
+    toggle::{ToggleRequest, ToggleResponse, TYPE_TOGGLE},
+    write::{WriteRequest, WriteResponse, TYPE_WRITE},
+    BodyLength, Decode, Encode,
+};
+use std::ops::Range;
+
+//
+// Human-readable packet bodies
+//
+
+/// describe a request or response packet body in human-readable form,
+/// e.g. `read digital pin 13 (pullup)` or `error: invalid pin`
+pub fn describe(packet_body: &[u8]) -> String {
+    let Some(&packet_type) = packet_body.first() else {
+        return "empty packet".to_string();
+    };
+
+    match packet_type {
+        t if t == as_request_type!(TYPE_READ) => describe_as(t, packet_body, |r: ReadRequest| {
+            let mut flags = Vec::new();
+            for (set, name) in [
+                (r.pullup, "pullup"),
+                (r.pulldown, "pulldown"),
+                (r.invert, "inverted"),
+                (r.direct, "direct"),
+            ] {
+                if set {
+                    flags.push(name);
+                }
+            }
+            format!(
+                "read {} pin {}{}",
+                analog_or_digital(r.analog),
+                r.pin,
+                describe_flags(&flags)
+            )
+        }),
+        t if t == as_request_type!(TYPE_WRITE) => describe_as(t, packet_body, |r: WriteRequest| {
+            let flags: &[&str] = if r.invert { &["inverted"] } else { &[] };
+            format!(
+                "write {} pin {} = {}{}",
+                analog_or_digital(r.analog),
+                r.pin,
+                r.value,
+                describe_flags(flags)
+            )
+        }),
+        t if t == as_request_type!(TYPE_TOGGLE) => {
+            describe_as(t, packet_body, |r: ToggleRequest| {
+                format!("toggle pin {}", r.pin)
+            })
+        }
+        t if t == as_request_type!(TYPE_IIC_WRITE) => {
+            describe_as(t, packet_body, |r: IICWriteRequest| {
+                let flags: &[&str] = if r.stop { &["stop"] } else { &[] };
+                format!(
+                    "i2c write to {:#04x}: {:02x?}{}",
+                    r.address,
+                    r.data,
+                    describe_flags(flags)
+                )
+            })
+        }
+        t if t == as_request_type!(TYPE_ECHO) => describe_as(t, packet_body, |r: EchoRequest| {
+            format!("echo {} bytes", r.data.len())
+        }),
+        t if t == as_response_type!(TYPE_READ) => describe_as(t, packet_body, |r: ReadResponse| {
+            format!("read response: value {}", r.value)
+        }),
+        t if t == as_response_type!(TYPE_WRITE) => {
+            describe_as(t, packet_body, |_: WriteResponse| {
+                "write response: ok".to_string()
+            })
+        }
+        t if t == as_response_type!(TYPE_TOGGLE) => {
+            describe_as(t, packet_body, |r: ToggleResponse| {
+                format!("toggle response: new value {}", r.new_value)
+            })
+        }
+        t if t == as_response_type!(TYPE_IIC_WRITE) => {
+            describe_as(t, packet_body, |r: IICWriteResponse| {
+                format!(
+                    "i2c write response: {}",
+                    describe_result_code(&r.result_code)
+                )
+            })
+        }
+        t if t == as_response_type!(TYPE_ECHO) => describe_as(t, packet_body, |r: EchoResponse| {
+            format!("echo response: {} bytes", r.data.len())
+        }),
+        t if t == as_response_type!(TYPE_ERROR) => {
+            describe_as(t, packet_body, |r: ErrorResponse| {
+                format!("error: {}", describe_error_code(r.code))
+            })
+        }
+        _ => format!(
+            "unknown packet type {:#04x} {:02x?}",
+            packet_type, packet_body
+        ),
+    }
+}
+
+/// describe an error code of an error response
+pub fn describe_error_code(code: u8) -> String {
+    match code {
+        ERR_MALFORMED_PACKET => "malformed packet".to_string(),
+        ERR_INVALID_TYPE => "invalid packet type".to_string(),
+        ERR_INVALID_PIN => "invalid pin".to_string(),
+        _ => format!("unknown error {:#04x}", code),
+    }
+}
+
+/// describe the result code of an i2c write
+pub fn describe_result_code(result_code: &IICResultCode) -> String {
+    match result_code {
+        IICResultCode::Success => "success".to_string(),
+        IICResultCode::DataTooLong => "data too long".to_string(),
+        IICResultCode::NACKOnAddress => "NACK on address".to_string(),
+        IICResultCode::NACKOnData => "NACK on data".to_string(),
+        IICResultCode::Other => "other error".to_string(),
+        IICResultCode::Timeout => "timeout".to_string(),
+        IICResultCode::Unknown { result_code } => format!("unknown result {:#04x}", result_code),
+    }
+}
+
+fn describe_as<T: Decode>(
+    type_id: u8,
+    packet_body: &[u8],
+    describe: impl FnOnce(T) -> String,
+) -> String {
+    match decode::<T>(type_id, packet_body) {
+        Ok(decoded) => describe(decoded),
+        Err(_) => format!("malformed packet {:02x?}", packet_body),
+    }
+}
+
+fn analog_or_digital(analog: bool) -> &'static str {
+    if analog {
+        "analog"
+    } else {
+        "digital"
+    }
+}
+
+fn describe_flags(flags: &[&str]) -> String {
+    if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", flags.join(", "))
+    }
+}
+
+//
+// Field-by-field explanation of packet bodies
+//
+
+/// a single field of a packet body
+#[derive(PartialEq, Debug, Clone)]
+pub struct Field {
+    pub name: String,
+
+    /// position of the field in the packet body.
+    /// empty for notes that are not about specific bytes.
+    pub range: Range<usize>,
+
+    /// the decoded value of the field
+    pub value: String,
+}
+
+impl Field {
+    pub fn new(name: &str, range: Range<usize>, value: String) -> Field {
+        Field {
+            name: name.to_string(),
+            range,
+            value,
+        }
+    }
+}
+
+/// name of a packet type, e.g. `read request`
+pub fn packet_type_name(packet_type: u8) -> Option<&'static str> {
+    match packet_type {
+        t if t == as_request_type!(TYPE_READ) => Some("read request"),
+        t if t == as_request_type!(TYPE_WRITE) => Some("write request"),
+        t if t == as_request_type!(TYPE_TOGGLE) => Some("toggle request"),
+        t if t == as_request_type!(TYPE_IIC_WRITE) => Some("i2c write request"),
+        t if t == as_request_type!(TYPE_ECHO) => Some("echo request"),
+        t if t == as_response_type!(TYPE_READ) => Some("read response"),
+        t if t == as_response_type!(TYPE_WRITE) => Some("write response"),
+        t if t == as_response_type!(TYPE_TOGGLE) => Some("toggle response"),
+        t if t == as_response_type!(TYPE_IIC_WRITE) => Some("i2c write response"),
+        t if t == as_response_type!(TYPE_ECHO) => Some("echo response"),
+        t if t == as_response_type!(TYPE_ERROR) => Some("error response"),
+        _ => None,
+    }
+}
+
+/// explain every field of a request or response packet body.
+/// the body is decoded with the same decoders the host controller uses, and
+/// bytes that cannot be decoded are explained as such.
+pub fn explain(packet_body: &[u8]) -> Vec<Field> {
+    let Some(&packet_type) = packet_body.first() else {
+        return Vec::new();
+    };
+
+    let type_name = packet_type_name(packet_type)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("unknown ({:#04x})", packet_type));
+    let mut fields = vec![Field::new("type", 0..1, type_name)];
+
+    let body_fields = match packet_type {
+        t if t == as_request_type!(TYPE_READ) => explain_as(t, packet_body, |r: ReadRequest| {
+            let mut flags = vec![analog_or_digital(r.analog)];
+            for (set, name) in [
+                (r.pullup, "pullup"),
+                (r.pulldown, "pulldown"),
+                (r.invert, "inverted"),
+                (r.direct, "direct"),
+            ] {
+                if set {
+                    flags.push(name);
+                }
+            }
+            vec![
+                Field::new("pin", 1..2, r.pin.to_string()),
+                Field::new("flags", 2..3, flags.join(", ")),
+            ]
+        }),
+        t if t == as_request_type!(TYPE_WRITE) => explain_as(t, packet_body, |r: WriteRequest| {
+            let mut flags = vec![analog_or_digital(r.analog)];
+            if r.invert {
+                flags.push("inverted");
+            }
+            vec![
+                Field::new("pin", 1..2, r.pin.to_string()),
+                Field::new("value", 2..4, r.value.to_string()),
+                Field::new("flags", 4..5, flags.join(", ")),
+            ]
+        }),
+        t if t == as_request_type!(TYPE_TOGGLE) => {
+            explain_as(t, packet_body, |r: ToggleRequest| {
+                vec![Field::new("pin", 1..2, r.pin.to_string())]
+            })
+        }
+        t if t == as_request_type!(TYPE_IIC_WRITE) => {
+            explain_as(t, packet_body, |r: IICWriteRequest| {
+                let flags = if r.stop { "stop" } else { "none" };
+                let mut fields = vec![
+                    Field::new("address", 1..2, format!("{:#04x}", r.address)),
+                    Field::new("flags", 2..3, flags.to_string()),
+                ];
+                if !r.data.is_empty() {
+                    let data_len = r.data.len();
+                    fields.push(Field::new(
+                        "data",
+                        3..3 + data_len,
+                        format!("{} bytes", data_len),
+                    ));
+                }
+                fields
+            })
+        }
+        t if t == as_request_type!(TYPE_ECHO) => {
+            explain_as(t, packet_body, |r: EchoRequest| explain_echo_data(&r.data))
+        }
+        t if t == as_response_type!(TYPE_READ) => explain_as(t, packet_body, |r: ReadResponse| {
+            vec![Field::new("value", 1..3, r.value.to_string())]
+        }),
+        t if t == as_response_type!(TYPE_WRITE) => {
+            explain_as(t, packet_body, |_: WriteResponse| Vec::new())
+        }
+        t if t == as_response_type!(TYPE_TOGGLE) => {
+            explain_as(t, packet_body, |r: ToggleResponse| {
+                vec![Field::new("new value", 1..2, r.new_value.to_string())]
+            })
+        }
+        t if t == as_response_type!(TYPE_IIC_WRITE) => {
+            explain_as(t, packet_body, |r: IICWriteResponse| {
+                vec![Field::new(
+                    "result",
+                    1..2,
+                    describe_result_code(&r.result_code),
+                )]
+            })
+        }
+        t if t == as_response_type!(TYPE_ECHO) => {
+            explain_as(t, packet_body, |r: EchoResponse| explain_echo_data(&r.data))
+        }
+        t if t == as_response_type!(TYPE_ERROR) => {
+            explain_as(t, packet_body, |r: ErrorResponse| {
+                vec![Field::new("error code", 1..2, describe_error_code(r.code))]
+            })
+        }
+        _ => Err("unknown packet type".to_string()),
+    };
+
+    match body_fields {
+        Ok(body_fields) => fields.extend(body_fields),
+        Err(reason) if packet_body.len() > 1 => {
+            fields.push(Field::new("body", 1..packet_body.len(), reason))
+        }
+        Err(reason) => fields[0].value = format!("{} ({})", fields[0].value, reason),
+    }
+    fields
+}
+
+fn explain_echo_data(data: &[u8]) -> Vec<Field> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    vec![Field::new(
+        "data",
+        1..1 + data.len(),
+        format!("{} bytes", data.len()),
+    )]
+}
+
+fn explain_as<T: Decode + Encode>(
+    type_id: u8,
+    packet_body: &[u8],
+    explain: impl FnOnce(T) -> Vec<Field>,
+) -> Result<Vec<Field>, String> {
+    let decoded = decode::<T>(type_id, packet_body).map_err(|_| match T::BODY_LEN {
+        BodyLength::Fixed(n) => format!("malformed, expected {} bytes after the type", n),
+        BodyLength::AtLeast(n) => {
+            format!("malformed, expected at least {} bytes after the type", n)
+        }
+    })?;
+
+    // encoding the decoded packet again must give the same bytes, otherwise
+    // the packet sets bits this host does not know about
+    let mut encoded = Vec::new();
+    decoded.encode(&mut encoded);
+    let mut fields = explain(decoded);
+    if encoded != packet_body {
+        fields.push(Field::new(
+            "note",
+            0..0,
+            format!("non-canonical encoding, expected {:02x?}", encoded),
+        ));
+    }
+    Ok(fields)
+}
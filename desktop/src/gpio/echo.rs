@@ -0,0 +1,69 @@
+use super::{as_request_type, as_response_type, BodyLength, Error, Packet, Request};
+
+//
+// Echo Request Constants
+//
+pub const TYPE_ECHO: u8 = 0x05;
+
+//
+// Echo Request Implementation
+//
+
+/// request that the remote controller answers with the same data, for testing the link
+#[derive(PartialEq, Debug)]
+pub struct EchoRequest {
+    pub data: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct EchoResponse {
+    pub data: Vec<u8>,
+}
+
+impl EchoRequest {
+    pub fn new(data: Vec<u8>) -> EchoRequest {
+        EchoRequest { data }
+    }
+}
+
+impl Packet for EchoRequest {
+    const TYPE_ID: u8 = as_request_type!(TYPE_ECHO);
+    const BODY_LEN: BodyLength = BodyLength::AtLeast(0);
+
+    fn body_len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data); // DATA
+    }
+
+    fn decode_body(body: &[u8]) -> Result<EchoRequest, Error> {
+        Ok(EchoRequest {
+            data: body.to_vec(),
+        })
+    }
+}
+
+impl Request for EchoRequest {
+    type Response = EchoResponse;
+}
+
+impl Packet for EchoResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_ECHO);
+    const BODY_LEN: BodyLength = BodyLength::AtLeast(0);
+
+    fn body_len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data); // DATA
+    }
+
+    fn decode_body(body: &[u8]) -> Result<EchoResponse, Error> {
+        Ok(EchoResponse {
+            data: body.to_vec(),
+        })
+    }
+}
@@ -1,46 +1,46 @@
-use super::{as_response_type, Error, Request};
+use super::{as_response_type, decode, BodyLength, Error, Packet};
 
 //
 // Error Response Constants
 //
-const TYPE_ERROR: u8 = 0x7f;
+pub const TYPE_ERROR: u8 = 0x7f;
 
-//const ERR_MALFORMED_PACKET: u8 = 0x01;
-//const ERR_INVALID_TYPE: u8 = 0x02;
-const ERR_INVALID_PIN: u8 = 0x03;
+pub const ERR_MALFORMED_PACKET: u8 = 0x01;
+pub const ERR_INVALID_TYPE: u8 = 0x02;
+pub const ERR_INVALID_PIN: u8 = 0x03;
 
 //
 // Error Response Implementation
 //
-#[derive(Debug)]
-pub struct ErrorRequest {}
-impl ErrorRequest {
-    pub fn new() -> ErrorRequest {
-        ErrorRequest {}
+#[derive(PartialEq, Debug)]
+pub struct ErrorResponse {
+    pub code: u8,
+}
+impl ErrorResponse {
+    /// attempt to parse a packet body as an error response
+    pub fn parse(packet_body: &[u8]) -> Result<ErrorResponse, Error> {
+        decode(as_response_type!(TYPE_ERROR), packet_body)
     }
 }
 
-impl Request<Error> for ErrorRequest {
-    fn get_packet_body(&self) -> Vec<u8> {
-        panic!("ErrorRequest is parse only")
-    }
+impl Packet for ErrorResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_ERROR);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(1);
 
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<Error, Error> {
-        // ensure length is correct
-        if packet_body.len() != 2 {
-            return Err(Error::ResponseMismatch);
-        }
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.code); // ERROR CODE
+    }
 
-        // ensure type is error response
-        if packet_body[0] != as_response_type!(TYPE_ERROR) {
-            return Err(Error::ResponseMismatch);
-        }
+    fn decode_body(body: &[u8]) -> Result<ErrorResponse, Error> {
+        Ok(ErrorResponse { code: body[0] })
+    }
+}
 
-        // check error code and create OK Error
-        let error_code = packet_body[1];
-        match error_code {
-            ERR_INVALID_PIN => return Ok(Error::InvalidPin),
-            _ => return Ok(Error::RemoteError { code: error_code }),
+impl From<ErrorResponse> for Error {
+    fn from(response: ErrorResponse) -> Error {
+        match response.code {
+            ERR_INVALID_PIN => Error::InvalidPin,
+            code => Error::RemoteError { code },
         }
     }
 }
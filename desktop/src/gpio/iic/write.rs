@@ -1,9 +1,9 @@
-use crate::gpio::{as_request_type, as_response_type, Error, Request};
+use crate::gpio::{as_request_type, as_response_type, BodyLength, Error, Packet, Request};
 
 //
 // IIC Write Request Constants
 //
-const TYPE_IIC_WRITE: u8 = 0x04;
+pub const TYPE_IIC_WRITE: u8 = 0x04;
 
 const FLAG_IIC_STOP: u8 = 1 << 0;
 
@@ -17,19 +17,19 @@ const RESULT_CODE_TIMEOUT: u8 = 0x05;
 //
 // IIC Write Request Implementation
 //
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct IICWriteRequest {
     pub address: u8,
     pub data: Vec<u8>,
     pub stop: bool,
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct IICWriteResponse {
     pub result_code: IICResultCode,
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub enum IICResultCode {
     Success,
     DataTooLong,
@@ -40,6 +40,21 @@ pub enum IICResultCode {
     Unknown { result_code: u8 },
 }
 
+impl IICResultCode {
+    /// the raw result code, as returned by `Wire.endTransmission()`
+    pub fn code(&self) -> u8 {
+        match self {
+            IICResultCode::Success => RESULT_CODE_SUCCESS,
+            IICResultCode::DataTooLong => RESULT_CODE_DATA_TOO_LONG,
+            IICResultCode::NACKOnAddress => RESULT_CODE_NACK_ON_ADDRESS,
+            IICResultCode::NACKOnData => RESULT_CODE_NACK_ON_DATA,
+            IICResultCode::Other => RESULT_CODE_OTHER,
+            IICResultCode::Timeout => RESULT_CODE_TIMEOUT,
+            IICResultCode::Unknown { result_code } => *result_code,
+        }
+    }
+}
+
 impl IICWriteRequest {
     pub fn new(address: u8, data: Vec<u8>, stop: bool) -> IICWriteRequest {
         IICWriteRequest {
@@ -50,46 +65,56 @@ impl IICWriteRequest {
     }
 }
 
-impl Request<IICWriteResponse> for IICWriteRequest {
-    fn get_packet_body(&self) -> Vec<u8> {
+impl Packet for IICWriteRequest {
+    const TYPE_ID: u8 = as_request_type!(TYPE_IIC_WRITE);
+    const BODY_LEN: BodyLength = BodyLength::AtLeast(2);
+
+    fn body_len(&self) -> usize {
+        2 + self.data.len()
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
         // set flags
         let mut flags = 0;
         if self.stop {
             flags |= FLAG_IIC_STOP;
         }
 
-        // assemble packet body
-        let mut body = vec![
-            as_request_type!(TYPE_IIC_WRITE), //TYPE
-            self.address,                     // ADDRESS
-            flags,                            // FLAGS
-        ];
-        body.extend_from_slice(&self.data); // DATA
-        return body;
+        out.push(self.address); // ADDRESS
+        out.push(flags); // FLAGS
+        out.extend_from_slice(&self.data); // DATA
     }
 
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<IICWriteResponse, Error> {
-        // ensure length is correct
-        if packet_body.len() != 2 {
-            return Err(Error::ResponseMismatch);
-        }
+    fn decode_body(body: &[u8]) -> Result<IICWriteRequest, Error> {
+        Ok(IICWriteRequest {
+            address: body[0],
+            stop: body[1] & FLAG_IIC_STOP != 0,
+            data: body[2..].to_vec(),
+        })
+    }
+}
 
-        // ensure type is iic write response
-        if packet_body[0] != as_response_type!(TYPE_IIC_WRITE) {
-            return Err(Error::ResponseMismatch);
-        }
+impl Request for IICWriteRequest {
+    type Response = IICWriteResponse;
+}
 
-        // parse result code
-        let result_code_raw = packet_body[1];
-        let result_code = parse_result_code(result_code_raw);
+impl Packet for IICWriteResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_IIC_WRITE);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(1);
 
-        // parse response
-        return Ok(IICWriteResponse { result_code });
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.result_code.code()); // RESULT
+    }
+
+    fn decode_body(body: &[u8]) -> Result<IICWriteResponse, Error> {
+        Ok(IICWriteResponse {
+            result_code: parse_result_code(body[0]),
+        })
     }
 }
 
-fn parse_result_code(result_code: u8) -> IICResultCode {
-    return match result_code {
+pub(crate) fn parse_result_code(result_code: u8) -> IICResultCode {
+    match result_code {
         RESULT_CODE_SUCCESS => IICResultCode::Success,
         RESULT_CODE_DATA_TOO_LONG => IICResultCode::DataTooLong,
         RESULT_CODE_NACK_ON_ADDRESS => IICResultCode::NACKOnAddress,
@@ -97,5 +122,5 @@ fn parse_result_code(result_code: u8) -> IICResultCode {
         RESULT_CODE_OTHER => IICResultCode::Other,
         RESULT_CODE_TIMEOUT => IICResultCode::Timeout,
         _ => IICResultCode::Unknown { result_code },
-    };
+    }
 }
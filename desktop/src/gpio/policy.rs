@@ -0,0 +1,179 @@
+use super::{
+    decode, read::ReadRequest, read::TYPE_READ, toggle::ToggleRequest, toggle::TYPE_TOGGLE,
+    write::WriteRequest, write::TYPE_WRITE,
+};
+use crate::config::{parse_pin, Board};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+//
+// Pin access policy
+//
+// pins are restricted in a TOML file, by pin number or analog pin name:
+//
+//   [pins.7]
+//   access = "output"         # input, output, forbidden or any
+//   max = 1
+//
+//   [pins.A0]
+//   access = "input"
+//
+// pins that are not listed can be used freely.
+//
+
+/// what a pin may be used for
+#[derive(PartialEq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    #[default]
+    Any,
+
+    /// only reads; writes and toggles are refused
+    Input,
+
+    /// only writes, toggles and reads with `direct`, which do not change the pin mode
+    Output,
+
+    /// no requests at all
+    Forbidden,
+}
+
+/// the restrictions of one pin
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinPolicy {
+    #[serde(default)]
+    pub access: Access,
+
+    /// the smallest value that may be written, as driven on the pin after inversion
+    #[serde(default)]
+    pub min: Option<u16>,
+
+    /// the largest value that may be written, as driven on the pin after inversion
+    #[serde(default)]
+    pub max: Option<u16>,
+}
+
+/// why a request was refused
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Violation {
+    /// the pin must not be used
+    Forbidden,
+
+    /// the pin is input-only, but the request drives it
+    InputOnly,
+
+    /// the pin is output-only, but the request would make it an input
+    OutputOnly,
+
+    /// the value is outside of the allowed range
+    OutOfRange { value: u16, min: u16, max: u16 },
+}
+
+/// restrictions for every pin of a controller, checked before a request is sent
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Policy {
+    pub pins: BTreeMap<u8, PinPolicy>,
+}
+
+impl Policy {
+    /// load a policy file. analog pin names are resolved for `board`
+    pub fn load(path: impl AsRef<Path>, board: Option<Board>) -> io::Result<Policy> {
+        Policy::parse(&std::fs::read_to_string(path)?, board)
+    }
+
+    /// parse a policy file. analog pin names are resolved for `board`
+    pub fn parse(text: &str, board: Option<Board>) -> io::Result<Policy> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct PolicyFile {
+            #[serde(default)]
+            pins: BTreeMap<String, PinPolicy>,
+        }
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let file: PolicyFile = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+        let mut policy = Policy::default();
+        for (name, pin) in file.pins {
+            let number = parse_pin(&name, board).map_err(invalid)?;
+            if let (Some(min), Some(max)) = (pin.min, pin.max) {
+                if min > max {
+                    return Err(invalid(format!("pin '{}': min is larger than max", name)));
+                }
+            }
+            if policy.pins.insert(number, pin).is_some() {
+                return Err(invalid(format!("pin {} is listed twice", number)));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// check an encoded request packet body against the policy.
+    /// returns the pin and the violation if the request must not be sent.
+    /// only read, write and toggle requests are checked.
+    pub fn check(&self, packet_body: &[u8]) -> Result<(), (u8, Violation)> {
+        let Some(&type_id) = packet_body.first() else {
+            return Ok(());
+        };
+        match type_id {
+            TYPE_READ => match decode::<ReadRequest>(type_id, packet_body) {
+                Ok(request) => self.check_read(request.pin, request.direct),
+                Err(_) => Ok(()),
+            },
+            TYPE_WRITE => match decode::<WriteRequest>(type_id, packet_body) {
+                Ok(request) => self.check_write(request.pin, &[driven_value(&request)]),
+                Err(_) => Ok(()),
+            },
+            TYPE_TOGGLE => match decode::<ToggleRequest>(type_id, packet_body) {
+                // a toggle may write either value
+                Ok(request) => self.check_write(request.pin, &[0, 1]),
+                Err(_) => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn check_read(&self, pin: u8, direct: bool) -> Result<(), (u8, Violation)> {
+        let Some(policy) = self.pins.get(&pin) else {
+            return Ok(());
+        };
+        match policy.access {
+            Access::Forbidden => Err((pin, Violation::Forbidden)),
+
+            // a read that is not direct sets the pin mode to input
+            Access::Output if !direct => Err((pin, Violation::OutputOnly)),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_write(&self, pin: u8, values: &[u16]) -> Result<(), (u8, Violation)> {
+        let Some(policy) = self.pins.get(&pin) else {
+            return Ok(());
+        };
+        match policy.access {
+            Access::Forbidden => return Err((pin, Violation::Forbidden)),
+            Access::Input => return Err((pin, Violation::InputOnly)),
+            _ => {}
+        }
+
+        let min = policy.min.unwrap_or(u16::MIN);
+        let max = policy.max.unwrap_or(u16::MAX);
+        match values.iter().find(|value| !(min..=max).contains(value)) {
+            Some(&value) => Err((pin, Violation::OutOfRange { value, min, max })),
+            None => Ok(()),
+        }
+    }
+}
+
+/// the value the controller drives on the pin for a write, after inverting it like the firmware.
+/// digital writes drive 0 or 1
+fn driven_value(request: &WriteRequest) -> u16 {
+    match (request.analog, request.invert) {
+        (true, false) => request.value,
+        (true, true) => 255u16.wrapping_sub(request.value),
+        (false, invert) => u16::from((request.value != 0) != invert),
+    }
+}
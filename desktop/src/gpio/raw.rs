@@ -0,0 +1,54 @@
+use super::{BodyLength, Decode, Encode, Error, Request};
+
+//
+// Raw Request Implementation
+//
+
+/// request with an arbitrary type and body, for command types this host does not know about.
+/// the response is expected to have the request type with the response bit set, like all other commands.
+#[derive(PartialEq, Debug)]
+pub struct RawRequest {
+    pub type_id: u8,
+    pub body: Vec<u8>,
+}
+
+/// response to a `RawRequest`
+#[derive(PartialEq, Debug)]
+pub struct RawResponse {
+    /// the response body, excluding the type byte
+    pub body: Vec<u8>,
+}
+
+impl RawRequest {
+    pub fn new(type_id: u8, body: Vec<u8>) -> RawRequest {
+        RawRequest { type_id, body }
+    }
+}
+
+impl Encode for RawRequest {
+    fn type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.body); // BODY
+    }
+}
+
+impl Request for RawRequest {
+    type Response = RawResponse;
+}
+
+impl Decode for RawResponse {
+    const BODY_LEN: BodyLength = BodyLength::AtLeast(0);
+
+    fn decode_body(body: &[u8]) -> Result<RawResponse, Error> {
+        Ok(RawResponse {
+            body: body.to_vec(),
+        })
+    }
+}
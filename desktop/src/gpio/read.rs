@@ -1,9 +1,9 @@
-use super::{as_request_type, as_response_type, Error, Request};
+use super::{as_request_type, as_response_type, BodyLength, Error, Packet, Request};
 
 //
 // Read Request Constants
 //
-const TYPE_READ: u8 = 0x01;
+pub const TYPE_READ: u8 = 0x01;
 
 const FLAG_READ_PULLUP: u8 = 1 << 0;
 const FLAG_READ_PULLDOWN: u8 = 1 << 1;
@@ -14,7 +14,7 @@ const FLAG_READ_DIRECT: u8 = 1 << 4;
 //
 // Read Request Implementation
 //
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct ReadRequest {
     pub pin: u8,
     pub pullup: bool,
@@ -24,7 +24,7 @@ pub struct ReadRequest {
     pub direct: bool,
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct ReadResponse {
     pub value: u16,
 }
@@ -49,8 +49,11 @@ impl ReadRequest {
     }
 }
 
-impl Request<ReadResponse> for ReadRequest {
-    fn get_packet_body(&self) -> Vec<u8> {
+impl Packet for ReadRequest {
+    const TYPE_ID: u8 = as_request_type!(TYPE_READ);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(2);
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
         // set flags
         let mut flags = 0;
         if self.pullup {
@@ -69,27 +72,38 @@ impl Request<ReadResponse> for ReadRequest {
             flags |= FLAG_READ_DIRECT;
         }
 
-        // assemble packet body
-        return vec![
-            as_request_type!(TYPE_READ), //TYPE
-            self.pin,                    // PIN
-            flags,                       // FLAGS
-        ];
+        out.push(self.pin); // PIN
+        out.push(flags); // FLAGS
     }
 
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<ReadResponse, Error> {
-        // ensure length is correct
-        if packet_body.len() != 3 {
-            return Err(Error::ResponseMismatch);
-        }
+    fn decode_body(body: &[u8]) -> Result<ReadRequest, Error> {
+        let flags = body[1];
+        Ok(ReadRequest {
+            pin: body[0],
+            pullup: flags & FLAG_READ_PULLUP != 0,
+            pulldown: flags & FLAG_READ_PULLDOWN != 0,
+            analog: flags & FLAG_READ_ANALOG != 0,
+            invert: flags & FLAG_READ_INVERT != 0,
+            direct: flags & FLAG_READ_DIRECT != 0,
+        })
+    }
+}
 
-        // ensure type is read response
-        if packet_body[0] != as_response_type!(TYPE_READ) {
-            return Err(Error::ResponseMismatch);
-        }
+impl Request for ReadRequest {
+    type Response = ReadResponse;
+}
+
+impl Packet for ReadResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_READ);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(2);
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.value.to_be_bytes()); // VALUE
+    }
 
-        // read value
-        let value = ((packet_body[1] as u16) << 8) | (packet_body[2] as u16);
-        return Ok(ReadResponse { value });
+    fn decode_body(body: &[u8]) -> Result<ReadResponse, Error> {
+        Ok(ReadResponse {
+            value: u16::from_be_bytes([body[0], body[1]]),
+        })
     }
 }
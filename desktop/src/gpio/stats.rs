@@ -0,0 +1,167 @@
+use super::Error;
+use crate::sdsp::ReadError;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+//
+// Link statistics
+//
+
+/// snapshot of the statistics of a `HostController`, see `HostController::stats()`
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Stats {
+    /// all requests
+    pub total: Counters,
+
+    /// requests by the ID of the controller they were sent to
+    pub devices: BTreeMap<u8, Counters>,
+
+    /// requests by their command type
+    pub types: BTreeMap<u8, Counters>,
+}
+
+/// request counters and round-trip latency.
+/// `requests`, `successes` and `failures` count calls to `HostController::send()`,
+/// the error counters count every single try, including retried ones.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Counters {
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub retries: u64,
+
+    /// tries without a response in time
+    pub timeouts: u64,
+
+    /// tries answered with a frame that failed the checksum
+    pub crc_errors: u64,
+
+    /// tries answered with a response that does not match the request
+    pub mismatches: u64,
+
+    /// time from writing a request to receiving a valid response frame
+    pub latency: Histogram,
+}
+
+/// sub-buckets per power of two, giving a resolution of about 12%
+const SUB_BUCKETS: u64 = 8;
+
+/// log-linear histogram of durations with microsecond resolution
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Stats {
+    /// apply `update` to the total and to the counters of `device_id` and `type_id`
+    pub(crate) fn record(&mut self, device_id: u8, type_id: u8, update: impl Fn(&mut Counters)) {
+        update(&mut self.total);
+        update(self.devices.entry(device_id).or_default());
+        update(self.types.entry(type_id).or_default());
+    }
+}
+
+impl Counters {
+    /// count a failed try by its error
+    pub(crate) fn record_error(&mut self, error: &Error) {
+        match error {
+            Error::SDSPError {
+                kind: ReadError::Timeout,
+            } => self.timeouts += 1,
+            Error::SDSPError {
+                kind: ReadError::ChecksumMismatch,
+            } => self.crc_errors += 1,
+            Error::ResponseMismatch => self.mismatches += 1,
+            _ => {}
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let bucket = bucket_of(duration.as_micros() as u64);
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+
+        if self.count == 0 || duration < self.min {
+            self.min = duration;
+        }
+        if duration > self.max {
+            self.max = duration;
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0)
+            .then(|| Duration::from_nanos((self.sum.as_nanos() / self.count as u128) as u64))
+    }
+
+    /// the duration `percentile` percent of all recorded durations are at most,
+    /// accurate to the bucket resolution
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let (low, high) = bucket_range(bucket);
+                let mid = Duration::from_micros(low + (high - low) / 2);
+                return Some(mid.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+//
+// Internal Functions
+//
+
+fn bucket_of(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+
+    // the highest bit selects the power of two, the next three bits the sub-bucket
+    let exp = 63 - micros.leading_zeros() as u64;
+    let sub = (micros >> (exp - 3)) & (SUB_BUCKETS - 1);
+    ((exp - 2) * SUB_BUCKETS + sub) as usize
+}
+
+/// the range of microseconds of a bucket, `low..high`
+fn bucket_range(bucket: usize) -> (u64, u64) {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return (bucket, bucket + 1);
+    }
+
+    let exp = bucket / SUB_BUCKETS + 2;
+    let sub = bucket % SUB_BUCKETS;
+    let low = (SUB_BUCKETS + sub) << (exp - 3);
+    let high = (SUB_BUCKETS + sub + 1) << (exp - 3);
+    (low, high)
+}
@@ -1,19 +1,19 @@
-use super::{as_request_type, as_response_type, Error, Request};
+use super::{as_request_type, as_response_type, BodyLength, Error, Packet, Request};
 
 //
 // Toggle Request Constants
 //
-const TYPE_TOGGLE: u8 = 0x03;
+pub const TYPE_TOGGLE: u8 = 0x03;
 
 //
 // Toggle Request Implementation
 //
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct ToggleRequest {
     pub pin: u8,
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct ToggleResponse {
     pub new_value: u8,
 }
@@ -24,29 +24,32 @@ impl ToggleRequest {
     }
 }
 
-impl Request<ToggleResponse> for ToggleRequest {
-    fn get_packet_body(&self) -> Vec<u8> {
-        // assemble packet body
-        return vec![
-            as_request_type!(TYPE_TOGGLE), // TYPE
-            self.pin,                      // PIN
-        ];
+impl Packet for ToggleRequest {
+    const TYPE_ID: u8 = as_request_type!(TYPE_TOGGLE);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(1);
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.pin); // PIN
+    }
+
+    fn decode_body(body: &[u8]) -> Result<ToggleRequest, Error> {
+        Ok(ToggleRequest { pin: body[0] })
+    }
+}
+
+impl Request for ToggleRequest {
+    type Response = ToggleResponse;
+}
+
+impl Packet for ToggleResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_TOGGLE);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(1);
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.new_value); // VALUE
     }
 
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<ToggleResponse, Error> {
-        // ensure length is correct
-        if packet_body.len() != 2 {
-            return Err(Error::ResponseMismatch);
-        }
-
-        // ensure type is correct
-        if packet_body[0] != as_response_type!(TYPE_TOGGLE) {
-            return Err(Error::ResponseMismatch);
-        }
-
-        // parse response
-        return Ok(ToggleResponse {
-            new_value: packet_body[1],
-        });
+    fn decode_body(body: &[u8]) -> Result<ToggleResponse, Error> {
+        Ok(ToggleResponse { new_value: body[0] })
     }
 }
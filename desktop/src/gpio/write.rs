@@ -1,9 +1,9 @@
-use super::{as_request_type, as_response_type, Error, Request};
+use super::{as_request_type, as_response_type, BodyLength, Error, Packet, Request};
 
 //
 // Write Request Constants
 //
-const TYPE_WRITE: u8 = 0x02;
+pub const TYPE_WRITE: u8 = 0x02;
 
 const FLAG_WRITE_ANALOG: u8 = 1 << 0;
 const FLAG_WRITE_INVERT: u8 = 1 << 1;
@@ -11,7 +11,7 @@ const FLAG_WRITE_INVERT: u8 = 1 << 1;
 //
 // Write Request Implementation
 //
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct WriteRequest {
     pub pin: u8,
     pub value: u16,
@@ -19,7 +19,7 @@ pub struct WriteRequest {
     pub invert: bool,
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub struct WriteResponse {}
 
 impl WriteRequest {
@@ -33,8 +33,11 @@ impl WriteRequest {
     }
 }
 
-impl Request<WriteResponse> for WriteRequest {
-    fn get_packet_body(&self) -> Vec<u8> {
+impl Packet for WriteRequest {
+    const TYPE_ID: u8 = as_request_type!(TYPE_WRITE);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(4);
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
         // set flags
         let mut flags = 0;
         if self.analog {
@@ -44,27 +47,33 @@ impl Request<WriteResponse> for WriteRequest {
             flags |= FLAG_WRITE_INVERT;
         }
 
-        // assemble packet body
-        return vec![
-            as_request_type!(TYPE_WRITE), // TYPE
-            self.pin,                     // PIN
-            (self.value << 8) as u8,      // VALUE (MSB)
-            (self.value & 0xFF) as u8,    // VALUE (LSB)
-            flags,                        // FLAGS
-        ];
+        out.push(self.pin); // PIN
+        out.extend_from_slice(&self.value.to_be_bytes()); // VALUE
+        out.push(flags); // FLAGS
     }
 
-    fn parse_response(&self, packet_body: &Vec<u8>) -> Result<WriteResponse, Error> {
-        // ensure length is correct
-        if packet_body.len() != 1 {
-            return Err(Error::ResponseMismatch);
-        }
+    fn decode_body(body: &[u8]) -> Result<WriteRequest, Error> {
+        let flags = body[3];
+        Ok(WriteRequest {
+            pin: body[0],
+            value: u16::from_be_bytes([body[1], body[2]]),
+            analog: flags & FLAG_WRITE_ANALOG != 0,
+            invert: flags & FLAG_WRITE_INVERT != 0,
+        })
+    }
+}
+
+impl Request for WriteRequest {
+    type Response = WriteResponse;
+}
+
+impl Packet for WriteResponse {
+    const TYPE_ID: u8 = as_response_type!(TYPE_WRITE);
+    const BODY_LEN: BodyLength = BodyLength::Fixed(0);
+
+    fn encode_body(&self, _out: &mut Vec<u8>) {}
 
-        // ensure type is write response
-        return if packet_body[0] == as_response_type!(TYPE_WRITE) {
-            Ok(WriteResponse {})
-        } else {
-            Err(Error::ResponseMismatch)
-        };
+    fn decode_body(_body: &[u8]) -> Result<WriteResponse, Error> {
+        Ok(WriteResponse {})
     }
 }
@@ -0,0 +1,8 @@
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod duration;
+pub mod gpio;
+pub mod sdsp;
+pub mod sim;
+pub mod transport;
@@ -1,25 +1,181 @@
-pub mod gpio;
-pub mod sdsp;
+mod cli;
 
-use clap::{Parser, Subcommand};
-use gpio::{
+use agpio::config::{Config, PinConfig};
+use agpio::gpio::{
+    custom::CommandSpec,
     iic::write::{IICResultCode, IICWriteRequest},
+    policy::Policy,
+    raw::RawRequest,
     read::ReadRequest,
     toggle::ToggleRequest,
     write::WriteRequest,
-    Error, HostController,
+    Error, HostController, Request,
 };
-use std::time::Duration;
+use agpio::sdsp;
+use agpio::transport::{
+    pcap::PcapWriter,
+    record::{Recorder, Recording},
+    replay::resend,
+    Transport,
+};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use cli::{
+    connection::Connection,
+    error::{ErrorCategory, ErrorInfo},
+    Reply,
+};
+use serde_json::{json, Map, Value};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 //
 // Clap argument structures
 //
 #[derive(Subcommand, Debug, Clone)]
 enum Command {
+    #[command(flatten)]
+    Request(RequestCommand),
+
+    /// print the frame a request is sent as, without sending it
+    Encode {
+        /// `None` for user-defined commands
+        #[command(subcommand)]
+        request: Option<RequestCommand>,
+    },
+
+    /// parse a frame given as hex bytes and explain every field
+    Decode {
+        /// the frame bytes, e.g. `7b aa ca 00 03 01 0d 00 0b 8c 7d`
+        #[arg(required = true)]
+        bytes: Vec<String>,
+    },
+
+    /// re-send the requests of a recorded session and compare the responses
+    Replay {
+        /// the recording to replay, as written by --record
+        file: PathBuf,
+
+        /// keep the delays between requests as recorded
+        #[arg(long)]
+        paced: bool,
+    },
+
+    /// measure the latency of every command on one connection
+    Bench {
+        /// how many times to send each command
+        #[arg(short = 'n', long, default_value_t = 100)]
+        iterations: usize,
+
+        /// the pin for digital writes, toggles and digital reads
+        #[arg(long, default_value_t = 13)]
+        pin: u8,
+
+        /// the pin for analog writes, must support PWM
+        #[arg(long, default_value_t = 9)]
+        pwm_pin: u8,
+
+        /// the pin for analog reads (A0 is 14)
+        #[arg(long, default_value_t = 14)]
+        analog_pin: u8,
+
+        /// the address for I2C writes. a missing device still answers with a NACK
+        #[arg(long, default_value = "0x27", value_parser = cli::parse_u8)]
+        i2c_address: u8,
+
+        /// print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// test the link with echo requests of increasing size and randomized payloads
+    Linktest {
+        /// how many echo requests to send per body size
+        #[arg(short = 'n', long, default_value_t = 20)]
+        iterations: usize,
+
+        /// the largest packet body to try, in bytes
+        #[arg(long, default_value_t = 64)]
+        max_size: usize,
+
+        /// seed for the random payloads. random if not specified
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+
+    /// enter requests interactively, all sent over one connection
+    Shell,
+
+    /// run a script of requests, with sleeps, loops, variables and conditions
+    Run {
+        /// the script to run, e.g. `blink.agpio`
+        file: PathBuf,
+    },
+
+    /// answer JSON requests read line by line from stdin, all sent over one connection
+    Rpc,
+
+    /// serve a REST API for reading and writing pins, all sent over one connection
+    Serve {
+        /// the address to listen on, e.g. 127.0.0.1:8080
+        #[arg(long, value_name = "ADDRESS")]
+        http: String,
+
+        /// require this bearer token in the Authorization header of every request
+        #[arg(long)]
+        token: Option<String>,
+
+        /// also push pin changes and accept writes on a WebSocket at this address, e.g. 127.0.0.1:8081
+        #[arg(long, value_name = "ADDRESS")]
+        ws: Option<String>,
+
+        /// interval in milliseconds at which the configured pins are read for the dashboard. 0 disables polling
+        #[arg(long, default_value_t = 1000)]
+        poll: u64,
+    },
+
+    /// bridge the configured pins to an MQTT broker, e.g. for Home Assistant
+    Mqtt {
+        /// the broker to connect to, e.g. localhost or mqtt://broker:1883
+        #[arg(long, value_name = "HOST[:PORT]")]
+        broker: String,
+
+        /// prefix of the topics, followed by the device and the pin, e.g. agpio/printer/led/state
+        #[arg(long, default_value = "agpio")]
+        prefix: String,
+
+        /// announce the configured pins to Home Assistant, under this discovery prefix
+        #[arg(long, value_name = "PREFIX", num_args = 0..=1, default_missing_value = "homeassistant")]
+        discovery: Option<String>,
+
+        /// the user to log in to the broker as
+        #[arg(long)]
+        username: Option<String>,
+
+        /// the password of the user
+        #[arg(long, requires = "username")]
+        password: Option<String>,
+
+        /// interval in milliseconds at which the configured pins are read. 0 disables polling
+        #[arg(long, default_value_t = 1000)]
+        poll: u64,
+    },
+
+    /// passively print every frame seen on the bus, without sending anything
+    Monitor {
+        /// silence in milliseconds after which a partial frame is given up and a gap is marked
+        #[arg(long, default_value_t = 20)]
+        gap: u64,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum RequestCommand {
     /// read from a gpio pin
     Read {
-        /// the pin to read from
-        pin: u8,
+        /// the pin to read from, e.g. 13, A0 or a named pin from the config file
+        pin: String,
 
         /// read a analog value?
         #[arg(short, long)]
@@ -45,8 +201,8 @@ enum Command {
 
     /// write to a gpio pin
     Write {
-        /// the pin to write to
-        pin: u8,
+        /// the pin to write to, e.g. 13, A0 or a named pin from the config file
+        pin: String,
 
         /// the value to write. (0|1 for digital, 0-255 for analog)
         value: u16,
@@ -62,8 +218,8 @@ enum Command {
 
     /// toggle a gpio pin
     Toggle {
-        /// the pin to toggle
-        pin: u8,
+        /// the pin to toggle, e.g. 13, A0 or a named pin from the config file
+        pin: String,
     },
 
     /// interact with I2C devices
@@ -75,6 +231,17 @@ enum Command {
         #[command(subcommand)]
         command: I2CCommand,
     },
+
+    /// send a command with an arbitrary type and body, and print the response body
+    Raw {
+        /// the command type, e.g. 0x10
+        #[arg(long = "type", value_parser = cli::parse_u8)]
+        type_id: u8,
+
+        /// the command body as hex bytes, excluding the type
+        #[arg(long, num_args = 1.., value_name = "HEX")]
+        body: Vec<String>,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -90,12 +257,33 @@ enum I2CCommand {
     },
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// one line entered in the shell or a script, using the same grammar as the request subcommands
+#[derive(Parser, Debug)]
+#[command(
+    name = "agpio",
+    about = "enter a request, or `exit` to leave the shell",
+    no_binary_name = true,
+    disable_version_flag = true
+)]
+struct ShellLine {
+    /// `None` for user-defined commands
+    #[command(subcommand)]
+    command: Option<RequestCommand>,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Args {
-    /// the serial port to use for communication
-    port: String,
+    /// the serial port to use for communication, or a device from the config file.
+    /// not needed for encode, decode and --dry-run
+    port: Option<String>,
 
     /// the baud rate to use for communication
     #[arg(short, long)]
@@ -113,53 +301,812 @@ struct Args {
     #[arg(short, long)]
     own_id: Option<u8>,
 
+    /// record every frame sent and received to a file, for later replay
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// capture every frame sent and received to a pcap file, for Wireshark
+    #[arg(long, value_name = "FILE")]
+    pcap: Option<PathBuf>,
+
+    /// print the frames that would be sent instead of sending them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// load user-defined commands from a spec file. they can be used like built-in commands
+    #[arg(long, value_name = "FILE")]
+    commands: Option<PathBuf>,
+
+    /// log requests, retries and latency to stderr. repeat (-vv) to also log every frame
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// print a hex dump of every frame sent and received to stderr
+    #[arg(long)]
+    hexdump: bool,
+
+    /// open the serial port even if agpiod is running
+    #[arg(long)]
+    no_daemon: bool,
+
+    /// print request, retry and error counters and latencies to stderr after the request
+    #[arg(long)]
+    stats: bool,
+
+    /// print the results of requests and errors as text, or as one JSON object per request
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// the config file with named devices and pins. defaults to ~/.config/agpio/config.toml
+    #[arg(long = "config", value_name = "FILE")]
+    config_file: Option<PathBuf>,
+
+    /// refuse requests that violate the pin access policy in this file, without sending them.
+    /// overrides the policy of the device from the config file
+    #[arg(long, value_name = "FILE")]
+    policy: Option<PathBuf>,
+
+    /// the loaded config file
+    #[arg(skip)]
+    config: Config,
+
+    /// the device from the config file that is used
+    #[arg(skip)]
+    device: Option<String>,
+
+    /// the connection settings given on the command line, which agpiod does not use
+    #[arg(skip)]
+    daemon_ignores: Vec<&'static str>,
+
     /// the address of the target controller. if not specified, defaults to a broadcast (only a valid strategy if a single controller is attached)
     target_id: Option<u8>,
 
-    /// subcommand to use
+    /// subcommand to use. `None` for user-defined commands
     #[command(subcommand)]
-    command: Command,
+    command: Option<Command>,
 }
 
 fn main() {
-    // parse command line args
-    let mut args = Args::parse();
+    // user-defined commands become subcommands, so load them before parsing
+    let specs = cli::custom::load_specs();
+    let command = cli::custom::add_subcommands(Args::command(), &specs)
+        .subcommand_required(true)
+        .mut_subcommand("encode", |encode| encode.subcommand_required(true));
 
-    // create the host controller instance
-    let mut host = create_host_controller(&args);
+    // parse command line args
+    let matches = command.get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_logging(args.verbose);
+    apply_config(&mut args);
 
-    // resolve target id, default to boardcast (only a valid strategy for single device networks)
-    args.target_id = Some(args.target_id.unwrap_or(0xFF));
+    match &args.command {
+        Some(Command::Request(command)) => {
+            execute_request(&mut Session::new(&args), command.clone());
+        }
+        Some(Command::Encode { request }) => {
+            // encoding is a dry run that never needs a port
+            let args = Args {
+                dry_run: true,
+                ..args.clone()
+            };
+            let mut session = Session::new(&args);
+            match request {
+                Some(request) => execute_request(&mut session, request.clone()),
+                None => {
+                    let encode = matches.subcommand_matches("encode").unwrap();
+                    execute_custom_request(&mut session, &specs, encode);
+                }
+            }
+        }
+        Some(Command::Decode { bytes }) => {
+            let frame = cli::codec::parse_hex(bytes).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(ErrorCategory::InvalidInput.exit_code());
+            });
+            if !cli::codec::explain_frame(&frame, &specs) {
+                std::process::exit(ErrorCategory::InvalidInput.exit_code());
+            }
+        }
+        Some(Command::Replay { file, paced }) => {
+            forbid_taps(&args, "replay");
+            forbid_daemon(&args, "replay");
+            replay_recording(&args, &specs, file, *paced)
+        }
+        Some(Command::Bench {
+            iterations,
+            pin,
+            pwm_pin,
+            analog_pin,
+            i2c_address,
+            json,
+        }) => {
+            forbid_dry_run(&args, "bench");
+            let target = cli::bench::BenchTarget {
+                target_id: args.target_id.unwrap_or(sdsp::BROADCAST_ID),
+                pin: *pin,
+                pwm_pin: *pwm_pin,
+                analog_pin: *analog_pin,
+                i2c_address: *i2c_address,
+            };
+            let mut connection = connect(&args);
+            let results = cli::bench::run(&mut connection, &target, *iterations);
+            if *json || args.output == OutputFormat::Json {
+                cli::bench::print_json(&results);
+            } else {
+                cli::bench::print_table(&results);
+            }
+            if args.stats {
+                eprintln!("{}", cli::stats::format_stats(&connection.stats()));
+            }
+        }
+        Some(Command::Linktest {
+            iterations,
+            max_size,
+            seed,
+        }) => {
+            forbid_dry_run(&args, "linktest");
+            let seed = seed.unwrap_or_else(|| {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+                now.map(|d| d.as_nanos() as u64).unwrap_or(1)
+            });
+            println!(
+                "link test at {} baud, seed {}",
+                args.baud.unwrap_or(115200),
+                seed
+            );
 
-    // execute the command
-    execute_command(&mut host, args.command.clone(), &args);
+            // every failure should be counted, so never retry
+            let args = Args {
+                retries: Some(0),
+                ..args.clone()
+            };
+            let mut connection = connect(&args);
+            #[cfg(unix)]
+            if let Connection::Daemon(_, device) = &connection {
+                tracing::warn!(
+                    device,
+                    "agpiod retries failed requests, so only failures that remain are counted"
+                );
+            }
+            let target_id = args.target_id.unwrap_or(sdsp::BROADCAST_ID);
+            match cli::linktest::run(&mut connection, target_id, *iterations, *max_size, seed) {
+                Ok(results) => cli::linktest::print_report(&results),
+                Err(error) => exit_with_error(&args, error.into()),
+            }
+        }
+        Some(Command::Shell) => run_shell(&args, &specs),
+        Some(Command::Run { file }) => run_script(&args, &specs, file),
+        Some(Command::Rpc) => {
+            forbid_dry_run(&args, "rpc");
+            let mut connection = connect(&args);
+            let target_id = args.target_id.unwrap_or(sdsp::BROADCAST_ID);
+            let result = cli::rpc::serve(
+                &mut connection,
+                target_id,
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+            );
+            if args.stats {
+                eprintln!("{}", cli::stats::format_stats(&connection.stats()));
+            }
+            if let Err(e) = result {
+                eprintln!("Failed to read input: {}", e);
+                std::process::exit(ErrorCategory::InvalidInput.exit_code());
+            }
+        }
+        Some(Command::Serve {
+            http,
+            token,
+            ws,
+            poll,
+        }) => {
+            forbid_dry_run(&args, "serve");
+            let api = Arc::new(cli::http::Api::new(
+                connect(&args),
+                args.config.clone(),
+                args.device.clone(),
+                args.target_id.unwrap_or(sdsp::BROADCAST_ID),
+                token.clone(),
+            ));
+            if let Err(e) = api.serve(http, ws.as_deref(), Duration::from_millis(*poll)) {
+                eprintln!("Failed to serve on {}: {}", http, e);
+                std::process::exit(ErrorCategory::InvalidInput.exit_code());
+            }
+        }
+        Some(Command::Mqtt {
+            broker,
+            prefix,
+            discovery,
+            username,
+            password,
+            poll,
+        }) => {
+            forbid_dry_run(&args, "mqtt");
+            let bridge = cli::mqtt::Bridge::new(
+                connect(&args),
+                args.config.clone(),
+                args.device.clone(),
+                args.target_id.unwrap_or(sdsp::BROADCAST_ID),
+                prefix,
+                discovery.clone(),
+            );
+            let broker = cli::mqtt::Broker {
+                address: broker.clone(),
+                username: username.clone(),
+                password: password.clone(),
+            };
+            if let Err(e) = bridge.run(&broker, Duration::from_millis(*poll)) {
+                eprintln!("Failed to bridge to {}: {}", broker.address, e);
+            }
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        }
+        Some(Command::Monitor { gap }) => {
+            // monitoring uses the port directly, without a host controller
+            forbid_taps(&args, "monitor");
+            forbid_daemon(&args, "monitor");
+            let mut port = open_port(&args);
+            let result = cli::monitor::run(port.as_mut(), Duration::from_millis(*gap), &specs);
+            if let Err(e) = result {
+                eprintln!("Failed to read from serial port: {}", e);
+            }
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        }
+        None => execute_custom_request(&mut Session::new(&args), &specs, &matches),
+    }
 
     // exit with success code
     std::process::exit(0);
 }
 
-fn create_host_controller(args: &Args) -> HostController {
-    // create the serial port
-    let port = serialport::new(args.port.clone(), args.baud.unwrap_or(115200))
+/// connection that is only opened when the first request is sent,
+/// so dry runs never open the port
+struct Session<'a> {
+    args: &'a Args,
+    connection: Option<Connection>,
+
+    /// round-trip time of the last request, including retries
+    latency: Option<Duration>,
+}
+
+impl<'a> Session<'a> {
+    fn new(args: &'a Args) -> Session<'a> {
+        Session {
+            args,
+            connection: None,
+            latency: None,
+        }
+    }
+
+    /// the target controller, defaults to broadcast (only a valid strategy for single device networks)
+    fn target_id(&self) -> u8 {
+        self.args.target_id.unwrap_or(sdsp::BROADCAST_ID)
+    }
+
+    /// resolve a pin argument, which is a pin number, an analog pin name or a named pin.
+    /// named pins come with the default flags from the config file
+    fn pin(&self, value: &str) -> Result<(u8, Option<&'a PinConfig>), ErrorInfo> {
+        self.args
+            .config
+            .resolve_pin(value, self.args.device.as_deref())
+            .map_err(ErrorInfo::invalid_input)
+    }
+
+    /// the connection, opened on first use
+    fn connect(&mut self) -> &mut Connection {
+        let args = self.args;
+        self.connection.get_or_insert_with(|| connect(args))
+    }
+
+    /// send a request to the target controller, or print it and exit on a dry run
+    fn send<R: Request>(&mut self, request: &R) -> Result<R::Response, Error> {
+        let target_id = self.target_id();
+        if self.args.dry_run {
+            cli::codec::print_request(own_id(self.args), target_id, request);
+            std::process::exit(0);
+        }
+
+        let stats = self.args.stats;
+        let start = Instant::now();
+        let connection = self.connect();
+        let response = connection.send(request, target_id);
+        if stats {
+            eprintln!("{}", cli::stats::format_stats(&connection.stats()));
+        }
+        self.latency = Some(start.elapsed());
+        response
+    }
+}
+
+/// connect through agpiod if it serves the device, or open the port
+fn connect(args: &Args) -> Connection {
+    connect_daemon(args).unwrap_or_else(|| Connection::Port(create_host_controller(args)))
+}
+
+/// connect to a running agpiod that serves the device, unless the port has to be opened directly.
+/// frames are only seen on a direct connection, so recording and dumping them never use the daemon
+#[cfg(unix)]
+fn connect_daemon(args: &Args) -> Option<Connection> {
+    let device = args.device.as_ref()?;
+    if args.no_daemon || args.record.is_some() || args.pcap.is_some() || args.hexdump {
+        return None;
+    }
+
+    let path = agpio::daemon::socket_path();
+    let mut client = agpio::daemon::Client::connect(&path).ok()?;
+    if !client.devices().contains(device) {
+        return None;
+    }
+    tracing::debug!(socket = %path.display(), device, "sending through agpiod");
+    for flag in &args.daemon_ignores {
+        tracing::warn!(
+            device,
+            "{} is ignored, agpiod uses its own settings for the device",
+            flag
+        );
+    }
+    if let Some(path) = &args.policy {
+        client.set_policy(load_policy(args, path));
+    }
+    Some(Connection::Daemon(client, device.clone()))
+}
+
+#[cfg(not(unix))]
+fn connect_daemon(_args: &Args) -> Option<Connection> {
+    None
+}
+
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+fn own_id(args: &Args) -> u8 {
+    args.own_id.unwrap_or(0xAA)
+}
+
+/// load the config file, and fill in the settings of the device used
+fn apply_config(args: &mut Args) {
+    // only a config file given explicitly has to exist
+    let path = args
+        .config_file
+        .clone()
+        .or_else(|| Config::default_path().filter(|path| path.exists()));
+    let config = match &path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load config {}: {}", path.display(), e);
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        }),
+        None => Config::default(),
+    };
+
+    // a port can name a device, or be the port of one. without a port, the device of a named pin
+    // or the default device is used
+    let device = match &args.port {
+        Some(port) => config
+            .devices
+            .contains_key(port)
+            .then(|| port.clone())
+            .or_else(|| device_of_port(&config, port)),
+        None => requested_pin(&args.command)
+            .and_then(|pin| config.pins.get(pin))
+            .and_then(|pin| pin.device.clone())
+            .or_else(|| config.default_device.clone()),
+    };
+
+    for (flag, given) in [
+        ("--baud", args.baud.is_some()),
+        ("--own-id", args.own_id.is_some()),
+        ("--retries", args.retries.is_some()),
+    ] {
+        if given {
+            args.daemon_ignores.push(flag);
+        }
+    }
+
+    // the command line overrides the settings of the device
+    if let Some(name) = &device {
+        let settings = &config.devices[name];
+        if args.port.as_ref() == Some(name) {
+            args.port = None;
+        }
+        args.baud = args.baud.or(settings.baud);
+        args.own_id = args.own_id.or(settings.own_id);
+        args.target_id = args.target_id.or(settings.target_id);
+        args.retries = args.retries.or(settings.retries);
+    }
+    args.config = config;
+    args.device = device;
+}
+
+/// the configured device connected to the serial port `port`, so requests to the port go
+/// through agpiod while it holds it
+fn device_of_port(config: &Config, port: &str) -> Option<String> {
+    let canonical = |path: &str| std::fs::canonicalize(path).ok();
+    let port = canonical(port)?;
+    config
+        .devices
+        .iter()
+        .find(|(_, device)| {
+            // only look up USB serial numbers if the device is found by them
+            let configured = match &device.usb_serial {
+                Some(_) => device.find_port().ok().flatten(),
+                None => device.port.clone(),
+            };
+            configured.as_deref().and_then(canonical) == Some(port.clone())
+        })
+        .map(|(name, _)| name.clone())
+}
+
+/// the pin argument of a single request
+fn requested_pin(command: &Option<Command>) -> Option<&str> {
+    let request = match command {
+        Some(Command::Request(request)) => request,
+        Some(Command::Encode {
+            request: Some(request),
+        }) => request,
+        _ => return None,
+    };
+    match request {
+        RequestCommand::Read { pin, .. }
+        | RequestCommand::Write { pin, .. }
+        | RequestCommand::Toggle { pin } => Some(pin),
+        _ => None,
+    }
+}
+
+fn open_port(args: &Args) -> Box<dyn Transport> {
+    // the port of a configured device is looked up by its USB serial number if needed
+    let device = args.device.as_ref().map(|name| &args.config.devices[name]);
+    let port = match (&args.port, device) {
+        (Some(port), _) => Some(port.clone()),
+        (None, Some(device)) => device.find_port().unwrap_or_else(|e| {
+            let message = format!("Failed to find serial port: {}", e);
+            exit_with_error(args, ErrorInfo::new(ErrorCategory::Port, message))
+        }),
+        (None, None) => None,
+    };
+    let Some(port) = port else {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a serial port is required for this command",
+            )
+            .exit();
+    };
+    let port = serialport::new(&port, args.baud.unwrap_or(115200))
         .timeout(Duration::from_millis(100))
         .open()
         .unwrap_or_else(|e| {
-            eprintln!("Failed to open serial port: {}", e);
-            std::process::exit(128);
+            let message = format!("Failed to open serial port: {}", e);
+            exit_with_error(args, ErrorInfo::new(ErrorCategory::Port, message))
         });
+    Box::new(port)
+}
 
+fn create_host_controller(args: &Args) -> HostController {
     // create the host controller instance
-    HostController::new(
-        port,
-        args.own_id.unwrap_or(0xAA),
+    let mut host = HostController::new(
+        open_port(args),
+        own_id(args),
         Some(Duration::from_millis(100)),
         args.retries,
-    )
+    );
+
+    // record the session if requested
+    if let Some(path) = &args.record {
+        let recorder = Recorder::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to create recording {}: {}", path.display(), e);
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        });
+        host.add_tap(Box::new(recorder));
+    }
+
+    // enforce the pin access policy if there is one
+    let device = args.device.as_ref().map(|name| &args.config.devices[name]);
+    let policy = args
+        .policy
+        .as_ref()
+        .or(device.and_then(|device| device.policy.as_ref()));
+    if let Some(path) = policy {
+        host.set_policy(load_policy(args, path));
+    }
+
+    // dump frames if requested
+    if args.hexdump {
+        host.add_tap(Box::new(cli::hexdump::HexDump));
+    }
+
+    // capture the session if requested
+    if let Some(path) = &args.pcap {
+        let capture = PcapWriter::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to create capture {}: {}", path.display(), e);
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        });
+        host.add_tap(Box::new(capture));
+    }
+
+    host
+}
+
+/// load a policy file, with the analog pins of the board of the device
+fn load_policy(args: &Args, path: &PathBuf) -> Policy {
+    let board = args.config.board(args.device.as_deref());
+    Policy::load(path, board).unwrap_or_else(|e| {
+        eprintln!("Failed to load policy {}: {}", path.display(), e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    })
+}
+
+fn replay_recording(args: &Args, specs: &[CommandSpec], file: &PathBuf, paced: bool) -> ! {
+    let recording = Recording::load(file).unwrap_or_else(|e| {
+        eprintln!("Failed to load recording {}: {}", file.display(), e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    });
+
+    // on a dry run, only print what would be re-sent
+    if args.dry_run {
+        for frame in recording.sent() {
+            println!("{}", cli::hex(&frame.frame));
+        }
+        std::process::exit(0);
+    }
+
+    // re-send the recorded requests
+    let mut port = open_port(args);
+    let outcomes = resend(&recording, port.as_mut(), Duration::from_millis(100), paced)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to write to serial port: {}", e);
+            std::process::exit(ErrorCategory::InvalidInput.exit_code());
+        });
+
+    // print the outcome of every request
+    let mut mismatches = 0;
+    for (i, outcome) in outcomes.iter().enumerate() {
+        println!("#{} {}", i, describe_frame(specs, Some(&outcome.request)));
+        if outcome.matches() {
+            println!(
+                "  ok: {}",
+                describe_frame(specs, outcome.received.as_deref())
+            );
+        } else {
+            mismatches += 1;
+            println!("  MISMATCH");
+            println!(
+                "    recorded: {}",
+                describe_frame(specs, outcome.expected.as_deref())
+            );
+            println!(
+                "    received: {}",
+                describe_frame(specs, outcome.received.as_deref())
+            );
+        }
+    }
+    println!(
+        "{} of {} responses matched the recording",
+        outcomes.len() - mismatches,
+        outcomes.len()
+    );
+
+    if mismatches > 0 {
+        std::process::exit(ErrorCategory::ResponseMismatch.exit_code());
+    }
+    std::process::exit(0);
+}
+
+fn describe_frame(specs: &[CommandSpec], frame: Option<&[u8]>) -> String {
+    let Some(frame) = frame else {
+        return "no response".to_string();
+    };
+    match sdsp::decode_packet(frame) {
+        Ok(packet) => format!(
+            "{:#04x} -> {:#04x}: {}",
+            packet.sender_id,
+            packet.receiver_id,
+            cli::custom::describe(specs, packet.body)
+        ),
+        Err(err) => format!("invalid frame ({:?}): {:02x?}", err, frame),
+    }
+}
+
+fn run_shell(args: &Args, specs: &[CommandSpec]) {
+    forbid_dry_run(args, "the shell");
+
+    // connect right away, so a wrong port is reported before the first prompt
+    let mut session = Session::new(args);
+    session.connect();
+
+    let pins = args.config.pins.keys().cloned().collect();
+    let result = cli::shell::run(line_grammar(specs), pins, |matches| {
+        let mut report = Report::default();
+        let result = send_line(&mut session, specs, matches, &mut report);
+        print_reply(&session, report, &result);
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to read input: {}", e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    }
+}
+
+fn run_script(args: &Args, specs: &[CommandSpec], file: &PathBuf) {
+    forbid_dry_run(args, "scripts");
+
+    let source = std::fs::read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("Failed to read script {}: {}", file.display(), e);
+        std::process::exit(ErrorCategory::InvalidInput.exit_code());
+    });
+
+    // check the whole script before sending anything
+    let mut grammar = line_grammar(specs);
+    let result = cli::script::Script::parse(&source, &mut grammar).and_then(|script| {
+        let mut session = Session::new(args);
+        script.run(&mut grammar, |matches, print| {
+            let mut report = Report::default();
+            let result = send_line(&mut session, specs, matches, &mut report);
+
+            // failures are reported with the line number instead
+            if print && result.is_ok() {
+                print_reply(&session, report, &result);
+            }
+            result
+        })
+    });
+    if let Err(e) = result {
+        eprintln!("{}: {}", file.display(), e);
+        std::process::exit(e.error.category.exit_code());
+    }
 }
 
-fn execute_command(host: &mut HostController, command: Command, args: &Args) {
+/// exit with a usage error if --dry-run is set, for commands that send more than one request
+fn forbid_dry_run(args: &Args, what: &str) {
+    if args.dry_run {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("{} cannot be used with --dry-run, use encode instead", what),
+            )
+            .exit();
+    }
+}
+
+/// exit with a usage error if frames are to be recorded, captured or dumped, for commands that
+/// use the port without a host controller, which is what passes the frames to the taps
+fn forbid_taps(args: &Args, what: &str) {
+    let flag = if args.record.is_some() {
+        "--record"
+    } else if args.pcap.is_some() {
+        "--pcap"
+    } else if args.hexdump {
+        "--hexdump"
+    } else {
+        return;
+    };
+    Args::command()
+        .error(
+            clap::error::ErrorKind::ArgumentConflict,
+            format!("{} cannot be used with {}", what, flag),
+        )
+        .exit();
+}
+
+/// exit with an error if agpiod serves the device, for commands that need the port itself
+fn forbid_daemon(args: &Args, what: &str) {
+    if connect_daemon(args).is_some() {
+        let message = format!(
+            "agpiod owns device {}, stop it to run {}",
+            args.device.as_deref().unwrap_or_default(),
+            what
+        );
+        exit_with_error(args, ErrorInfo::new(ErrorCategory::Port, message));
+    }
+}
+
+/// the grammar of a line in the shell or a script
+fn line_grammar(specs: &[CommandSpec]) -> clap::Command {
+    cli::custom::add_subcommands(ShellLine::command(), specs).subcommand_required(true)
+}
+
+/// send the request of a line in the shell or a script
+fn send_line(
+    session: &mut Session,
+    specs: &[CommandSpec],
+    matches: &ArgMatches,
+    report: &mut Report,
+) -> Result<Reply, ErrorInfo> {
+    session.latency = None;
+    let line = ShellLine::from_arg_matches(matches).unwrap();
+    match line.command {
+        Some(command) => send_request(session, command, report),
+        None => send_custom_request(session, specs, matches, report),
+    }
+}
+
+/// fields describing a request and its response, for JSON output
+#[derive(Default)]
+struct Report(Map<String, Value>);
+
+impl Report {
+    fn set(&mut self, key: &str, value: impl Into<Value>) {
+        self.0.insert(key.to_string(), value.into());
+    }
+}
+
+/// send a request and exit with its result
+fn execute_request(session: &mut Session, command: RequestCommand) {
+    let mut report = Report::default();
+    let result = send_request(session, command, &mut report);
+    exit_with_reply(session, report, result);
+}
+
+/// execute the user-defined command matched as subcommand of `matches` and exit with its result
+fn execute_custom_request(session: &mut Session, specs: &[CommandSpec], matches: &ArgMatches) {
+    let mut report = Report::default();
+    let result = send_custom_request(session, specs, matches, &mut report);
+    exit_with_reply(session, report, result);
+}
+
+/// print the reply of a request, as text or as JSON object
+fn print_reply(session: &Session, report: Report, result: &Result<Reply, ErrorInfo>) {
+    match session.args.output {
+        OutputFormat::Text => match result {
+            Ok(Reply::Value(value) | Reply::Analog(value)) => println!("{}", value),
+            Ok(Reply::Text(text)) => println!("{}", text),
+            Err(error) => eprintln!("{}", error.message),
+        },
+        OutputFormat::Json => {
+            let mut object = Report::default();
+            object.set("ok", result.is_ok());
+            object.set("device", session.target_id());
+            object.0.extend(report.0);
+            if let Some(latency) = session.latency {
+                object.set("latency_us", latency.as_micros() as u64);
+            }
+            if let Err(error) = result {
+                object.set("error", serde_json::to_value(error).unwrap());
+            }
+            println!("{}", Value::Object(object.0));
+        }
+    }
+}
+
+/// print the reply of a request and exit with the correct code
+fn exit_with_reply(session: &Session, report: Report, result: Result<Reply, ErrorInfo>) {
+    print_reply(session, report, &result);
+    match result {
+        Ok(Reply::Value(value)) => {
+            // digital values are the exit code
+            if !session.args.no_exit_code {
+                std::process::exit(value as i32);
+            }
+        }
+        Ok(Reply::Analog(_) | Reply::Text(_)) => {}
+        Err(error) => std::process::exit(error.category.exit_code()),
+    }
+}
+
+/// print an error that ends the program, as text or as JSON object, and exit with its code
+fn exit_with_error(args: &Args, error: ErrorInfo) -> ! {
+    match args.output {
+        OutputFormat::Text => eprintln!("{}", error.message),
+        OutputFormat::Json => println!("{}", json!({ "ok": false, "error": error })),
+    }
+    std::process::exit(error.category.exit_code());
+}
+
+fn send_request(
+    session: &mut Session,
+    command: RequestCommand,
+    report: &mut Report,
+) -> Result<Reply, ErrorInfo> {
     match command {
-        Command::Read {
+        RequestCommand::Read {
             pin,
             analog,
             inverted,
@@ -167,145 +1114,131 @@ fn execute_command(host: &mut HostController, command: Command, args: &Args) {
             pulldown,
             direct,
         } => {
-            // create the request
+            // create the request, with the defaults of a named pin
+            report.set("op", "read");
+            let (pin, named) = resolve_pin(session, &pin, report)?;
+            let analog = analog || named.is_some_and(|named| named.analog);
+            let inverted = inverted || named.is_some_and(|named| named.inverted);
+            let pullup = pullup || named.is_some_and(|named| named.pullup);
+            let pulldown = pulldown || named.is_some_and(|named| named.pulldown);
             let request = ReadRequest::new(pin, pullup, pulldown, analog, inverted, direct);
 
-            // send the request and handle the response
-            let response = host.send(&request, args.target_id.unwrap());
-            match response {
-                Ok(response) => {
-                    // print the response value
-                    println!("{}", response.value);
-
-                    // exit with the correct code
-                    if !args.no_exit_code {
-                        std::process::exit(response.value as i32);
-                    }
-                }
-                Err(error) => {
-                    print_gpio_error_and_exit(error);
-                }
+            // send the request and return the read value
+            let response = session.send(&request)?;
+            report.set("value", response.value);
+            if analog {
+                Ok(Reply::Analog(response.value))
+            } else {
+                Ok(Reply::Value(response.value))
             }
         }
-        Command::Write {
+        RequestCommand::Write {
             pin,
             value,
             inverted,
             analog,
         } => {
-            // create the request
+            // create the request, with the defaults of a named pin
+            report.set("op", "write");
+            let (pin, named) = resolve_pin(session, &pin, report)?;
+            let analog = analog || named.is_some_and(|named| named.analog);
+            let inverted = inverted || named.is_some_and(|named| named.inverted);
+            report.set("value", value);
             let request = WriteRequest::new(pin, value, analog, inverted);
 
             // send the request
-            let response = host.send(&request, args.target_id.unwrap());
-            match response {
-                Ok(_) => {
-                    println!("{}", value);
-                }
-                Err(error) => {
-                    print_gpio_error_and_exit(error);
-                }
-            }
+            session.send(&request)?;
+            Ok(Reply::Text(value.to_string()))
         }
-        Command::Toggle { pin } => {
+        RequestCommand::Toggle { pin } => {
             // create the request
+            report.set("op", "toggle");
+            let (pin, _) = resolve_pin(session, &pin, report)?;
             let request = ToggleRequest::new(pin);
 
-            // send the request
-            let response = host.send(&request, args.target_id.unwrap());
-            match response {
-                Ok(response) => {
-                    // print the response value
-                    println!("{}", response.new_value);
-
-                    // exit with the correct code
-                    if !args.no_exit_code {
-                        std::process::exit(response.new_value as i32);
-                    }
-                }
-                Err(error) => {
-                    print_gpio_error_and_exit(error);
-                }
-            }
+            // send the request and return the new value
+            let response = session.send(&request)?;
+            report.set("value", response.new_value);
+            Ok(Reply::Value(response.new_value.into()))
         }
-        Command::I2C { address, command } => {
-            execute_i2c_command(host, command, address, &args);
+        RequestCommand::I2C { address, command } => {
+            send_i2c_command(session, command, address, report)
         }
+        RequestCommand::Raw { type_id, body } => {
+            // create the request
+            report.set("op", "raw");
+            report.set("type", type_id);
+            let body = cli::codec::parse_hex(&body).map_err(ErrorInfo::invalid_input)?;
+            let request = RawRequest::new(type_id, body);
+
+            // send the request and return the response body
+            let response = session.send(&request)?;
+            report.set("body", response.body.clone());
+            Ok(Reply::Text(cli::hex(&response.body)))
+        }
+    }
+}
+
+/// resolve the pin argument of a request, and add it to the report
+fn resolve_pin<'a>(
+    session: &Session<'a>,
+    value: &str,
+    report: &mut Report,
+) -> Result<(u8, Option<&'a PinConfig>), ErrorInfo> {
+    let (pin, named) = session.pin(value)?;
+    report.set("pin", pin);
+    if named.is_some() {
+        report.set("name", value);
     }
+    Ok((pin, named))
 }
 
-fn execute_i2c_command(host: &mut HostController, command: I2CCommand, address: u8, args: &Args) {
+/// send the user-defined command matched as subcommand of `matches`
+fn send_custom_request(
+    session: &mut Session,
+    specs: &[CommandSpec],
+    matches: &ArgMatches,
+    report: &mut Report,
+) -> Result<Reply, ErrorInfo> {
+    let (name, matches) = matches.subcommand().unwrap();
+    let spec = specs.iter().find(|spec| spec.name == name).unwrap();
+
+    // create the request
+    report.set("op", name);
+    let request = cli::custom::request(spec, matches);
+
+    // send the request and return the response values
+    let response = session.send(&request)?;
+    let values = spec.response_values(&response)?;
+    report.set(
+        "values",
+        values
+            .iter()
+            .map(|(name, value)| (name.clone(), serde_json::to_value(value).unwrap()))
+            .collect::<Map<_, _>>(),
+    );
+    Ok(Reply::Text(cli::custom::format_values(&values)))
+}
+
+fn send_i2c_command(
+    session: &mut Session,
+    command: I2CCommand,
+    address: u8,
+    report: &mut Report,
+) -> Result<Reply, ErrorInfo> {
     match command {
         I2CCommand::Write { data, stop } => {
             // create the request
+            report.set("op", "i2c_write");
+            report.set("address", address);
             let request = IICWriteRequest::new(address, data.unwrap_or(vec![]), stop);
 
             // send the request
-            let response = host.send(&request, args.target_id.unwrap());
-            match response {
-                Ok(response) => match response.result_code {
-                    IICResultCode::Success => {
-                        println!("Success");
-                    }
-                    IICResultCode::DataTooLong => {
-                        eprintln!("i2c write failed: data too long reported by Wire");
-                        std::process::exit(128);
-                    }
-                    IICResultCode::NACKOnAddress => {
-                        eprintln!("i2c write failed: NACK on address reported by Wire");
-                        std::process::exit(128);
-                    }
-                    IICResultCode::NACKOnData => {
-                        eprintln!("i2c write failed: NACK on data reported by Wire");
-                        std::process::exit(128);
-                    }
-                    IICResultCode::Other => {
-                        eprintln!("i2c write failed: other error reported by Wire");
-                        std::process::exit(128);
-                    }
-                    IICResultCode::Timeout => {
-                        eprintln!("i2c write failed: timeout reported by Wire");
-                        std::process::exit(128);
-                    }
-                    IICResultCode::Unknown { result_code } => {
-                        eprintln!(
-                            "i2c write failed: unknown error {:#04x} reported by Wire",
-                            result_code
-                        );
-                        std::process::exit(128);
-                    }
-                },
-                Err(error) => {
-                    print_gpio_error_and_exit(error);
-                }
+            let response = session.send(&request)?;
+            match response.result_code {
+                IICResultCode::Success => Ok(Reply::Text("Success".to_string())),
+                result_code => Err(ErrorInfo::i2c(&result_code)),
             }
         }
     }
 }
-
-fn print_gpio_error_and_exit(error: Error) -> ! {
-    // print a nice error message
-    match error {
-        Error::SDSPError { kind } => {
-            eprintln!(
-                "communication failed with remote controller (SDSP error: {:?})",
-                kind
-            );
-        }
-        Error::RemoteError { code } => {
-            eprintln!("remote controller returned error code {:#04x}", code);
-        }
-        Error::HostError { code } => {
-            eprintln!("host controller returned error code {:#04x}", code);
-        }
-        Error::InvalidPin => {
-            eprintln!("the pin number is invalid for the requested operation");
-        }
-        Error::ResponseMismatch => {
-            eprintln!("the response from the remote controller did not match the expected response. this could be caused by a communication issue or a incompatible controller");
-        }
-    };
-
-    // exit with error code
-    std::process::exit(128);
-}
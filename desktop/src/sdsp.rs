@@ -1,16 +1,27 @@
-use serialport::SerialPort;
-use std::time::Duration;
+pub mod scanner;
+
+use crate::transport::Transport;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
 
 //
 // SDSP Protocol Constants
 //
-const PKG_START_BYTE: u8 = 0x7B;
-const PKG_END_BYTE: u8 = 0x7D;
+pub const PKG_START_BYTE: u8 = 0x7B;
+pub const PKG_END_BYTE: u8 = 0x7D;
+
+/// number of bytes a packet adds around its body (prologue and epilogue)
+pub const PKG_OVERHEAD: usize = 8;
+
+/// receiver ID that addresses all devices
+pub const BROADCAST_ID: u8 = 0xFF;
 
 //
 // Public API
 //
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ReadError {
     ChecksumMismatch,
     RecipientMismatch,
@@ -18,209 +29,301 @@ pub enum ReadError {
     Timeout,
 }
 
-#[derive(Debug)]
-pub struct Packet {
+/// a decoded packet, borrowing its body from the buffer it was decoded from
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PacketRef<'a> {
     pub sender_id: u8,
     pub receiver_id: u8,
-    pub body: Vec<u8>,
+    pub body: &'a [u8],
+}
+
+/// encode a packet into `out`, replacing its previous contents.
+/// `out` keeps its capacity, so reusing it avoids allocating per packet.
+pub fn encode_packet(sender_id: u8, receiver_id: u8, body: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(body.len() + PKG_OVERHEAD);
+
+    // prologue
+    let body_len = body.len() as u16;
+    out.extend_from_slice(&[PKG_START_BYTE, sender_id, receiver_id]);
+    out.extend_from_slice(&body_len.to_be_bytes());
+
+    // body
+    out.extend_from_slice(body);
+
+    // epilogue, with the checksum as placeholder
+    out.extend_from_slice(&[0, 0, PKG_END_BYTE]);
 
-    /// internal, set to 0 when creating a new packet
-    pub body_len: u16,
+    // calculate and insert checksum
+    let crc = crc16(out);
+    let pkg_len = out.len();
+    out[pkg_len - 3..pkg_len - 1].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// decode a single, complete packet from `frame`
+pub fn decode_packet(frame: &[u8]) -> Result<PacketRef<'_>, ReadError> {
+    let mut decoder = Decoder::new();
+    for (i, &byte) in frame.iter().enumerate() {
+        match decoder.push(byte) {
+            Some(Ok(())) if i + 1 == frame.len() => {
+                // the decoder skips garbage before the start byte
+                let start = frame.len() - decoder.buf.len();
+                return Ok(decoder.packet_in(&frame[start..]));
+            }
+            Some(Ok(())) => return Err(ReadError::InvalidPacket),
+            Some(Err(err)) => return Err(err),
+            None => {}
+        }
+    }
+
+    Err(ReadError::InvalidPacket)
+}
+
+/// incremental packet decoder.
+/// the decoder keeps its buffer between packets, so reusing it avoids allocating per packet.
+#[derive(Debug)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    state: ReadState,
+    body_len: usize,
+}
 
-    /// internal, set to 0 when creating a new packet
-    pub checksum: u16,
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadState {
+    StartByte,
+    SenderID,
+    ReceiverID,
+    LengthMSB,
+    LengthLSB,
+    Body,
+    ChecksumMSB,
+    ChecksumLSB,
+    EndByte,
+    Done,
 }
-impl Packet {
-    pub fn new(sender_id: u8, receiver_id: u8, body: Vec<u8>) -> Packet {
-        Packet {
-            sender_id: sender_id,
-            receiver_id: receiver_id,
-            body: body,
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            buf: Vec::with_capacity(64),
+            state: ReadState::StartByte,
             body_len: 0,
-            checksum: 0,
         }
     }
+
+    /// discard any partially decoded packet
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.state = ReadState::StartByte;
+        self.body_len = 0;
+    }
+
+    /// feed a single byte into the decoder.
+    /// returns `Some(Ok(()))` once a complete packet with valid checksum was decoded (see `packet()`),
+    /// `Some(Err(..))` if the packet is invalid, and `None` if more bytes are needed.
+    /// the next byte after a result starts a new packet.
+    pub fn push(&mut self, byte: u8) -> Option<Result<(), ReadError>> {
+        if self.state == ReadState::Done {
+            self.reset();
+        }
+
+        match self.state {
+            ReadState::StartByte => {
+                // wait for start byte
+                if byte != PKG_START_BYTE {
+                    return None;
+                }
+                self.state = ReadState::SenderID;
+            }
+            ReadState::SenderID => self.state = ReadState::ReceiverID,
+            ReadState::ReceiverID => self.state = ReadState::LengthMSB,
+            ReadState::LengthMSB => self.state = ReadState::LengthLSB,
+            ReadState::LengthLSB => {
+                self.body_len = u16::from_be_bytes([self.buf[3], byte]) as usize;
+                self.state = if self.body_len == 0 {
+                    ReadState::ChecksumMSB
+                } else {
+                    ReadState::Body
+                };
+            }
+            ReadState::Body => {
+                // wait until the full body was received
+                if self.buf.len() + 1 == 5 + self.body_len {
+                    self.state = ReadState::ChecksumMSB;
+                }
+            }
+            ReadState::ChecksumMSB => self.state = ReadState::ChecksumLSB,
+            ReadState::ChecksumLSB => self.state = ReadState::EndByte,
+            ReadState::EndByte | ReadState::Done => {
+                self.buf.push(byte);
+                self.state = ReadState::Done;
+                return Some(self.validate());
+            }
+        }
+
+        self.buf.push(byte);
+        None
+    }
+
+    /// the last packet decoded by `push()`.
+    /// only valid directly after `push()` returned `Some(Ok(()))`.
+    pub fn packet(&self) -> PacketRef<'_> {
+        self.packet_in(&self.buf)
+    }
+
+    /// the raw bytes of the last packet, starting at the start byte.
+    /// only valid directly after `push()` returned `Some(..)`.
+    pub fn frame(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn packet_in<'a>(&self, frame: &'a [u8]) -> PacketRef<'a> {
+        PacketRef {
+            sender_id: frame[1],
+            receiver_id: frame[2],
+            body: &frame[5..5 + self.body_len],
+        }
+    }
+
+    fn validate(&self) -> Result<(), ReadError> {
+        // check end byte
+        let len = self.buf.len();
+        if self.buf[len - 1] != PKG_END_BYTE {
+            return Err(ReadError::InvalidPacket);
+        }
+
+        // checksum is calculated with the checksum field set to 0
+        let checksum = u16::from_be_bytes([self.buf[len - 3], self.buf[len - 2]]);
+        let crc = crc16_update(crc16(&self.buf[..len - 3]), &[0, 0, PKG_END_BYTE]);
+        if crc != checksum {
+            return Err(ReadError::ChecksumMismatch);
+        }
+
+        Ok(())
+    }
 }
 
-pub fn read_packet(
-    port: &mut Box<dyn SerialPort>,
+/// read a packet addressed to `own_id` (or broadcast) from the transport
+pub fn read_packet<'a>(
+    port: &mut dyn Transport,
+    decoder: &'a mut Decoder,
     own_id: u8,
     timeout: Duration,
-) -> Result<Packet, ReadError> {
-    #[derive(Debug)]
-    enum ReadState {
-        StartByte,
-        SenderID,
-        ReceiverID,
-        LengthMSB,
-        LengthLSB,
-        Body,
-        ChecksumMSB,
-        ChecksumLSB,
-        EndByte,
-    }
-
+) -> Result<PacketRef<'a>, ReadError> {
     // start timeout timer
-    let start = std::time::Instant::now();
-
-    // read packet in chunks of 64 bytes
-    const MAX_BUF_SIZE: usize = 64;
-    let mut pkg = Packet {
-        sender_id: 0,
-        receiver_id: 0,
-        body_len: 0,
-        body: Vec::new(),
-        checksum: 0,
-    };
-    let mut state = ReadState::StartByte;
+    let deadline = Instant::now() + timeout;
+
+    // read packet in chunks of up to 64 bytes
+    let mut buf = [0u8; 64];
+    decoder.reset();
     loop {
         // check timeout
-        if start.elapsed() > timeout {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            debug!(
+                ?timeout,
+                partial = %Hex(decoder.frame()),
+                "timeout waiting for response"
+            );
             return Err(ReadError::Timeout);
         }
 
-        // figure out how many bytes are available to read
-        let bytes_available =
-            std::cmp::min(port.bytes_to_read().unwrap_or(0) as usize, MAX_BUF_SIZE);
-        if bytes_available == 0 {
-            continue;
-        }
+        // read the available bytes and push them into the decoder
+        let bytes_read = port.read(&mut buf, remaining).unwrap_or(0);
+        for &byte in &buf[..bytes_read] {
+            match decoder.push(byte) {
+                Some(Ok(())) => {
+                    trace!(frame = %Hex(decoder.frame()), "frame received");
 
-        // read n bytes from port
-        let mut buf = vec![0; bytes_available];
-        let bytes_read = port.read(&mut buf).unwrap_or(0);
-
-        // handle every byte using state machine
-        for i in 0..bytes_read {
-            let byte = buf[i];
-            match state {
-                ReadState::StartByte => {
-                    // wait for start byte
-                    if byte == PKG_START_BYTE {
-                        state = ReadState::SenderID;
-                    }
-                }
-                ReadState::SenderID => {
-                    // read sender ID
-                    pkg.sender_id = byte;
-                    state = ReadState::ReceiverID;
-                }
-                ReadState::ReceiverID => {
-                    // read receiver ID
-                    pkg.receiver_id = byte;
-                    state = ReadState::LengthMSB;
-                }
-                ReadState::LengthMSB => {
-                    // read length MSB into pkg_len
-                    pkg.body_len = (byte as u16) << 8;
-                    state = ReadState::LengthLSB;
-                }
-                ReadState::LengthLSB => {
-                    // read length LSB into pkg_len
-                    pkg.body_len |= byte as u16;
-                    state = ReadState::Body;
-                }
-                ReadState::Body => {
-                    // push bytes into pkg until pkg_len is reached
-                    pkg.body.push(byte);
-                    if (pkg.body.len() as u16) == pkg.body_len {
-                        state = ReadState::ChecksumMSB;
-                    }
-                }
-                ReadState::ChecksumMSB => {
-                    // read checksum MSB into pkg_chsum
-                    pkg.checksum = (byte as u16) << 8;
-                    state = ReadState::ChecksumLSB;
-                }
-                ReadState::ChecksumLSB => {
-                    // read checksum LSB into pkg_chsum
-                    pkg.checksum |= byte as u16;
-                    state = ReadState::EndByte;
-                }
-                ReadState::EndByte => {
-                    // check end byte
-                    if byte == PKG_END_BYTE {
-                        // calculate and check checksum
-                        let pkg_for_checksum = assemble_packet(&pkg);
-                        if sdsp_crc16(&pkg_for_checksum) == pkg.checksum {
-                            // check recipient ID
-                            if pkg.receiver_id == own_id || pkg.receiver_id == 0xff {
-                                return Ok(pkg);
-                            } else {
-                                return Err(ReadError::RecipientMismatch);
-                            }
-                        } else {
-                            return Err(ReadError::ChecksumMismatch);
-                        }
+                    // check recipient ID
+                    let receiver_id = decoder.packet().receiver_id;
+                    if receiver_id == own_id || receiver_id == BROADCAST_ID {
+                        return Ok(decoder.packet());
                     } else {
-                        return Err(ReadError::InvalidPacket);
+                        debug!(receiver_id, own_id, "frame addressed to another device");
+                        return Err(ReadError::RecipientMismatch);
                     }
                 }
+                Some(Err(err)) => {
+                    debug!(error = ?err, frame = %Hex(decoder.frame()), "invalid frame received");
+                    return Err(err);
+                }
+                None => {}
             }
         }
     }
 }
 
+/// encode a packet into `frame_buf` and write it to the transport
 pub fn write_packet(
-    port: &mut Box<dyn SerialPort>,
-    packet: &mut Packet,
-) -> Result<usize, std::io::Error> {
-    // init internal fields of packet
-    packet.body_len = packet.body.len() as u16;
-    packet.checksum = 0;
-
-    // assemble packet
-    let mut pkg_data = assemble_packet(packet);
-
-    // calculate checksum
-    let crc = sdsp_crc16(&pkg_data);
-    let pkg_len = pkg_data.len();
-    pkg_data[pkg_len - 3] = (crc >> 8) as u8;
-    pkg_data[pkg_len - 2] = (crc & 0xFF) as u8;
-
-    // write packet to port
-    return port.write(&pkg_data);
+    port: &mut dyn Transport,
+    frame_buf: &mut Vec<u8>,
+    sender_id: u8,
+    receiver_id: u8,
+    body: &[u8],
+) -> Result<(), std::io::Error> {
+    encode_packet(sender_id, receiver_id, body, frame_buf);
+    trace!(frame = %Hex(frame_buf), "writing frame");
+    port.write_all(frame_buf)
+}
+
+/// calculate the SDSP CRC-16 of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0x0000, data)
 }
 
 //
 // Internal Functions
 //
-fn assemble_packet(pkg: &Packet) -> Vec<u8> {
-    let mut data: Vec<u8> = Vec::new();
-
-    // prologue
-    data.push(PKG_START_BYTE);
-    data.push(pkg.sender_id);
-    data.push(pkg.receiver_id);
-    data.push((pkg.body_len >> 8) as u8);
-    data.push((pkg.body_len & 0xFF) as u8);
-
-    // body
-    data.append(&mut pkg.body.clone());
 
-    // epilogue
-    //data.push((pkg.checksum >> 8) as u8);
-    //data.push((pkg.checksum & 0xFF) as u8);
-    data.push(0);
-    data.push(0);
-    data.push(PKG_END_BYTE);
+/// formats bytes as space-separated hex in trace events, without allocating
+pub(crate) struct Hex<'a>(pub &'a [u8]);
 
-    return data;
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
 }
 
-fn sdsp_crc16(data: &Vec<u8>) -> u16 {
-    let mut crc: u16 = 0x0000;
-    for byte in data {
-        crc ^= *byte as u16;
-        for _ in 0..8 {
+const CRC_TABLE: [u16; 256] = crc16_table();
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
             if (crc & 0x0001) != 0 {
-                crc >>= 1;
-                crc ^= 0x1021;
+                crc = (crc >> 1) ^ 0x1021;
             } else {
                 crc >>= 1;
             }
+            bit += 1;
         }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc = (crc >> 8) ^ CRC_TABLE[((crc ^ byte as u16) & 0xFF) as usize];
     }
 
-    return crc;
+    crc
 }
@@ -0,0 +1,162 @@
+use super::{decode_packet, ReadError, PKG_END_BYTE, PKG_OVERHEAD, PKG_START_BYTE};
+use std::collections::VecDeque;
+
+//
+// Resynchronising frame scanner
+//
+
+/// longest packet body the scanner accepts by default.
+/// a start byte followed by a longer declared length is treated as noise.
+pub const DEFAULT_MAX_BODY_LEN: usize = 255;
+
+/// garbage is reported in chunks of at most this many bytes
+const MAX_GARBAGE_LEN: usize = 64;
+
+/// something the scanner found in the byte stream
+#[derive(PartialEq, Debug, Clone)]
+pub enum ScanEvent {
+    /// a complete frame with valid checksum
+    Frame(Vec<u8>),
+
+    /// a complete frame that failed validation
+    Corrupt { frame: Vec<u8>, error: ReadError },
+
+    /// bytes that are not part of any frame
+    Garbage(Vec<u8>),
+}
+
+/// frame scanner for passively watching a bus.
+///
+/// unlike `Decoder`, the scanner does not commit to a start byte: if the bytes following
+/// a start byte do not form a frame, it resynchronises at the next start byte, so a
+/// single corrupted length or stray start byte does not swallow the frames after it.
+#[derive(Debug)]
+pub struct Scanner {
+    buf: Vec<u8>,
+    garbage: Vec<u8>,
+    events: VecDeque<ScanEvent>,
+    max_body_len: usize,
+}
+
+#[derive(PartialEq, Debug)]
+enum Candidate {
+    /// more bytes are needed to decide
+    Incomplete,
+
+    /// the start byte does not start a frame
+    NoFrame,
+
+    /// a frame of this many bytes, valid or not
+    Frame(usize, Result<(), ReadError>),
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scanner {
+    pub fn new() -> Scanner {
+        Scanner::with_max_body_len(DEFAULT_MAX_BODY_LEN)
+    }
+
+    pub fn with_max_body_len(max_body_len: usize) -> Scanner {
+        Scanner {
+            buf: Vec::new(),
+            garbage: Vec::new(),
+            events: VecDeque::new(),
+            max_body_len,
+        }
+    }
+
+    /// feed received bytes into the scanner
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.scan(false);
+    }
+
+    /// give up on a partially received frame, e.g. after the bus went idle.
+    /// its bytes are rescanned for frames and reported as garbage otherwise.
+    pub fn flush(&mut self) {
+        self.scan(true);
+        self.emit_garbage();
+    }
+
+    /// check if the scanner holds bytes that were not reported yet
+    pub fn is_pending(&self) -> bool {
+        !self.buf.is_empty() || !self.garbage.is_empty()
+    }
+
+    /// the next event found, oldest first
+    pub fn next_event(&mut self) -> Option<ScanEvent> {
+        self.events.pop_front()
+    }
+
+    fn scan(&mut self, flush: bool) {
+        let mut pos = 0;
+        while pos < self.buf.len() {
+            let rest = &self.buf[pos..];
+            if rest[0] != PKG_START_BYTE {
+                self.garbage.push(rest[0]);
+                pos += 1;
+                continue;
+            }
+
+            match self.candidate(rest) {
+                Candidate::Incomplete if !flush => break,
+                Candidate::Incomplete | Candidate::NoFrame => {
+                    // resynchronise at the next start byte
+                    self.garbage.push(PKG_START_BYTE);
+                    pos += 1;
+                }
+                Candidate::Frame(len, result) => {
+                    let frame = rest[..len].to_vec();
+                    self.emit_garbage();
+                    self.events.push_back(match result {
+                        Ok(()) => ScanEvent::Frame(frame),
+                        Err(error) => ScanEvent::Corrupt { frame, error },
+                    });
+                    pos += len;
+                }
+            }
+
+            if self.garbage.len() >= MAX_GARBAGE_LEN {
+                self.emit_garbage();
+            }
+        }
+
+        self.buf.drain(..pos);
+        if self.garbage.len() >= MAX_GARBAGE_LEN {
+            self.emit_garbage();
+        }
+    }
+
+    fn candidate(&self, data: &[u8]) -> Candidate {
+        if data.len() < 5 {
+            return Candidate::Incomplete;
+        }
+
+        let body_len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        if body_len > self.max_body_len {
+            return Candidate::NoFrame;
+        }
+
+        let len = body_len + PKG_OVERHEAD;
+        if data.len() < len {
+            return Candidate::Incomplete;
+        }
+        if data[len - 1] != PKG_END_BYTE {
+            return Candidate::NoFrame;
+        }
+
+        Candidate::Frame(len, decode_packet(&data[..len]).map(|_| ()))
+    }
+
+    fn emit_garbage(&mut self) {
+        if !self.garbage.is_empty() {
+            let garbage = std::mem::take(&mut self.garbage);
+            self.events.push_back(ScanEvent::Garbage(garbage));
+        }
+    }
+}
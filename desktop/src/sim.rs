@@ -0,0 +1,70 @@
+pub mod controller;
+pub mod i2c;
+
+use crate::transport::Transport;
+use controller::VirtualController;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+//
+// In-memory transport to a virtual controller
+//
+
+/// transport that delivers everything written to it to a `VirtualController`,
+/// and reads back the controller's responses.
+///
+/// the controller is shared, so tests can inspect and change its state
+/// while a `HostController` owns the transport.
+pub struct SimTransport {
+    controller: Arc<Mutex<VirtualController>>,
+    rx: VecDeque<u8>,
+    frame_buf: Vec<u8>,
+}
+
+impl SimTransport {
+    pub fn new(controller: Arc<Mutex<VirtualController>>) -> SimTransport {
+        SimTransport {
+            controller,
+            rx: VecDeque::new(),
+            frame_buf: Vec::new(),
+        }
+    }
+
+    /// the controller this transport is connected to
+    pub fn controller(&self) -> &Arc<Mutex<VirtualController>> {
+        &self.controller
+    }
+}
+
+impl Transport for SimTransport {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.frame_buf.clear();
+        self.controller
+            .lock()
+            .unwrap()
+            .receive(data, &mut self.frame_buf);
+        self.rx.extend(self.frame_buf.iter());
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // the controller answers synchronously, so nothing arrives while waiting
+        if self.rx.is_empty() {
+            std::thread::sleep(timeout);
+            return Ok(0);
+        }
+
+        let n = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.rx.clear();
+        Ok(())
+    }
+}
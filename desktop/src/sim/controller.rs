@@ -0,0 +1,622 @@
+use super::i2c::{I2CBus, SimulatedI2CBus};
+use crate::gpio::{
+    as_request_type, decode,
+    echo::{EchoRequest, EchoResponse, TYPE_ECHO},
+    error::{ErrorResponse, ERR_INVALID_PIN, ERR_INVALID_TYPE, ERR_MALFORMED_PACKET},
+    iic::write::{parse_result_code, IICWriteRequest, IICWriteResponse, TYPE_IIC_WRITE},
+    read::{ReadRequest, ReadResponse, TYPE_READ},
+    toggle::{ToggleRequest, ToggleResponse, TYPE_TOGGLE},
+    write::{WriteRequest, WriteResponse, TYPE_WRITE},
+    Encode,
+};
+use crate::sdsp;
+
+//
+// Board configuration, matching arduino.ino on an Arduino Uno / Nano
+//
+
+/// default device ID of the firmware
+pub const DEFAULT_DEVICE_ID: u8 = 0xCA;
+
+/// number of pins on the board (D0 - D13, A0 - A7)
+pub const PIN_COUNT: usize = 22;
+
+/// pin number of A0
+pub const A0: u8 = 14;
+const A7: u8 = 21;
+
+/// size of the firmware packet buffer, including the SDSP prologue and epilogue
+pub const PKG_BUFFER_LEN: usize = 32;
+
+fn is_digital_pin(pin: u8) -> bool {
+    (2..=13).contains(&pin)
+}
+
+fn is_analog_pin(pin: u8) -> bool {
+    (A0..A7).contains(&pin)
+}
+
+fn is_pwm_pin(pin: u8) -> bool {
+    matches!(pin, 3 | 5 | 6 | 9 | 10 | 11)
+}
+
+//
+// Pin model
+//
+
+/// pin mode, as set by `pinMode()`.
+/// the Uno has no INPUT_PULLDOWN, so the firmware falls back to INPUT.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PinMode {
+    Input,
+    InputPullup,
+    Output,
+}
+
+/// state of a single pin on the virtual controller
+#[derive(PartialEq, Debug, Clone)]
+pub struct PinState {
+    /// the mode last set by `pinMode()`
+    pub mode: PinMode,
+
+    /// the output latch (PORTx bit), set by `digitalWrite()` and `pinMode(INPUT_PULLUP)`
+    pub output: bool,
+
+    /// PWM duty cycle set by `analogWrite()`, while PWM is active on the pin
+    pub pwm: Option<u16>,
+
+    /// level driven onto the pin from outside. `None` if the pin is floating
+    pub input: Option<bool>,
+
+    /// value returned by `analogRead()`, 0 - 1023
+    pub analog_input: u16,
+}
+
+impl Default for PinState {
+    fn default() -> Self {
+        PinState {
+            mode: PinMode::Input,
+            output: false,
+            pwm: None,
+            input: None,
+            analog_input: 0,
+        }
+    }
+}
+
+impl PinState {
+    /// the level currently on the pin, without side effects.
+    /// floating inputs read LOW, unless the pullup is enabled.
+    pub fn level(&self) -> bool {
+        match self.mode {
+            PinMode::Output => self.output,
+            PinMode::InputPullup => self.input.unwrap_or(true),
+            PinMode::Input => self.input.unwrap_or(false),
+        }
+    }
+}
+
+//
+// SDSP receiver, matching sdsp_read_packet() in sdsp.h
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadResult {
+    Ok,
+    NoEnd,
+    ChecksumMismatch,
+    DataTooLong,
+    RecipientMismatch,
+}
+
+impl ReadResult {
+    fn reason(&self) -> &'static str {
+        match self {
+            ReadResult::Ok => "ok",
+            ReadResult::NoEnd => "missing end byte",
+            ReadResult::ChecksumMismatch => "checksum mismatch",
+            ReadResult::DataTooLong => "packet too long",
+            ReadResult::RecipientMismatch => "recipient mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RxState {
+    StartByte,
+    SenderID,
+    ReceiverID,
+    LengthMSB,
+    LengthLSB,
+    Body,
+    ChecksumMSB,
+    ChecksumLSB,
+    EndByte,
+
+    /// skip `remaining` bytes, then everything up to the next end byte
+    FastForward {
+        remaining: usize,
+        result: ReadResult,
+    },
+}
+
+//
+// Virtual controller
+//
+
+/// something the virtual controller did, reported to its observer
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// a command packet was received and answered with `responses` (zero or more SDSP frames)
+    Command {
+        from: u8,
+        body: &'a [u8],
+        responses: &'a [u8],
+    },
+
+    /// a packet was rejected by the SDSP layer and answered with `responses`
+    Rejected {
+        from: u8,
+        reason: &'static str,
+        responses: &'a [u8],
+    },
+}
+
+type Observer = Box<dyn FnMut(&Event) + Send>;
+
+/// in-process model of a controller running `arduino/arduino.ino`
+pub struct VirtualController {
+    id: u8,
+    pins: [PinState; PIN_COUNT],
+    i2c: Box<dyn I2CBus>,
+    observer: Option<Observer>,
+
+    // SDSP receiver state
+    state: RxState,
+    rx_buf: Vec<u8>,
+    body_len: usize,
+
+    // reused between responses
+    body_buf: Vec<u8>,
+    frame_buf: Vec<u8>,
+}
+
+impl Default for VirtualController {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEVICE_ID)
+    }
+}
+
+impl VirtualController {
+    /// create a controller with the given device ID and an empty `SimulatedI2CBus`
+    pub fn new(id: u8) -> VirtualController {
+        VirtualController {
+            id,
+            pins: Default::default(),
+            i2c: Box::new(SimulatedI2CBus::new()),
+            observer: None,
+            state: RxState::StartByte,
+            rx_buf: Vec::with_capacity(PKG_BUFFER_LEN),
+            body_len: 0,
+            body_buf: Vec::with_capacity(PKG_BUFFER_LEN),
+            frame_buf: Vec::with_capacity(PKG_BUFFER_LEN),
+        }
+    }
+
+    /// replace the I2C bus attached to the controller
+    pub fn with_i2c_bus(mut self, bus: impl I2CBus + 'static) -> VirtualController {
+        self.i2c = Box::new(bus);
+        self
+    }
+
+    /// call `observer` for every packet the controller handles
+    pub fn set_observer(&mut self, observer: impl FnMut(&Event) + Send + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// the device ID of the controller
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// state of a pin, or `None` if the pin does not exist
+    pub fn pin(&self, pin: u8) -> Option<&PinState> {
+        self.pins.get(pin as usize)
+    }
+
+    /// drive a pin from outside, or let it float with `None`
+    pub fn set_input(&mut self, pin: u8, level: Option<bool>) {
+        if let Some(state) = self.pins.get_mut(pin as usize) {
+            state.input = level;
+        }
+    }
+
+    /// set the value `analogRead()` returns for a pin (clamped to 0 - 1023)
+    pub fn set_analog_input(&mut self, pin: u8, value: u16) {
+        if let Some(state) = self.pins.get_mut(pin as usize) {
+            state.analog_input = value.min(1023);
+        }
+    }
+
+    /// feed bytes received over serial into the controller.
+    /// response frames are appended to `out`.
+    pub fn receive(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        for &byte in bytes {
+            if let Some(result) = self.push(byte) {
+                self.handle_read_result(result, out);
+            }
+        }
+    }
+
+    /// reset the controller, like a power cycle or the auto-reset when a serial port is opened.
+    /// all pins return to INPUT, any partially received packet is discarded.
+    /// externally driven pin levels are kept.
+    pub fn reset(&mut self) {
+        for pin in self.pins.iter_mut() {
+            *pin = PinState {
+                input: pin.input,
+                analog_input: pin.analog_input,
+                ..PinState::default()
+            };
+        }
+        self.state = RxState::StartByte;
+        self.rx_buf.clear();
+    }
+
+    //
+    // SDSP receiver
+    //
+    fn push(&mut self, byte: u8) -> Option<ReadResult> {
+        match self.state {
+            RxState::StartByte => {
+                // anything but a start byte is dropped (NO_START)
+                if byte == sdsp::PKG_START_BYTE {
+                    self.rx_buf.clear();
+                    self.rx_buf.push(byte);
+                    self.state = RxState::SenderID;
+                }
+            }
+            RxState::SenderID => {
+                self.rx_buf.push(byte);
+                self.state = RxState::ReceiverID;
+            }
+            RxState::ReceiverID => {
+                self.rx_buf.push(byte);
+                self.state = RxState::LengthMSB;
+            }
+            RxState::LengthMSB => {
+                self.rx_buf.push(byte);
+                self.state = RxState::LengthLSB;
+            }
+            RxState::LengthLSB => {
+                self.rx_buf.push(byte);
+                self.body_len = u16::from_be_bytes([self.rx_buf[3], byte]) as usize;
+
+                // ensure the packet will fit into the buffer, and is for us
+                let receiver_id = self.rx_buf[2];
+                self.state = if self.body_len + sdsp::PKG_OVERHEAD > PKG_BUFFER_LEN {
+                    self.fast_forward(ReadResult::DataTooLong)
+                } else if receiver_id != self.id && receiver_id != sdsp::BROADCAST_ID {
+                    self.fast_forward(ReadResult::RecipientMismatch)
+                } else if self.body_len == 0 {
+                    RxState::ChecksumMSB
+                } else {
+                    RxState::Body
+                };
+            }
+            RxState::Body => {
+                self.rx_buf.push(byte);
+                if self.rx_buf.len() == 5 + self.body_len {
+                    self.state = RxState::ChecksumMSB;
+                }
+            }
+            RxState::ChecksumMSB => {
+                self.rx_buf.push(byte);
+                self.state = RxState::ChecksumLSB;
+            }
+            RxState::ChecksumLSB => {
+                self.rx_buf.push(byte);
+                self.state = RxState::EndByte;
+            }
+            RxState::EndByte => {
+                self.rx_buf.push(byte);
+                self.state = RxState::StartByte;
+                if byte != sdsp::PKG_END_BYTE {
+                    return Some(ReadResult::NoEnd);
+                }
+
+                // validate the checksum
+                return match sdsp::decode_packet(&self.rx_buf) {
+                    Ok(_) => Some(ReadResult::Ok),
+                    Err(_) => Some(ReadResult::ChecksumMismatch),
+                };
+            }
+            RxState::FastForward { remaining, result } => {
+                if remaining > 0 {
+                    self.state = RxState::FastForward {
+                        remaining: remaining - 1,
+                        result,
+                    };
+                } else if byte == sdsp::PKG_END_BYTE {
+                    self.state = RxState::StartByte;
+                    return Some(result);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn fast_forward(&self, result: ReadResult) -> RxState {
+        RxState::FastForward {
+            remaining: self.body_len,
+            result,
+        }
+    }
+
+    fn handle_read_result(&mut self, result: ReadResult, out: &mut Vec<u8>) {
+        let sender_id = self.rx_buf[1];
+        let responses_start = out.len();
+        let frame = std::mem::take(&mut self.rx_buf);
+        let body = &frame[5..(5 + self.body_len).min(frame.len())];
+        let reason = match result {
+            ReadResult::Ok => {
+                self.handle_packet(body, sender_id, out);
+                None
+            }
+            ReadResult::NoEnd | ReadResult::ChecksumMismatch | ReadResult::DataTooLong => {
+                // send error response if package is malformed
+                self.send_error_response(ERR_MALFORMED_PACKET, sender_id, out);
+                Some(result.reason())
+            }
+            ReadResult::RecipientMismatch => Some(result.reason()),
+        };
+
+        if let Some(observer) = self.observer.as_mut() {
+            let responses = &out[responses_start..];
+            observer(&match reason {
+                None => Event::Command {
+                    from: sender_id,
+                    body,
+                    responses,
+                },
+                Some(reason) => Event::Rejected {
+                    from: sender_id,
+                    reason,
+                    responses,
+                },
+            });
+        }
+        self.rx_buf = frame;
+    }
+
+    //
+    // Command handling, matching handle_packet() in arduino.ino
+    //
+    fn handle_packet(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) {
+        // read packet type
+        let Some(&packet_type) = body.first() else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return;
+        };
+
+        // handle packet types.
+        // like the firmware, a command that fails with an error response is followed by
+        // a second ERR_MALFORMED_PACKET response.
+        let handled = match packet_type {
+            t if t == as_request_type!(TYPE_READ) => self.handle_read(body, from, out),
+            t if t == as_request_type!(TYPE_WRITE) => self.handle_write(body, from, out),
+            t if t == as_request_type!(TYPE_TOGGLE) => self.handle_toggle(body, from, out),
+            t if t == as_request_type!(TYPE_IIC_WRITE) => self.handle_iic_write(body, from, out),
+            t if t == as_request_type!(TYPE_ECHO) => self.handle_echo(body, from, out),
+            _ => {
+                self.send_error_response(ERR_INVALID_TYPE, from, out);
+                true
+            }
+        };
+
+        // default to malformed packet error
+        if !handled {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+        }
+    }
+
+    fn handle_read(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) -> bool {
+        // ensure packet length is correct
+        let Ok(request) = decode::<ReadRequest>(as_request_type!(TYPE_READ), body) else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return false;
+        };
+        let pin = request.pin;
+
+        // ensure pin is valid
+        let valid = if request.analog {
+            is_analog_pin(pin)
+        } else {
+            is_digital_pin(pin) || is_analog_pin(pin)
+        };
+        if !valid {
+            self.send_error_response(ERR_INVALID_PIN, from, out);
+            return false;
+        }
+
+        if request.direct {
+            // DIRECT mode cannot be used with analog, pullup or pulldown
+            if request.analog || request.pullup || request.pulldown {
+                self.send_error_response(ERR_INVALID_TYPE, from, out);
+                return false;
+            }
+        } else if request.analog {
+            // the firmware sets INPUT_PULLUP before analog reads
+            self.pin_mode(pin, PinMode::InputPullup);
+        } else {
+            // there is no INPUT_PULLDOWN on the Uno, and the pullup flag is not used by the firmware
+            self.pin_mode(pin, PinMode::Input);
+        }
+
+        // read pin value
+        let value = if request.analog {
+            let value = self.pins[pin as usize].analog_input;
+            if request.invert {
+                1024 - value
+            } else {
+                value
+            }
+        } else {
+            let value = self.digital_read(pin);
+            (value != request.invert) as u16
+        };
+
+        // send response
+        self.send_response(&ReadResponse { value }, from, out);
+        true
+    }
+
+    fn handle_write(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) -> bool {
+        // ensure packet length is correct
+        let Ok(request) = decode::<WriteRequest>(as_request_type!(TYPE_WRITE), body) else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return false;
+        };
+        let pin = request.pin;
+
+        // ensure pin is valid
+        let valid = if request.analog {
+            is_pwm_pin(pin)
+        } else {
+            is_digital_pin(pin) || is_analog_pin(pin)
+        };
+        if !valid {
+            self.send_error_response(ERR_INVALID_PIN, from, out);
+            return false;
+        }
+
+        // set pin mode and write pin value
+        self.pin_mode(pin, PinMode::Output);
+        if request.analog {
+            let value = if request.invert {
+                255u16.wrapping_sub(request.value)
+            } else {
+                request.value
+            };
+            self.analog_write(pin, value);
+        } else {
+            let value = (request.value != 0) != request.invert;
+            self.digital_write(pin, value);
+        }
+
+        // send response
+        self.send_response(&WriteResponse {}, from, out);
+        true
+    }
+
+    fn handle_toggle(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) -> bool {
+        // ensure packet length is correct
+        let Ok(request) = decode::<ToggleRequest>(as_request_type!(TYPE_TOGGLE), body) else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return false;
+        };
+        let pin = request.pin;
+
+        // ensure pin is valid
+        if !(is_digital_pin(pin) || is_analog_pin(pin)) {
+            self.send_error_response(ERR_INVALID_PIN, from, out);
+            return false;
+        }
+
+        // set pin mode, then read and invert the current value
+        self.pin_mode(pin, PinMode::Output);
+        let value = !self.digital_read(pin);
+        self.digital_write(pin, value);
+
+        // send response
+        self.send_response(
+            &ToggleResponse {
+                new_value: value as u8,
+            },
+            from,
+            out,
+        );
+        true
+    }
+
+    fn handle_iic_write(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) -> bool {
+        // ensure packet length looks valid
+        let Ok(request) = decode::<IICWriteRequest>(as_request_type!(TYPE_IIC_WRITE), body) else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return false;
+        };
+
+        // run the transaction
+        let result = self.i2c.write(request.address, &request.data, request.stop);
+
+        // send response
+        self.send_response(
+            &IICWriteResponse {
+                result_code: parse_result_code(result),
+            },
+            from,
+            out,
+        );
+        true
+    }
+
+    fn handle_echo(&mut self, body: &[u8], from: u8, out: &mut Vec<u8>) -> bool {
+        let Ok(request) = decode::<EchoRequest>(as_request_type!(TYPE_ECHO), body) else {
+            self.send_error_response(ERR_MALFORMED_PACKET, from, out);
+            return false;
+        };
+
+        // send the data back unchanged
+        self.send_response(&EchoResponse { data: request.data }, from, out);
+        true
+    }
+
+    //
+    // Arduino API
+    //
+    fn pin_mode(&mut self, pin: u8, mode: PinMode) {
+        let state = &mut self.pins[pin as usize];
+        state.mode = mode;
+        match mode {
+            PinMode::Input => state.output = false,
+            PinMode::InputPullup => state.output = true,
+            PinMode::Output => {}
+        }
+    }
+
+    fn digital_read(&mut self, pin: u8) -> bool {
+        // digitalRead() turns off PWM on the pin
+        let state = &mut self.pins[pin as usize];
+        state.pwm = None;
+        state.level()
+    }
+
+    fn digital_write(&mut self, pin: u8, value: bool) {
+        let state = &mut self.pins[pin as usize];
+        state.pwm = None;
+        state.output = value;
+    }
+
+    fn analog_write(&mut self, pin: u8, value: u16) {
+        // analogWrite() uses plain digital output for 0 and 255
+        match value {
+            0 => self.digital_write(pin, false),
+            255 => self.digital_write(pin, true),
+            _ => self.pins[pin as usize].pwm = Some(value),
+        }
+    }
+
+    //
+    // Responses
+    //
+    fn send_response(&mut self, response: &dyn Encode, to: u8, out: &mut Vec<u8>) {
+        self.body_buf.clear();
+        response.encode(&mut self.body_buf);
+        sdsp::encode_packet(self.id, to, &self.body_buf, &mut self.frame_buf);
+        out.extend_from_slice(&self.frame_buf);
+    }
+
+    fn send_error_response(&mut self, error_code: u8, to: u8, out: &mut Vec<u8>) {
+        self.send_response(&ErrorResponse { code: error_code }, to, out);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+//
+// Wire result codes, as returned by Wire.endTransmission()
+//
+pub const WIRE_SUCCESS: u8 = 0x00;
+pub const WIRE_NACK_ON_ADDRESS: u8 = 0x02;
+
+//
+// Simulated I2C bus
+//
+
+/// I2C bus attached to the virtual controller
+pub trait I2CBus: Send {
+    /// perform a write transaction, like `Wire.beginTransmission()`, `Wire.write()` and `Wire.endTransmission(stop)`.
+    /// returns the result code of `Wire.endTransmission()`.
+    fn write(&mut self, address: u8, data: &[u8], stop: bool) -> u8;
+}
+
+/// a single write transaction seen on a `SimulatedI2CBus`
+#[derive(PartialEq, Debug, Clone)]
+pub struct I2CTransaction {
+    pub address: u8,
+    pub data: Vec<u8>,
+    pub stop: bool,
+    pub result_code: u8,
+}
+
+/// I2C bus with a set of devices that acknowledge every write.
+/// writes to any other address are answered with a NACK on the address.
+///
+/// clones share the same bus, so a clone can be kept to inspect the bus after
+/// handing it to the virtual controller.
+#[derive(Clone, Default)]
+pub struct SimulatedI2CBus {
+    state: Arc<Mutex<BusState>>,
+}
+
+#[derive(Default)]
+struct BusState {
+    devices: BTreeSet<u8>,
+    transactions: Vec<I2CTransaction>,
+}
+
+impl SimulatedI2CBus {
+    pub fn new() -> SimulatedI2CBus {
+        SimulatedI2CBus::default()
+    }
+
+    /// attach a device with the given address to the bus
+    pub fn add_device(&self, address: u8) {
+        self.state.lock().unwrap().devices.insert(address);
+    }
+
+    /// remove the device with the given address from the bus
+    pub fn remove_device(&self, address: u8) {
+        self.state.lock().unwrap().devices.remove(&address);
+    }
+
+    /// all transactions seen on the bus so far, oldest first
+    pub fn transactions(&self) -> Vec<I2CTransaction> {
+        self.state.lock().unwrap().transactions.clone()
+    }
+}
+
+impl I2CBus for SimulatedI2CBus {
+    fn write(&mut self, address: u8, data: &[u8], stop: bool) -> u8 {
+        let mut state = self.state.lock().unwrap();
+        let result_code = if state.devices.contains(&address) {
+            WIRE_SUCCESS
+        } else {
+            WIRE_NACK_ON_ADDRESS
+        };
+
+        state.transactions.push(I2CTransaction {
+            address,
+            data: data.to_vec(),
+            stop,
+            result_code,
+        });
+        result_code
+    }
+}
@@ -0,0 +1,69 @@
+pub mod mock;
+pub mod pcap;
+pub mod record;
+pub mod replay;
+
+use serialport::SerialPort;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+//
+// Transport abstraction
+//
+
+/// byte stream that SDSP packets are sent and received over
+pub trait Transport: Send {
+    /// write all of `data`
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// read the bytes currently available into `buf`, waiting up to `timeout` for data to arrive.
+    /// returns `Ok(0)` if no data arrived in time.
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+
+    /// discard any bytes that were received but not read yet
+    fn clear_input(&mut self) -> io::Result<()>;
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        io::Write::write_all(self, data)
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if self.timeout() != timeout {
+            self.set_timeout(timeout)?;
+        }
+
+        match io::Read::read(self, buf) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            result => result,
+        }
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.clear(serialport::ClearBuffer::Input)
+            .map_err(io::Error::from)
+    }
+}
+
+//
+// Frame taps
+//
+
+/// direction of a frame, as seen from the host
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Direction {
+    /// written by the host
+    Sent,
+
+    /// read by the host
+    Received,
+}
+
+/// observer for every frame a `HostController` writes and reads.
+/// received frames are passed to the tap even if they failed to decode.
+/// a tap cannot fail the session it observes, so it has to drop its own errors.
+pub trait FrameTap: Send {
+    /// called with the raw bytes of a frame, starting at the start byte
+    fn frame(&mut self, direction: Direction, timestamp: SystemTime, frame: &[u8]);
+}
@@ -0,0 +1,276 @@
+use super::Transport;
+use crate::gpio::Encode;
+use crate::sdsp::{self, PacketRef};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+//
+// Expectation-based mock transport
+//
+
+type Matcher = Box<dyn Fn(&PacketRef) -> bool + Send>;
+type Responder = Box<dyn FnMut(&PacketRef) -> Option<Vec<u8>> + Send>;
+
+/// a frame the `MockTransport` expects to be written, and how to answer it
+pub struct Expectation {
+    description: String,
+    matcher: Matcher,
+    responder: Responder,
+}
+
+impl Expectation {
+    /// expect any frame
+    pub fn any() -> Expectation {
+        Expectation {
+            description: "any frame".to_string(),
+            matcher: Box::new(|_| true),
+            responder: Box::new(|_| None),
+        }
+    }
+
+    /// expect a frame with exactly this packet body
+    pub fn body(body: impl Into<Vec<u8>>) -> Expectation {
+        let body = body.into();
+        Expectation {
+            description: format!("body {:02x?}", body),
+            matcher: Box::new(move |packet| packet.body == body.as_slice()),
+            responder: Box::new(|_| None),
+        }
+    }
+
+    /// expect a frame carrying exactly this request
+    pub fn request(request: &dyn Encode) -> Expectation {
+        let mut body = Vec::new();
+        request.encode(&mut body);
+        Expectation {
+            description: format!("{:?}", request),
+            ..Expectation::body(body)
+        }
+    }
+
+    /// expect a frame matching a custom predicate
+    pub fn matching(
+        description: &str,
+        matcher: impl Fn(&PacketRef) -> bool + Send + 'static,
+    ) -> Expectation {
+        Expectation {
+            description: description.to_string(),
+            matcher: Box::new(matcher),
+            responder: Box::new(|_| None),
+        }
+    }
+
+    /// also expect the frame to be addressed to `receiver_id`
+    pub fn to(mut self, receiver_id: u8) -> Expectation {
+        let matcher = self.matcher;
+        self.description = format!("{} to {:#04x}", self.description, receiver_id);
+        self.matcher = Box::new(move |packet| packet.receiver_id == receiver_id && matcher(packet));
+        self
+    }
+
+    /// answer with a frame carrying this packet body
+    pub fn reply_body(mut self, body: impl Into<Vec<u8>>) -> Expectation {
+        let body = body.into();
+        self.responder = Box::new(move |_| Some(body.clone()));
+        self
+    }
+
+    /// answer with a frame carrying this response
+    pub fn reply(self, response: &dyn Encode) -> Expectation {
+        let mut body = Vec::new();
+        response.encode(&mut body);
+        self.reply_body(body)
+    }
+
+    /// answer with the packet body returned by `responder`, or not at all if it returns `None`
+    pub fn reply_with(
+        mut self,
+        responder: impl FnMut(&PacketRef) -> Option<Vec<u8>> + Send + 'static,
+    ) -> Expectation {
+        self.responder = Box::new(responder);
+        self
+    }
+}
+
+/// transport that records every frame written to it and answers them according to a queue of expectations.
+///
+/// clones share the same state, so a clone can be kept to add expectations and
+/// verify them after handing the transport to a `HostController`.
+#[derive(Clone)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+struct MockState {
+    device_id: u8,
+    expectations: VecDeque<Expectation>,
+    fallback: Option<Responder>,
+    frames: Vec<Vec<u8>>,
+    failures: Vec<String>,
+    decoder: sdsp::Decoder,
+    rx: VecDeque<u8>,
+    frame_buf: Vec<u8>,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    /// create a mock that answers as the controller with the firmware default ID
+    pub fn new() -> MockTransport {
+        MockTransport::with_device_id(crate::sim::controller::DEFAULT_DEVICE_ID)
+    }
+
+    /// create a mock that answers broadcast requests as the controller with `device_id`
+    pub fn with_device_id(device_id: u8) -> MockTransport {
+        MockTransport {
+            state: Arc::new(Mutex::new(MockState {
+                device_id,
+                expectations: VecDeque::new(),
+                fallback: None,
+                frames: Vec::new(),
+                failures: Vec::new(),
+                decoder: sdsp::Decoder::new(),
+                rx: VecDeque::new(),
+                frame_buf: Vec::new(),
+            })),
+        }
+    }
+
+    /// expect a frame, after all previously expected frames
+    pub fn expect(&self, expectation: Expectation) -> &MockTransport {
+        self.state
+            .lock()
+            .unwrap()
+            .expectations
+            .push_back(expectation);
+        self
+    }
+
+    /// answer frames that arrive while no expectation is queued, instead of failing
+    pub fn fallback(&self, responder: impl FnMut(&PacketRef) -> Option<Vec<u8>> + Send + 'static) {
+        self.state.lock().unwrap().fallback = Some(Box::new(responder));
+    }
+
+    /// every frame written so far, oldest first
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().frames.clone()
+    }
+
+    /// the packet bodies of every valid frame written so far, oldest first
+    pub fn bodies(&self) -> Vec<Vec<u8>> {
+        self.frames()
+            .iter()
+            .filter_map(|frame| sdsp::decode_packet(frame).ok())
+            .map(|packet| packet.body.to_vec())
+            .collect()
+    }
+
+    /// panic if a frame did not match its expectation, or if expected frames were never written
+    pub fn verify(&self) {
+        let state = self.state.lock().unwrap();
+        let mut problems = state.failures.clone();
+        problems.extend(
+            state
+                .expectations
+                .iter()
+                .map(|e| format!("expected {}, but it was never written", e.description)),
+        );
+
+        if !problems.is_empty() {
+            panic!("MockTransport: {}", problems.join("; "));
+        }
+    }
+}
+
+impl MockState {
+    fn handle_frame(&mut self) {
+        let packet = self.decoder.packet();
+        let index = self.frames.len() - 1;
+
+        // match the frame against the next expectation
+        let body = match self.expectations.pop_front() {
+            Some(mut expectation) => {
+                if (expectation.matcher)(&packet) {
+                    (expectation.responder)(&packet)
+                } else {
+                    self.failures.push(format!(
+                        "frame {} {:02x?} does not match expected {}",
+                        index, packet.body, expectation.description
+                    ));
+                    None
+                }
+            }
+            None => match self.fallback.as_mut() {
+                Some(fallback) => fallback(&packet),
+                None => {
+                    self.failures
+                        .push(format!("unexpected frame {} {:02x?}", index, packet.body));
+                    None
+                }
+            },
+        };
+
+        // answer as the addressed controller
+        if let Some(body) = body {
+            let sender_id = match packet.receiver_id {
+                sdsp::BROADCAST_ID => self.device_id,
+                receiver_id => receiver_id,
+            };
+            sdsp::encode_packet(sender_id, packet.sender_id, &body, &mut self.frame_buf);
+            self.rx.extend(self.frame_buf.iter());
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for &byte in data {
+            let Some(result) = state.decoder.push(byte) else {
+                continue;
+            };
+
+            // record the raw frame, valid or not
+            let frame = state.decoder.frame().to_vec();
+            state.frames.push(frame);
+            match result {
+                Ok(()) => state.handle_frame(),
+                Err(err) => {
+                    let index = state.frames.len() - 1;
+                    state
+                        .failures
+                        .push(format!("invalid frame {} written: {:?}", index, err));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        // replies are queued synchronously, so nothing arrives while waiting
+        if state.rx.is_empty() {
+            drop(state);
+            std::thread::sleep(timeout);
+            return Ok(0);
+        }
+
+        let n = buf.len().min(state.rx.len());
+        for (dst, src) in buf.iter_mut().zip(state.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().rx.clear();
+        Ok(())
+    }
+}
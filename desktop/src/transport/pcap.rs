@@ -0,0 +1,91 @@
+use super::{Direction, FrameTap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//
+// pcap export
+//
+// every frame is written as one pcap record, prefixed with a single direction byte:
+//
+//   [DIRECTION][FRAME...]
+//    1b         n bytes
+//
+// see `docs/sdsp.lua` for a Wireshark dissector.
+//
+
+/// link type of the capture, the first of the `DLT_USER` link types reserved for private use
+pub const LINKTYPE_USER0: u32 = 147;
+
+/// direction byte of frames sent by the host
+pub const PCAP_DIRECTION_SENT: u8 = 0x00;
+
+/// direction byte of frames received by the host
+pub const PCAP_DIRECTION_RECEIVED: u8 = 0x01;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535 + 1 + crate::sdsp::PKG_OVERHEAD as u32;
+
+/// frame tap that writes every frame to a pcap capture
+pub struct PcapWriter {
+    out: Box<dyn Write + Send>,
+}
+
+impl PcapWriter {
+    /// create a capture in a new file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<PcapWriter> {
+        PcapWriter::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// create a capture writing to `out`
+    pub fn new(out: impl Write + Send + 'static) -> io::Result<PcapWriter> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+
+        // global header, in native byte order as indicated by the magic number
+        out.write_all(&PCAP_MAGIC.to_ne_bytes())?;
+        out.write_all(&PCAP_VERSION_MAJOR.to_ne_bytes())?;
+        out.write_all(&PCAP_VERSION_MINOR.to_ne_bytes())?;
+        out.write_all(&0i32.to_ne_bytes())?; // THISZONE
+        out.write_all(&0u32.to_ne_bytes())?; // SIGFIGS
+        out.write_all(&PCAP_SNAPLEN.to_ne_bytes())?;
+        out.write_all(&LINKTYPE_USER0.to_ne_bytes())?;
+        out.flush()?;
+        Ok(PcapWriter { out })
+    }
+
+    fn write_record(
+        &mut self,
+        direction: Direction,
+        timestamp: SystemTime,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let timestamp = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let direction = match direction {
+            Direction::Sent => PCAP_DIRECTION_SENT,
+            Direction::Received => PCAP_DIRECTION_RECEIVED,
+        };
+        let len = (1 + frame.len()) as u32;
+
+        // record header
+        self.out
+            .write_all(&(timestamp.as_secs() as u32).to_ne_bytes())?;
+        self.out
+            .write_all(&timestamp.subsec_micros().to_ne_bytes())?;
+        self.out.write_all(&len.to_ne_bytes())?; // INCL_LEN
+        self.out.write_all(&len.to_ne_bytes())?; // ORIG_LEN
+
+        // record data
+        self.out.write_all(&[direction])?;
+        self.out.write_all(frame)?;
+        self.out.flush()
+    }
+}
+
+impl FrameTap for PcapWriter {
+    fn frame(&mut self, direction: Direction, timestamp: SystemTime, frame: &[u8]) {
+        let _ = self.write_record(direction, timestamp, frame);
+    }
+}
@@ -0,0 +1,161 @@
+use super::{Direction, FrameTap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//
+// Session recordings
+//
+// a recording is a text file with one frame per line:
+//
+//   # agpio recording v1
+//   1697040000.123456 tx 7b aa ff 00 03 01 0d 00 13 5b 7d
+//   1697040000.125012 rx 7b ca aa 00 02 81 01 12 5c 7d
+//
+// the first column is the time since the unix epoch, in seconds with microsecond precision.
+// lines starting with '#' and empty lines are ignored.
+//
+
+/// first line of every recording
+pub const RECORDING_HEADER: &str = "# agpio recording v1";
+
+/// a single frame of a recording
+#[derive(PartialEq, Debug, Clone)]
+pub struct RecordedFrame {
+    /// time since the unix epoch
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub frame: Vec<u8>,
+}
+
+/// a recorded session, as written by `Recorder`
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Recording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    /// load a recording from a file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Recording> {
+        Recording::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// parse a recording from its text form
+    pub fn parse(text: &str) -> io::Result<Recording> {
+        let mut frames = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let frame = parse_line(line).map_err(|msg| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, msg),
+                )
+            })?;
+            frames.push(frame);
+        }
+
+        Ok(Recording { frames })
+    }
+
+    /// the frames sent by the host, oldest first
+    pub fn sent(&self) -> impl Iterator<Item = &RecordedFrame> {
+        self.frames
+            .iter()
+            .filter(|frame| frame.direction == Direction::Sent)
+    }
+}
+
+/// frame tap that writes every frame to a recording
+pub struct Recorder {
+    out: Box<dyn Write + Send>,
+}
+
+impl Recorder {
+    /// create a recorder writing to a new file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Recorder> {
+        Recorder::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// create a recorder writing to `out`
+    pub fn new(out: impl Write + Send + 'static) -> io::Result<Recorder> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        writeln!(out, "{}", RECORDING_HEADER)?;
+        out.flush()?;
+        Ok(Recorder { out })
+    }
+}
+
+impl FrameTap for Recorder {
+    fn frame(&mut self, direction: Direction, timestamp: SystemTime, frame: &[u8]) {
+        let line = format_line(&RecordedFrame {
+            timestamp: timestamp.duration_since(UNIX_EPOCH).unwrap_or_default(),
+            direction,
+            frame: frame.to_vec(),
+        });
+
+        // flush every frame, so the recording survives a crash
+        let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+    }
+}
+
+//
+// Internal Functions
+//
+
+fn format_line(frame: &RecordedFrame) -> String {
+    let direction = match frame.direction {
+        Direction::Sent => "tx",
+        Direction::Received => "rx",
+    };
+    let bytes: Vec<String> = frame.frame.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}.{:06} {} {}",
+        frame.timestamp.as_secs(),
+        frame.timestamp.subsec_micros(),
+        direction,
+        bytes.join(" ")
+    )
+}
+
+fn parse_line(line: &str) -> Result<RecordedFrame, String> {
+    let mut fields = line.split_whitespace();
+
+    // timestamp
+    let timestamp = fields.next().ok_or("missing timestamp")?;
+    let (secs, micros) = timestamp.split_once('.').unwrap_or((timestamp, "0"));
+    let invalid_timestamp = || format!("invalid timestamp '{}'", timestamp);
+    let secs: u64 = secs.parse().map_err(|_| invalid_timestamp())?;
+    if micros.len() > 6 {
+        return Err(invalid_timestamp());
+    }
+    let micros: u32 = format!("{:0<6}", micros)
+        .parse()
+        .map_err(|_| invalid_timestamp())?;
+
+    // direction
+    let direction = match fields.next() {
+        Some("tx") => Direction::Sent,
+        Some("rx") => Direction::Received,
+        Some(other) => return Err(format!("invalid direction '{}', expected tx or rx", other)),
+        None => return Err("missing direction".to_string()),
+    };
+
+    // frame bytes
+    let frame = fields
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("invalid byte '{}'", byte)))
+        .collect::<Result<Vec<u8>, String>>()?;
+    if frame.is_empty() {
+        return Err("missing frame bytes".to_string());
+    }
+
+    Ok(RecordedFrame {
+        timestamp: Duration::new(secs, micros * 1000),
+        direction,
+        frame,
+    })
+}
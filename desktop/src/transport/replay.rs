@@ -0,0 +1,218 @@
+use super::record::{RecordedFrame, Recording};
+use super::{Direction, Transport};
+use crate::sdsp::{self, ReadError};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//
+// Replay of recorded sessions
+//
+
+/// transport that plays back a recording.
+/// every frame written is checked against the next recorded sent frame,
+/// and answered with the frames that were received after it.
+///
+/// clones share the same state, so a clone can be kept to verify the replay
+/// after handing the transport to a `HostController`.
+#[derive(Clone)]
+pub struct ReplayTransport {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+struct ReplayState {
+    frames: VecDeque<RecordedFrame>,
+    written: usize,
+    failures: Vec<String>,
+    decoder: sdsp::Decoder,
+    rx: VecDeque<u8>,
+}
+
+impl ReplayTransport {
+    pub fn new(recording: Recording) -> ReplayTransport {
+        ReplayTransport {
+            state: Arc::new(Mutex::new(ReplayState {
+                frames: recording.frames.into(),
+                written: 0,
+                failures: Vec::new(),
+                decoder: sdsp::Decoder::new(),
+                rx: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// number of recorded sent frames that were not written yet
+    pub fn remaining(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .frames
+            .iter()
+            .filter(|frame| frame.direction == Direction::Sent)
+            .count()
+    }
+
+    /// panic if a written frame differs from the recording, or if recorded frames were never written
+    pub fn verify(&self) {
+        let remaining = self.remaining();
+        let state = self.state.lock().unwrap();
+        let mut problems = state.failures.clone();
+        if remaining > 0 {
+            problems.push(format!("{} recorded frames were never written", remaining));
+        }
+
+        if !problems.is_empty() {
+            panic!("ReplayTransport: {}", problems.join("; "));
+        }
+    }
+}
+
+impl ReplayState {
+    fn handle_frame(&mut self) {
+        let index = self.written;
+        self.written += 1;
+
+        // responses recorded before the first request are stale, skip them
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| frame.direction == Direction::Received)
+        {
+            self.frames.pop_front();
+        }
+
+        let Some(expected) = self.frames.pop_front() else {
+            self.failures.push(format!(
+                "unexpected frame {} {:02x?}, the recording has ended",
+                index,
+                self.decoder.frame()
+            ));
+            return;
+        };
+        if expected.frame != self.decoder.frame() {
+            self.failures.push(format!(
+                "frame {} {:02x?} does not match recorded {:02x?}",
+                index,
+                self.decoder.frame(),
+                expected.frame
+            ));
+        }
+
+        // answer with everything received until the next request
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| frame.direction == Direction::Received)
+        {
+            let frame = self.frames.pop_front().unwrap();
+            self.rx.extend(frame.frame);
+        }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for &byte in data {
+            if state.decoder.push(byte).is_some() {
+                state.handle_frame();
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        // responses are queued synchronously, so nothing arrives while waiting
+        if state.rx.is_empty() {
+            drop(state);
+            std::thread::sleep(timeout);
+            return Ok(0);
+        }
+
+        let n = buf.len().min(state.rx.len());
+        for (dst, src) in buf.iter_mut().zip(state.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().rx.clear();
+        Ok(())
+    }
+}
+
+//
+// Re-sending recorded sessions
+//
+
+/// result of re-sending a single recorded frame
+#[derive(PartialEq, Debug, Clone)]
+pub struct ResendOutcome {
+    /// the frame that was re-sent
+    pub request: Vec<u8>,
+
+    /// the first frame received after the request in the recording, if any
+    pub expected: Option<Vec<u8>>,
+
+    /// the frame received now, if any
+    pub received: Option<Vec<u8>>,
+}
+
+impl ResendOutcome {
+    /// check if the device answered exactly like in the recording
+    pub fn matches(&self) -> bool {
+        self.expected == self.received
+    }
+}
+
+/// re-send every frame the host sent in `recording` to a live device, and collect the responses.
+/// if `paced` is set, the frames are sent with the same delays between them as recorded.
+pub fn resend(
+    recording: &Recording,
+    port: &mut dyn Transport,
+    timeout: Duration,
+    paced: bool,
+) -> io::Result<Vec<ResendOutcome>> {
+    let mut decoder = sdsp::Decoder::new();
+    let mut outcomes = Vec::new();
+    let start = Instant::now();
+    let first_timestamp = recording.frames.first().map(|frame| frame.timestamp);
+
+    for (i, frame) in recording.frames.iter().enumerate() {
+        if frame.direction != Direction::Sent {
+            continue;
+        }
+
+        // wait until the frame is due
+        if let (true, Some(first_timestamp)) = (paced, first_timestamp) {
+            let due = frame.timestamp.saturating_sub(first_timestamp);
+            std::thread::sleep(due.saturating_sub(start.elapsed()));
+        }
+
+        // send the recorded frame as-is
+        let _ = port.clear_input();
+        port.write_all(&frame.frame)?;
+
+        // read the response, addressed to whoever sent the request
+        let own_id = frame.frame.get(1).copied().unwrap_or(0);
+        let received = match sdsp::read_packet(port, &mut decoder, own_id, timeout) {
+            Err(ReadError::Timeout) => None,
+            _ => Some(decoder.frame().to_vec()),
+        };
+
+        let expected = recording.frames[i + 1..]
+            .first()
+            .filter(|frame| frame.direction == Direction::Received)
+            .map(|frame| frame.frame.clone());
+        outcomes.push(ResendOutcome {
+            request: frame.frame.clone(),
+            expected,
+            received,
+        });
+    }
+
+    Ok(outcomes)
+}
@@ -0,0 +1,1012 @@
+#![cfg(target_os = "linux")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// agpio-sim running on a pty, killed when dropped
+struct Simulator {
+    child: Child,
+    port: String,
+}
+
+impl Simulator {
+    fn start(args: &[&str]) -> Simulator {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_agpio-sim"))
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // the simulator prints the pty path first
+        let mut port = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut port)
+            .unwrap();
+        Simulator {
+            child,
+            port: port.trim().to_string(),
+        }
+    }
+
+    fn agpio(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .arg(&self.port)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// run agpio with `input` written to its stdin
+    fn agpio_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .arg(&self.port)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+fn write_read_toggle() {
+    let sim = Simulator::start(&[]);
+
+    let write = sim.agpio(&["write", "13", "1"]);
+    assert!(write.status.success());
+    assert_eq!(stdout(&write), "1");
+
+    // digital reads exit with the pin value
+    let read = sim.agpio(&["read", "13", "--direct"]);
+    assert_eq!(stdout(&read), "1");
+    assert_eq!(read.status.code(), Some(1));
+
+    let toggle = sim.agpio(&["--no-exit-code", "toggle", "13"]);
+    assert_eq!(stdout(&toggle), "0");
+    assert_eq!(toggle.status.code(), Some(0));
+}
+
+#[test]
+fn errors_exit_with_category_code() {
+    let sim = Simulator::start(&[]);
+
+    let read = sim.agpio(&["read", "1"]);
+    assert_eq!(read.status.code(), Some(15));
+
+    // nobody answers 203
+    let timeout = sim.agpio(&["--retries", "0", "203", "toggle", "13"]);
+    assert_eq!(timeout.status.code(), Some(12));
+
+    let port = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .args(["/dev/agpio-missing", "toggle", "13"])
+        .output()
+        .unwrap();
+    assert_eq!(port.status.code(), Some(11));
+}
+
+#[test]
+fn analog_reads_do_not_exit_with_their_value() {
+    // 12 would be the exit code of a timeout
+    let inputs = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("analog.inputs");
+    std::fs::write(&inputs, "analog A0 12\n").unwrap();
+    let sim = Simulator::start(&["--inputs", inputs.to_str().unwrap()]);
+
+    // the inputs are applied once the simulator is running
+    let read = (0..50)
+        .map(|_| sim.agpio(&["read", "A0", "--analog"]))
+        .find(|read| stdout(read) == "12")
+        .expect("the analog input was never applied");
+    assert_eq!(read.status.code(), Some(0));
+}
+
+#[test]
+fn i2c_write() {
+    let sim = Simulator::start(&["--i2c-device", "0x27"]);
+
+    assert!(sim
+        .agpio(&["i2c", "39", "write", "1", "2"])
+        .status
+        .success());
+    assert_eq!(
+        sim.agpio(&["i2c", "40", "write", "1"]).status.code(),
+        Some(16)
+    );
+}
+
+#[test]
+fn retries_recover_from_corrupted_responses() {
+    let sim = Simulator::start(&["--corrupt-rate", "0.3", "--seed", "7"]);
+
+    for _ in 0..5 {
+        let write = sim.agpio(&["--retries", "10", "write", "13", "1"]);
+        assert!(write.status.success());
+    }
+}
+
+#[test]
+fn verbose_logging_and_hexdump() {
+    let sim = Simulator::start(&[]);
+
+    // nobody answers 203, so the request is retried
+    let toggle = sim.agpio(&["-v", "--hexdump", "--retries", "1", "203", "toggle", "13"]);
+    assert_eq!(toggle.status.code(), Some(12));
+    let stderr = String::from_utf8_lossy(&toggle.stderr);
+    assert!(stderr.contains("retrying request error=SDSPError { kind: Timeout } retry=1"));
+    assert!(stderr.contains(">> sent 10 bytes\n0000  7b aa cb 00 02 03 0d"));
+
+    // frames are only logged at -vv
+    assert!(!stderr.contains("writing frame"));
+    let toggle = sim.agpio(&["-vv", "toggle", "13"]);
+    assert!(String::from_utf8_lossy(&toggle.stderr).contains("frame received"));
+}
+
+#[test]
+fn encode_and_decode_without_port() {
+    let agpio = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let encode = agpio(&["encode", "read", "13", "--pullup"]);
+    assert!(encode.status.success());
+    assert_eq!(stdout(&encode), "7b aa ff 00 03 01 0d 01 09 a1 7d");
+
+    let decode = agpio(&["decode", "7b aa ca 00 03 01 0d 00 0b 8c 7d"]);
+    assert!(decode.status.success());
+    assert!(stdout(&decode).ends_with("=> 0xaa -> 0xca: read digital pin 13"));
+
+    // corrupted checksum
+    let decode = agpio(&["decode", "7baaca0003010d000b8d7d"]);
+    assert_eq!(decode.status.code(), Some(10));
+    assert!(stdout(&decode).contains("checksum: INVALID, expected 0b 8c"));
+
+    // either case of the prefix, and commas with spaces
+    let decode = agpio(&["decode", "0X7B 0xaa, 0xca 00 03 01 0d 00 0b 8c 7d"]);
+    assert!(decode.status.success());
+
+    for invalid in ["aé1", "+f", "7b a", "0x0x7b", "0x"] {
+        let decode = agpio(&["decode", invalid]);
+        assert_eq!(decode.status.code(), Some(10), "accepted: {}", invalid);
+    }
+}
+
+#[test]
+fn dry_run_does_not_send() {
+    let sim = Simulator::start(&[]);
+
+    let write = sim.agpio(&["--dry-run", "write", "13", "1"]);
+    assert!(write.status.success());
+    assert_eq!(stdout(&write), "7b aa ff 00 05 02 0d 00 01 00 07 7d 7d");
+
+    // commands that send many requests refuse to run
+    let bench = sim.agpio(&["--dry-run", "bench", "--iterations", "1"]);
+    assert_eq!(bench.status.code(), Some(2));
+    let linktest = sim.agpio(&["--dry-run", "linktest", "--iterations", "1"]);
+    assert_eq!(linktest.status.code(), Some(2));
+
+    let read = sim.agpio(&["--no-exit-code", "read", "13", "--direct"]);
+    assert_eq!(stdout(&read), "0");
+}
+
+#[test]
+fn taps_are_refused_without_host_controller() {
+    let sim = Simulator::start(&[]);
+    let tmp = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let recording = tmp.join("refused.rec");
+    let capture = tmp.join("refused.pcap");
+
+    // monitor and replay use the port directly, so they would record or capture nothing
+    for (flag, path) in [("--record", &recording), ("--pcap", &capture)] {
+        let path = path.to_str().unwrap();
+        let monitor = sim.agpio(&[flag, path, "monitor"]);
+        let replay = sim.agpio(&[flag, path, "replay", path]);
+        for output in [monitor, replay] {
+            assert_eq!(output.status.code(), Some(2));
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(&format!("cannot be used with {}", flag)));
+        }
+    }
+    let monitor = sim.agpio(&["--hexdump", "monitor"]);
+    assert_eq!(monitor.status.code(), Some(2));
+}
+
+#[test]
+fn replay_exits_with_mismatch_code() {
+    let sim = Simulator::start(&[]);
+    let recording = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mismatch.rec");
+    let recording = recording.to_str().unwrap();
+
+    let read = sim.agpio(&["--record", recording, "read", "13", "--direct"]);
+    assert_eq!(read.status.code(), Some(0));
+    let replay = sim.agpio(&["replay", recording]);
+    assert!(replay.status.success());
+
+    // the pin reads differently than recorded
+    assert!(sim.agpio(&["write", "13", "1"]).status.success());
+    let replay = sim.agpio(&["replay", recording]);
+    assert_eq!(replay.status.code(), Some(18));
+    assert!(stdout(&replay).ends_with("0 of 1 responses matched the recording"));
+}
+
+#[test]
+fn raw_command() {
+    let sim = Simulator::start(&[]);
+
+    let toggle = sim.agpio(&["raw", "--type", "0x03", "--body", "0d"]);
+    assert!(toggle.status.success());
+    assert_eq!(stdout(&toggle), "01");
+
+    let unknown = sim.agpio(&["raw", "--type", "0x10", "--body", "01", "02"]);
+    assert_eq!(unknown.status.code(), Some(14));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("(invalid packet type)"));
+}
+
+#[test]
+fn user_defined_commands() {
+    let spec = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("commands.toml");
+    std::fs::write(
+        &spec,
+        "[[command]]\nname = \"servo\"\ntype = 0x10\nrequest = [\n    { name = \"pin\", type = \"u8\" },\n    { name = \"slow\", type = \"flags\", flags = { slow = 0 } },\n]\n",
+    )
+    .unwrap();
+    let agpio = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .arg("--commands")
+            .arg(&spec)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let encode = agpio(&["encode", "servo", "9", "--slow"]);
+    assert!(encode.status.success());
+    let frame = stdout(&encode);
+    assert!(frame.starts_with("7b aa ff 00 03 10 09 01"));
+
+    let decode = agpio(&["decode", &frame]);
+    assert!(decode.status.success());
+    assert!(stdout(&decode).ends_with("=> 0xaa -> 0xff: servo request: pin = 9, slow = slow"));
+
+    let invalid = agpio(&["encode", "servo", "256"]);
+    assert_eq!(invalid.status.code(), Some(10));
+}
+
+#[test]
+fn bench_reports_every_operation() {
+    let sim = Simulator::start(&[]);
+
+    let bench = sim.agpio(&["bench", "-n", "5", "--json"]);
+    assert!(bench.status.success());
+    let results: serde_json::Value = serde_json::from_slice(&bench.stdout).unwrap();
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 6);
+    for result in results {
+        assert_eq!(result["ops"], 5);
+        assert_eq!(result["errors"], 0);
+        assert!(result["min_us"].as_f64().unwrap() <= result["p50_us"].as_f64().unwrap());
+        assert!(result["p99_us"].as_f64().unwrap() <= result["max_us"].as_f64().unwrap());
+    }
+}
+
+#[test]
+fn linktest_finds_largest_body() {
+    let sim = Simulator::start(&[]);
+
+    let linktest = sim.agpio(&["linktest", "-n", "4", "--seed", "1"]);
+    assert!(linktest.status.success());
+    let report = stdout(&linktest);
+    assert!(report.contains("stopped at 25 bytes: no echo came back intact"));
+    assert!(report.contains("crc failure rate: 0.00%"));
+    assert!(report.ends_with("largest reliable body size: 24 bytes"));
+}
+
+#[test]
+fn shell_sends_every_line() {
+    let sim = Simulator::start(&[]);
+
+    let input =
+        "write 13 1\n# a comment\nread 13 --direct\nread 1\nbogus\ntoggle A0\nexit\ntoggle 13\n";
+    let shell = sim.agpio_with_input(&["shell"], input);
+    assert!(shell.status.success());
+
+    // errors do not end the shell, exit does
+    assert_eq!(stdout(&shell), "1\n1\n1");
+    let stderr = String::from_utf8_lossy(&shell.stderr);
+    assert!(stderr.contains("the pin number is invalid"));
+    assert!(stderr.contains("unrecognized subcommand 'bogus'"));
+}
+
+#[test]
+fn run_script() {
+    let sim = Simulator::start(&[]);
+    let script = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("script.agpio");
+    let run = |source: &str| {
+        std::fs::write(&script, source).unwrap();
+        sim.agpio(&["run", script.to_str().unwrap()])
+    };
+
+    let ok = run("# toggle three times\nrepeat 3 {\n    toggle 13\n    sleep 1ms\n}\n$v = read 13 --direct\nif $v == 1 {\n    write 13 0\n} else {\n    write 13 $v\n}\n");
+    assert!(ok.status.success());
+    assert_eq!(stdout(&ok), "1\n0\n1\n0");
+
+    // errors report the line number and exit with the code of their category,
+    // parse errors before anything is sent
+    let failed = run("toggle 13\nread 1\ntoggle 13\n");
+    assert_eq!(failed.status.code(), Some(15));
+    assert_eq!(stdout(&failed), "1");
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("line 2: the pin number is invalid"));
+
+    let invalid = run("toggle 13\nrepeat 2 {\n    sleep 1\n}\n");
+    assert_eq!(invalid.status.code(), Some(10));
+    assert_eq!(stdout(&invalid), "");
+    assert!(String::from_utf8_lossy(&invalid.stderr).contains("line 3: invalid duration '1'"));
+}
+
+#[test]
+fn rpc_answers_every_line() {
+    let sim = Simulator::start(&[]);
+
+    let input = [
+        r#"{"id":1,"op":"write","pin":13,"value":1}"#,
+        r#"{"id":"two","op":"read","pin":13,"direct":true}"#,
+        r#"{"id":3,"op":"toggle","pin":1}"#,
+        r#"{"id":4,"op":"blink","pin":13}"#,
+        "not json",
+    ]
+    .join("\n");
+    let rpc = sim.agpio_with_input(&["rpc"], &input);
+    assert!(rpc.status.success());
+
+    let responses: Vec<serde_json::Value> = rpc
+        .stdout
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(responses.len(), 5);
+    assert_eq!(responses[0], serde_json::json!({"id": 1, "ok": true}));
+    assert_eq!(
+        responses[1],
+        serde_json::json!({"id": "two", "ok": true, "value": 1})
+    );
+    assert_eq!(responses[2]["ok"], false);
+    assert_eq!(responses[2]["error"]["category"], "invalid_pin");
+    assert_eq!(responses[3]["id"], 4);
+    assert_eq!(responses[3]["error"]["category"], "invalid_input");
+    assert_eq!(responses[4]["id"], serde_json::Value::Null);
+}
+
+#[test]
+fn json_output() {
+    let sim = Simulator::start(&[]);
+    let json = |args: &[&str]| {
+        let output = sim.agpio(&[&["--output", "json"], args].concat());
+        let object: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (output.status.code(), object)
+    };
+
+    let (code, write) = json(&["write", "13", "1"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(write["ok"], true);
+    assert_eq!(write["device"], 0xff);
+    assert_eq!(write["op"], "write");
+    assert_eq!((&write["pin"], &write["value"]), (&13.into(), &1.into()));
+    assert!(write["latency_us"].as_u64().unwrap() > 0);
+
+    // digital values are still the exit code
+    let (code, read) = json(&["202", "read", "13", "--direct"]);
+    assert_eq!(code, Some(1));
+    assert_eq!((&read["device"], &read["value"]), (&202.into(), &1.into()));
+
+    let (code, invalid) = json(&["read", "1"]);
+    assert_eq!(code, Some(15));
+    assert_eq!(invalid["ok"], false);
+    assert_eq!(invalid["pin"], 1);
+    assert_eq!(
+        invalid["error"],
+        serde_json::json!({
+            "category": "invalid_pin",
+            "code": 3,
+            "message": "the pin number is invalid for the requested operation",
+        })
+    );
+}
+
+#[test]
+fn config_names_devices_and_pins() {
+    let sim = Simulator::start(&[]);
+    let config = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "default_device = \"sim\"\n\n[devices.sim]\nport = \"{}\"\ntarget_id = 202\n\n[pins.led]\ndevice = \"sim\"\npin = 13\n\n[pins.dimmer]\npin = \"A1\"\nanalog = true\n",
+            sim.port
+        ),
+    )
+    .unwrap();
+    let agpio = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .arg("--config")
+            .arg(&config)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // the port and target of the default device are used
+    let write = agpio(&["write", "led", "1"]);
+    assert!(write.status.success());
+    let read = agpio(&["sim", "read", "led", "--direct"]);
+    assert_eq!(read.status.code(), Some(1));
+
+    let json = agpio(&["--output", "json", "toggle", "led"]);
+    let object: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(
+        (&object["device"], &object["value"]),
+        (&202.into(), &0.into())
+    );
+    assert_eq!(
+        (&object["pin"], &object["name"]),
+        (&13.into(), &"led".into())
+    );
+
+    // the flags of a named pin are defaults for the request
+    let encode = agpio(&["encode", "write", "dimmer", "128"]);
+    assert!(encode.status.success());
+    assert!(stdout(&encode).starts_with("7b aa ca 00 05 02 0f 00 80 01"));
+
+    let unknown = agpio(&["read", "lamp"]);
+    assert_eq!(unknown.status.code(), Some(10));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("named pin"));
+}
+
+#[test]
+fn policy_refuses_requests() {
+    let sim = Simulator::start(&[]);
+    let policy = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("policy.toml");
+    std::fs::write(&policy, "[pins.13]\naccess = \"output\"\nmax = 1\n").unwrap();
+    let agpio =
+        |args: &[&str]| sim.agpio(&[&["--policy", policy.to_str().unwrap()], args].concat());
+
+    assert!(agpio(&["write", "13", "1"]).status.success());
+    let out_of_range = agpio(&["write", "13", "2", "--analog"]);
+    assert_eq!(out_of_range.status.code(), Some(19));
+    assert!(String::from_utf8_lossy(&out_of_range.stderr).contains("out of range for pin 13"));
+
+    // reading would make the output an input, unless the output is read directly
+    assert_eq!(agpio(&["read", "13"]).status.code(), Some(19));
+    assert_eq!(agpio(&["read", "13", "--direct"]).status.code(), Some(1));
+}
+
+#[test]
+fn requests_go_through_the_daemon() {
+    let sim = Simulator::start(&[]);
+    let dir = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let config = dir.join("daemon.toml");
+    let socket = dir.join("agpiod-cli.sock");
+    std::fs::write(
+        &config,
+        format!(
+            "default_device = \"sim\"\n\n[devices.sim]\nport = \"{}\"\n",
+            sim.port
+        ),
+    )
+    .unwrap();
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_agpiod"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--socket")
+        .arg(&socket)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // the daemon prints the socket once it is listening
+    let mut listening = String::new();
+    BufReader::new(daemon.stdout.take().unwrap())
+        .read_line(&mut listening)
+        .unwrap();
+    assert_eq!(listening.trim(), socket.to_str().unwrap());
+
+    let agpio = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_agpio"))
+            .env("AGPIOD_SOCKET", &socket)
+            .arg("--config")
+            .arg(&config)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let write = agpio(&["-v", "write", "13", "1"]);
+    assert!(write.status.success());
+    assert!(String::from_utf8_lossy(&write.stderr).contains("sending through agpiod"));
+
+    // concurrent invocations are serialized by the daemon
+    std::thread::scope(|scope| {
+        let toggles: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| agpio(&["--no-exit-code", "toggle", "13"])))
+            .collect();
+        for toggle in toggles {
+            assert!(toggle.join().unwrap().status.success());
+        }
+    });
+    assert_eq!(agpio(&["read", "13", "--direct"]).status.code(), Some(1));
+
+    // so are requests to the port of the device, and the requests of rpc
+    let port = agpio(&["-v", &sim.port, "toggle", "13"]);
+    assert_eq!(port.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&port.stderr).contains("sending through agpiod"));
+
+    let mut rpc = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .env("AGPIOD_SOCKET", &socket)
+        .arg("--config")
+        .arg(&config)
+        .args(["-v", &sim.port, "rpc"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(
+        rpc.stdin.take().unwrap(),
+        r#"{{"id":1,"op":"toggle","pin":13}}"#
+    )
+    .unwrap();
+    let rpc = rpc.wait_with_output().unwrap();
+    assert!(stdout(&rpc).contains(r#""value":1"#));
+    assert!(String::from_utf8_lossy(&rpc.stderr).contains("sending through agpiod"));
+
+    let mut serve = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .env("AGPIOD_SOCKET", &socket)
+        .arg("--config")
+        .arg(&config)
+        .args(["-v", "serve", "--http", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut url = String::new();
+    BufReader::new(serve.stdout.take().unwrap())
+        .read_line(&mut url)
+        .unwrap();
+    let (status, _) = http(url.trim(), "POST", "/devices/202/pins/13/toggle", "", "");
+    assert_eq!(status, 200);
+    let _ = serve.kill();
+    let serve = serve.wait_with_output().unwrap();
+    assert!(String::from_utf8_lossy(&serve.stderr).contains("sending through agpiod"));
+
+    // the bridge connects to the daemon before the broker, which is not running
+    let mut mqtt = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .env("AGPIOD_SOCKET", &socket)
+        .arg("--config")
+        .arg(&config)
+        .args(["-v", "mqtt", "--broker", "127.0.0.1:1"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let first = BufReader::new(mqtt.stderr.take().unwrap())
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.contains("sending through agpiod") || line.contains("broker"));
+    let _ = mqtt.kill();
+    let _ = mqtt.wait();
+    assert!(first.is_some_and(|line| line.contains("sending through agpiod")));
+
+    // bench and linktest send many requests, which the daemon serializes too
+    let bench = agpio(&["-v", "bench", "--iterations", "1"]);
+    assert!(bench.status.success());
+    assert!(String::from_utf8_lossy(&bench.stderr).contains("sending through agpiod"));
+    let linktest = agpio(&["-v", "linktest", "-n", "1", "--seed", "1"]);
+    assert!(linktest.status.success());
+    assert!(String::from_utf8_lossy(&linktest.stderr).contains("sending through agpiod"));
+
+    // monitor and replay need the port itself
+    let recording = dir.join("daemon.rec");
+    for args in [&["monitor"][..], &["replay", recording.to_str().unwrap()]] {
+        let refused = agpio(args);
+        assert_eq!(refused.status.code(), Some(11));
+        assert!(String::from_utf8_lossy(&refused.stderr).contains("agpiod owns device sim"));
+    }
+
+    // the daemon sends with its own connection settings
+    let retries = agpio(&["--retries", "3", "--no-exit-code", "toggle", "13"]);
+    assert!(retries.status.success());
+    assert!(String::from_utf8_lossy(&retries.stderr).contains("--retries is ignored"));
+
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+}
+
+#[test]
+fn daemon_keeps_files_that_are_not_sockets() {
+    let sim = Simulator::start(&[]);
+    let dir = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let config = dir.join("daemon-file.toml");
+    std::fs::write(&config, format!("[devices.sim]\nport = \"{}\"\n", sim.port)).unwrap();
+
+    // a mistyped socket that names the config file
+    let daemon = Command::new(env!("CARGO_BIN_EXE_agpiod"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--socket")
+        .arg(&config)
+        .output()
+        .unwrap();
+    assert_eq!(daemon.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&daemon.stderr).contains("is not a socket"));
+    assert!(std::fs::read_to_string(&config)
+        .unwrap()
+        .contains("[devices.sim]"));
+}
+
+/// send an HTTP request, and return the status and the JSON body of the response
+fn http(url: &str, method: &str, path: &str, token: &str, body: &str) -> (u16, serde_json::Value) {
+    use std::io::Read;
+
+    let address = url.strip_prefix("http://").unwrap();
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        address,
+        token,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn http_api() {
+    let sim = Simulator::start(&[]);
+    let mut server = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .args([
+            &sim.port,
+            "serve",
+            "--http",
+            "127.0.0.1:0",
+            "--token",
+            "s3cret",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // the server prints its URL once it is listening
+    let mut url = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut url)
+        .unwrap();
+    let request = |method, path, body| http(url.trim(), method, path, "s3cret", body);
+
+    let (status, write) = request("PUT", "/devices/202/pins/13", r#"{"value":1}"#);
+    assert_eq!(status, 200);
+    assert_eq!(
+        write,
+        serde_json::json!({"ok": true, "device": 202, "pin": 13, "value": 1})
+    );
+
+    let (status, read) = request("GET", "/devices/202/pins/13?direct=true", "");
+    assert_eq!((status, &read["value"]), (200, &1.into()));
+
+    let (status, toggle) = request("POST", "/devices/202/pins/13/toggle", "");
+    assert_eq!((status, &toggle["value"]), (200, &0.into()));
+
+    // errors come with a status code and the error category
+    let (status, nack) = request("POST", "/devices/202/i2c/0x27/write", r#"{"data":[1]}"#);
+    assert_eq!((status, &nack["error"]["category"]), (502, &"i2c".into()));
+    let (status, invalid) = request("PUT", "/devices/202/pins/led", r#"{"value":1}"#);
+    assert_eq!((status, &invalid["ok"]), (400, &false.into()));
+    assert_eq!(request("DELETE", "/devices/202/pins/13", "").0, 405);
+    assert_eq!(request("GET", "/pins/13", "").0, 404);
+
+    let (status, _) = http(url.trim(), "GET", "/devices/202/pins/13", "guess", "");
+    assert_eq!(status, 401);
+
+    let _ = server.kill();
+    let _ = server.wait();
+}
+
+#[test]
+fn live_pins() {
+    use tungstenite::Message;
+
+    fn receive<S: std::io::Read + std::io::Write>(
+        socket: &mut tungstenite::WebSocket<S>,
+    ) -> serde_json::Value {
+        match socket.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    let sim = Simulator::start(&[]);
+    let config = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("live.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "default_device = \"sim\"\n\n[devices.sim]\nport = \"{}\"\ntarget_id = 202\n\n[pins.led]\npin = 13\noutput = true\n\n[pins.level]\npin = \"A0\"\nanalog = true\n",
+            sim.port
+        ),
+    )
+    .unwrap();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .arg("--config")
+        .arg(&config)
+        .args([
+            "serve",
+            "--http",
+            "127.0.0.1:0",
+            "--ws",
+            "127.0.0.1:0",
+            "--poll",
+            "0",
+            "--token",
+            "s3cret",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // the server prints the URL of the API, then of the WebSocket
+    let mut lines = BufReader::new(server.stdout.take().unwrap()).lines();
+    let url = lines.next().unwrap().unwrap();
+    let ws = lines.next().unwrap().unwrap();
+
+    let (status, pins) = http(&url, "GET", "/pins", "s3cret", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        pins["ws"],
+        ws.rsplit(':').next().unwrap().parse::<u16>().unwrap()
+    );
+    assert_eq!(
+        pins["pins"],
+        serde_json::json!([
+            {"name": "led", "pin": 13, "widget": "toggle", "value": null},
+            {"name": "level", "pin": 14, "widget": "gauge", "value": null},
+        ])
+    );
+
+    // a wrong token is refused during the handshake
+    assert!(tungstenite::connect(format!("{}/?token=guess", ws)).is_err());
+
+    let (mut socket, _) = tungstenite::connect(format!("{}/?token=s3cret", ws)).unwrap();
+    assert_eq!(receive(&mut socket)["event"], "pins");
+
+    // commands are answered, and their changes pushed
+    socket
+        .send(Message::Text(
+            r#"{"op":"write","pin":"led","value":1,"id":7}"#.to_string(),
+        ))
+        .unwrap();
+    assert_eq!(
+        receive(&mut socket),
+        serde_json::json!({"event": "result", "id": 7, "ok": true, "pin": 13, "name": "led", "value": 1})
+    );
+    assert_eq!(
+        receive(&mut socket),
+        serde_json::json!({"event": "value", "name": "led", "pin": 13, "value": 1})
+    );
+
+    // so are the changes of requests to the REST API
+    let (status, _) = http(&url, "POST", "/devices/202/pins/led/toggle", "s3cret", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        receive(&mut socket),
+        serde_json::json!({"event": "value", "name": "led", "pin": 13, "value": 0})
+    );
+
+    let (_, pins) = http(&url, "GET", "/pins", "s3cret", "");
+    assert_eq!(pins["pins"][0]["value"], 0);
+
+    let _ = server.kill();
+    let _ = server.wait();
+}
+
+/// a broker for one MQTT client: answers its connect and subscriptions,
+/// and collects what it publishes
+struct Broker {
+    stream: std::net::TcpStream,
+    buffer: bytes::BytesMut,
+}
+
+impl Broker {
+    fn accept(listener: &std::net::TcpListener) -> Broker {
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        let mut broker = Broker {
+            stream,
+            buffer: bytes::BytesMut::new(),
+        };
+        match broker.read() {
+            rumqttc::Packet::Connect(_) => {
+                broker.write(|buffer| {
+                    rumqttc::ConnAck::new(rumqttc::ConnectReturnCode::Success, false).write(buffer)
+                });
+            }
+            packet => panic!("expected connect, got {:?}", packet),
+        }
+        broker
+    }
+
+    fn read(&mut self) -> rumqttc::Packet {
+        use std::io::Read;
+
+        loop {
+            match rumqttc::mqttbytes::v4::read(&mut self.buffer, 64 * 1024) {
+                Ok(packet) => return packet,
+                Err(rumqttc::Error::InsufficientBytes(_)) => {
+                    let mut chunk = [0; 1024];
+                    let n = self.stream.read(&mut chunk).unwrap();
+                    assert!(n > 0, "client disconnected");
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                }
+                Err(e) => panic!("invalid packet: {:?}", e),
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        packet: impl FnOnce(&mut bytes::BytesMut) -> Result<usize, rumqttc::Error>,
+    ) {
+        let mut buffer = bytes::BytesMut::new();
+        packet(&mut buffer).unwrap();
+        self.stream.write_all(&buffer).unwrap();
+    }
+
+    /// the payload of the next message the client publishes to `topic`
+    fn published(&mut self, topic: &str) -> String {
+        loop {
+            match self.read() {
+                rumqttc::Packet::Publish(publish) => {
+                    if publish.pkid != 0 {
+                        self.write(|buffer| rumqttc::PubAck::new(publish.pkid).write(buffer));
+                    }
+                    if publish.topic == topic {
+                        return String::from_utf8(publish.payload.to_vec()).unwrap();
+                    }
+                }
+                rumqttc::Packet::Subscribe(subscribe) => {
+                    let codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| rumqttc::SubscribeReasonCode::Success(filter.qos))
+                        .collect();
+                    self.write(|buffer| rumqttc::SubAck::new(subscribe.pkid, codes).write(buffer));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// publish a message to the client
+    fn publish(&mut self, topic: &str, payload: &str) {
+        let publish = rumqttc::Publish::new(topic, rumqttc::QoS::AtMostOnce, payload);
+        self.write(|buffer| publish.write(buffer));
+    }
+}
+
+#[test]
+fn mqtt_bridge() {
+    let dir = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let inputs = dir.join("mqtt.inputs");
+    std::fs::write(&inputs, "sleep 1s\nset 2 high\n").unwrap();
+    let sim = Simulator::start(&["--inputs", inputs.to_str().unwrap()]);
+    let config = dir.join("mqtt.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "default_device = \"sim\"\n\n[devices.sim]\nport = \"{}\"\ntarget_id = 202\n\n[pins.button]\npin = 2\n\n[pins.led]\npin = 13\noutput = true\n\n[pins.dimmer]\npin = 9\nanalog = true\noutput = true\n",
+            sim.port
+        ),
+    )
+    .unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut bridge = Command::new(env!("CARGO_BIN_EXE_agpio"))
+        .arg("--config")
+        .arg(&config)
+        .args(["mqtt", "--broker"])
+        .arg(listener.local_addr().unwrap().to_string())
+        .args(["--discovery", "--poll", "100"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut broker = Broker::accept(&listener);
+
+    // the configured pins are announced in the order of their names, and their values published
+    assert_eq!(broker.published("agpio/sim/status"), "online");
+    let light: serde_json::Value =
+        serde_json::from_str(&broker.published("homeassistant/light/agpio_sim_dimmer/config"))
+            .unwrap();
+    assert_eq!(light["brightness_command_topic"], "agpio/sim/dimmer/set");
+    let switch: serde_json::Value =
+        serde_json::from_str(&broker.published("homeassistant/switch/agpio_sim_led/config"))
+            .unwrap();
+    assert_eq!(
+        (&switch["state_topic"], &switch["command_topic"]),
+        (&"agpio/sim/led/state".into(), &"agpio/sim/led/set".into())
+    );
+    assert_eq!(broker.published("agpio/sim/led/state"), "0");
+
+    // set topics write and toggle pins, and their new values are published
+    broker.publish("agpio/sim/led/set", "ON");
+    assert_eq!(broker.published("agpio/sim/led/state"), "1");
+    broker.publish("agpio/sim/led/set", "TOGGLE");
+    assert_eq!(broker.published("agpio/sim/led/state"), "0");
+    broker.publish("agpio/sim/dimmer/set", "128");
+    assert_eq!(broker.published("agpio/sim/dimmer/state"), "128");
+
+    // inputs are polled even while set messages arrive faster than the poll interval
+    let flooding = Arc::new(AtomicBool::new(true));
+    let flood = {
+        let flooding = flooding.clone();
+        let mut stream = broker.stream.try_clone().unwrap();
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            while flooding.load(Ordering::Relaxed) {
+                if start.elapsed() > Duration::from_secs(5) {
+                    return false;
+                }
+                let mut buffer = bytes::BytesMut::new();
+                rumqttc::Publish::new("agpio/sim/dimmer/set", rumqttc::QoS::AtMostOnce, "7")
+                    .write(&mut buffer)
+                    .unwrap();
+                stream.write_all(&buffer).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            true
+        })
+    };
+    while broker.published("agpio/sim/button/state") != "1" {}
+    flooding.store(false, Ordering::Relaxed);
+    assert!(
+        flood.join().unwrap(),
+        "the button was only polled after the messages stopped"
+    );
+
+    let _ = bridge.kill();
+    let _ = bridge.wait();
+}
@@ -0,0 +1,13 @@
+use agpio::gpio::HostController;
+use agpio::transport::mock::MockTransport;
+use std::time::Duration;
+
+/// host controller with ID 0xAA on `mock`, with a short timeout so missing responses fail fast
+pub fn connect(mock: &MockTransport, retries: i32) -> HostController {
+    HostController::new(
+        Box::new(mock.clone()),
+        0xAA,
+        Some(Duration::from_millis(10)),
+        Some(retries),
+    )
+}
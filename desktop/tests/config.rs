@@ -0,0 +1,87 @@
+use agpio::config::{parse_pin, parse_u8, Board, Config};
+
+const CONFIG: &str = r#"
+default_device = "printer"
+
+[devices.printer]
+port = "/dev/ttyUSB0"
+baud = 57600
+target_id = 0xCA
+board = "mega"
+
+[devices.bench]
+usb_serial = "A50285BI"
+
+[pins.bed_light]
+device = "printer"
+pin = 9
+analog = true
+inverted = true
+
+[pins.fan]
+device = "printer"
+pin = "A0"
+
+[pins.button]
+pin = "a2"
+pullup = true
+"#;
+
+#[test]
+fn parse_devices_and_pins() {
+    let config = Config::parse(CONFIG).unwrap();
+    assert_eq!(config.default_device.as_deref(), Some("printer"));
+
+    let printer = &config.devices["printer"];
+    assert_eq!(printer.port.as_deref(), Some("/dev/ttyUSB0"));
+    assert_eq!((printer.baud, printer.target_id), (Some(57600), Some(0xCA)));
+    assert_eq!(config.board(Some("printer")), Some(Board::Mega));
+    assert_eq!(config.board(Some("bench")), None);
+
+    let bed_light = &config.pins["bed_light"];
+    assert!(bed_light.analog && bed_light.inverted && !bed_light.pullup);
+    assert_eq!(config.pin_number("bed_light"), Ok(9));
+
+    // analog pins are numbered by the board of the device, or like the Nano without one
+    assert_eq!(config.pin_number("fan"), Ok(54));
+    assert_eq!(config.pin_number("button"), Ok(16));
+    assert!(config.pin_number("lamp").is_err());
+}
+
+#[test]
+fn parse_pin_names() {
+    assert_eq!(parse_pin("13", None), Ok(13));
+    assert_eq!(parse_pin("A7", None), Ok(21));
+    assert_eq!(parse_pin("A5", Some(Board::Uno)), Ok(19));
+    assert_eq!(parse_pin("A15", Some(Board::Mega)), Ok(69));
+    assert_eq!(
+        parse_pin("A6", Some(Board::Uno)),
+        Err("'A6' is not a valid pin, use a number or A0 - A5".to_string())
+    );
+    assert!(parse_pin("led", None).is_err());
+}
+
+#[test]
+fn parse_byte_values() {
+    assert_eq!(parse_u8("202"), Ok(202));
+    assert_eq!(parse_u8("0xca"), Ok(0xCA));
+    assert_eq!(parse_u8("0XCA"), Ok(0xCA));
+    assert!(parse_u8("256").is_err());
+    assert!(parse_u8("0x").is_err());
+}
+
+#[test]
+fn reject_invalid_configs() {
+    let invalid = [
+        "default_device = \"printer\"",
+        "[devices.printer]\nport = \"COM3\"\nusb_serial = \"A50285BI\"",
+        "[devices.printer]\nspeed = 9600",
+        "[pins.13]\npin = 9",
+        "[pins.fan]\ndevice = \"printer\"\npin = 9",
+        "[devices.printer]\nboard = \"uno\"\n\n[pins.fan]\ndevice = \"printer\"\npin = \"A7\"",
+    ];
+    for text in invalid {
+        assert!(Config::parse(text).is_err(), "accepted: {}", text);
+    }
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use agpio::gpio::{
+    custom::{CommandSpec, Value},
+    Error,
+};
+use agpio::transport::mock::{Expectation, MockTransport};
+use common::connect;
+
+const SPEC: &str = r#"
+[[command]]
+name = "servo"
+type = 0x10
+request = [
+    { name = "pin", type = "u8" },
+    { name = "angle", type = "u16", endian = "little" },
+    { name = "options", type = "flags", flags = { detach = 0, slow = 1 } },
+]
+response = [
+    { name = "position", type = "i16" },
+]
+
+[[command]]
+name = "display"
+type = 0x11
+request = [
+    { name = "line", type = "u8" },
+    { name = "text", type = "bytes" },
+]
+"#;
+
+#[test]
+fn custom_requests_round_trip() {
+    let specs = CommandSpec::parse(SPEC).unwrap();
+    let servo = &specs[0];
+    let request = servo
+        .request(&[
+            Value::Int(9),
+            Value::Int(300),
+            Value::Flags(vec!["slow".to_string()]),
+        ])
+        .unwrap();
+
+    let mock = MockTransport::new();
+    mock.expect(Expectation::request(&request).reply_body([0x90, 0xff, 0xd6]))
+        .expect(Expectation::request(&request).reply_body([0x90, 0x01]));
+
+    // fields are encoded in order with their own endianness
+    let mut host = connect(&mock, 0);
+    let response = host.send(&request, 0xCA).unwrap();
+    assert_eq!(mock.bodies()[0], vec![0x10, 9, 0x2c, 0x01, 0x02]);
+    assert_eq!(
+        servo.response_values(&response).unwrap(),
+        vec![("position".to_string(), Value::Int(-42))]
+    );
+
+    // responses are checked against the declared length
+    assert_eq!(host.send(&request, 0xCA), Err(Error::ResponseMismatch));
+    mock.verify();
+
+    // requests decode to the values they were built from
+    assert_eq!(
+        servo.decode_request(&mock.bodies()[0]).unwrap(),
+        vec![
+            ("pin".to_string(), Value::Int(9)),
+            ("angle".to_string(), Value::Int(300)),
+            (
+                "options".to_string(),
+                Value::Flags(vec!["slow".to_string()])
+            ),
+        ]
+    );
+    assert_eq!(
+        servo.describe(&mock.bodies()[0]),
+        "servo request: pin = 9, angle = 300, options = slow"
+    );
+}
+
+#[test]
+fn custom_request_values_are_validated() {
+    let specs = CommandSpec::parse(SPEC).unwrap();
+    let (servo, display) = (&specs[0], &specs[1]);
+
+    assert!(servo
+        .request(&[Value::Int(9), Value::Int(70000), Value::Flags(vec![])])
+        .unwrap_err()
+        .contains("out of range"));
+    assert!(servo
+        .request(&[
+            Value::Int(9),
+            Value::Int(0),
+            Value::Flags(vec!["fast".to_string()])
+        ])
+        .unwrap_err()
+        .contains("unknown flag 'fast'"));
+    assert!(servo.request(&[Value::Int(9)]).is_err());
+
+    // bytes fields take all remaining bytes
+    let request = display
+        .request(&[Value::Int(1), Value::Bytes(b"hi".to_vec())])
+        .unwrap();
+    assert_eq!(request.body, [1, b'h', b'i']);
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let error = |spec: &str| CommandSpec::parse(spec).unwrap_err().to_string();
+
+    assert!(error("[[command]]\nname = \"a\"\ntype = 0x01\n").contains("built-in command"));
+    assert!(error("[[command]]\nname = \"a\"\ntype = 0x90\n").contains("response bit"));
+    assert!(error(
+        "[[command]]\nname = \"a\"\ntype = 0x10\n[[command]]\nname = \"b\"\ntype = 0x10\n"
+    )
+    .contains("same type"));
+    assert!(error(
+        "[[command]]\nname = \"a\"\ntype = 0x10\nrequest = [{ name = \"x\", type = \"bytes\" }, { name = \"y\", type = \"u8\" }]\n"
+    )
+    .contains("must be the last field"));
+    assert!(error(
+        "[[command]]\nname = \"a\"\ntype = 0x10\nrequest = [{ name = \"x\", type = \"flags\", flags = { a = 8 } }]\n"
+    )
+    .contains("bits 0 to 7"));
+    assert!(error("[[command]]\nname = \"a\"\ntype = 0x10\nwidth = 2\n").contains("unknown field"));
+}
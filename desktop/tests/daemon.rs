@@ -0,0 +1,110 @@
+#![cfg(unix)]
+
+mod common;
+
+use agpio::daemon::{self, Client, ERR_UNKNOWN_DEVICE};
+use agpio::gpio::{
+    policy::{Policy, Violation},
+    read::{ReadRequest, ReadResponse},
+    toggle::ToggleRequest,
+    write::WriteRequest,
+    Error,
+};
+use agpio::transport::mock::{Expectation, MockTransport};
+use std::collections::BTreeMap;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+
+/// serve a host controller on `mock` as device "printer" on `<name>.sock`, and connect to it
+fn start(name: &str, mock: &MockTransport) -> Client {
+    let socket = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.sock", name));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+
+    let mut host = common::connect(mock, 0);
+    host.set_policy(Policy::parse("[pins.7]\naccess = \"input\"", None).unwrap());
+    let devices = BTreeMap::from([("printer".to_string(), host)]);
+    std::thread::spawn(move || daemon::serve(listener, devices));
+
+    Client::connect(&socket).unwrap()
+}
+
+#[test]
+fn requests_are_sent_by_the_daemon() {
+    let mock = MockTransport::new();
+    mock.expect(
+        Expectation::request(&ReadRequest::new(13, false, false, false, false, true))
+            .to(0xCA)
+            .reply(&ReadResponse { value: 1 }),
+    )
+    .expect(Expectation::request(&WriteRequest::new(13, 0, false, false)).reply_body([0x82]))
+    .expect(Expectation::request(&ToggleRequest::new(1)).reply_body([0xff, 0x03]));
+
+    let mut client = start("requests", &mock);
+    assert_eq!(client.devices(), ["printer"]);
+
+    let read = ReadRequest::new(13, false, false, false, false, true);
+    assert_eq!(client.send("printer", &read, 0xCA).unwrap().value, 1);
+    client
+        .send("printer", &WriteRequest::new(13, 0, false, false), 0xCA)
+        .unwrap();
+
+    // errors of the controller and of the daemon are passed on
+    assert_eq!(
+        client
+            .send("printer", &ToggleRequest::new(1), 0xCA)
+            .unwrap_err(),
+        Error::InvalidPin
+    );
+    assert_eq!(
+        client
+            .send("printer", &ToggleRequest::new(7), 0xCA)
+            .unwrap_err(),
+        Error::PolicyViolation {
+            pin: 7,
+            violation: Violation::InputOnly
+        }
+    );
+    assert_eq!(
+        client
+            .send("bench", &ToggleRequest::new(13), 0xCA)
+            .unwrap_err(),
+        Error::HostError {
+            code: ERR_UNKNOWN_DEVICE
+        }
+    );
+    mock.verify();
+
+    let stats = client.stats();
+    assert_eq!((stats.total.requests, stats.total.successes), (5, 2));
+}
+
+#[test]
+fn clients_share_a_device() {
+    let mock = MockTransport::new();
+    mock.fallback(|packet| match packet.body {
+        [0x01, pin, _] => Some(vec![0x81, 0x00, *pin]),
+        _ => None,
+    });
+    let first = start("shared", &mock);
+    drop(first);
+
+    // every client gets the response to its own request
+    let socket = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("shared.sock");
+    let clients: Vec<_> = (0..4)
+        .map(|_| {
+            let mut client = Client::connect(&socket).unwrap();
+            std::thread::spawn(move || {
+                for pin in 8..14 {
+                    let read = ReadRequest::new(pin, false, false, false, false, true);
+                    let response = client.send("printer", &read, 0xCA).unwrap();
+                    assert_eq!(response.value, u16::from(pin));
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    assert_eq!(mock.bodies().len(), 4 * 6);
+}
@@ -0,0 +1,18 @@
+use agpio::duration::parse_duration;
+use std::time::Duration;
+
+#[test]
+fn durations_need_a_unit() {
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+    assert_eq!(parse_duration("0.5s"), Ok(Duration::from_millis(500)));
+    assert!(parse_duration("250").is_err());
+    assert!(parse_duration("").is_err());
+}
+
+#[test]
+fn invalid_durations_are_rejected_instead_of_panicking() {
+    for invalid in ["-1s", "-5ms", "nans", "infs", "1e30s", "1e300ms"] {
+        assert!(parse_duration(invalid).is_err(), "accepted: {}", invalid);
+    }
+}
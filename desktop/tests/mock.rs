@@ -0,0 +1,101 @@
+mod common;
+
+use agpio::gpio::{
+    read::{ReadRequest, ReadResponse},
+    toggle::ToggleRequest,
+    write::WriteRequest,
+    Error,
+};
+use agpio::sdsp::{self, ReadError};
+use agpio::transport::mock::{Expectation, MockTransport};
+use common::connect;
+
+#[test]
+fn expected_requests_are_answered() {
+    let mock = MockTransport::new();
+    mock.expect(Expectation::request(&WriteRequest::new(13, 1, false, false)).reply_body([0x82]))
+        .expect(
+            Expectation::request(&ReadRequest::new(7, true, false, false, false, false))
+                .to(0xCA)
+                .reply(&ReadResponse { value: 1 }),
+        );
+
+    let mut host = connect(&mock, 0);
+    host.send(&WriteRequest::new(13, 1, false, false), 0xCA)
+        .unwrap();
+    let read = host
+        .send(&ReadRequest::new(7, true, false, false, false, false), 0xCA)
+        .unwrap();
+    assert_eq!(read.value, 1);
+    mock.verify();
+
+    // the exact wire traffic is recorded
+    let frames = mock.frames();
+    assert_eq!(
+        frames[0],
+        [0x7b, 0xaa, 0xca, 0x00, 0x05, 0x02, 0x0d, 0x00, 0x01, 0x00, 0x19, 0x90, 0x7d]
+    );
+    assert_eq!(mock.bodies()[1], vec![0x01, 0x07, 0x01]);
+}
+
+#[test]
+#[should_panic(expected = "does not match expected")]
+fn mismatching_request_fails_verification() {
+    let mock = MockTransport::new();
+    mock.expect(Expectation::request(&WriteRequest::new(13, 1, false, false)).reply_body([0x82]));
+
+    let mut host = connect(&mock, 0);
+    let _ = host.send(&WriteRequest::new(12, 1, false, false), 0xCA);
+    mock.verify();
+}
+
+#[test]
+#[should_panic(expected = "never written")]
+fn missing_request_fails_verification() {
+    let mock = MockTransport::new();
+    mock.expect(Expectation::any());
+    mock.verify();
+}
+
+#[test]
+fn closure_responses_and_retries() {
+    let mock = MockTransport::new();
+
+    // no answer to the first attempt, then an error, then the real response
+    mock.expect(Expectation::body([0x03, 13]))
+        .expect(Expectation::body([0x03, 13]).reply_body([0xff, 0x01]))
+        .expect(Expectation::body([0x03, 13]).reply_with(|packet| {
+            assert_eq!(packet.receiver_id, sdsp::BROADCAST_ID);
+            Some(vec![0x83, 0x01])
+        }));
+
+    let mut host = connect(&mock, 2);
+    let toggle = host
+        .send(&ToggleRequest::new(13), sdsp::BROADCAST_ID)
+        .unwrap();
+    assert_eq!(toggle.new_value, 1);
+    mock.verify();
+}
+
+#[test]
+fn fallback_responder() {
+    let mock = MockTransport::new();
+    mock.fallback(|packet| match packet.body {
+        [0x03, _] => Some(vec![0xff, 0x03]),
+        _ => None,
+    });
+
+    let mut host = connect(&mock, 0);
+    assert_eq!(
+        host.send(&ToggleRequest::new(1), 0xCA).unwrap_err(),
+        Error::InvalidPin
+    );
+    assert_eq!(
+        host.send(&WriteRequest::new(1, 0, false, false), 0xCA)
+            .unwrap_err(),
+        Error::SDSPError {
+            kind: ReadError::Timeout
+        }
+    );
+    mock.verify();
+}
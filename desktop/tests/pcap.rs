@@ -0,0 +1,58 @@
+use agpio::gpio::{toggle::ToggleRequest, HostController};
+use agpio::sdsp;
+use agpio::sim::{controller::VirtualController, SimTransport};
+use agpio::transport::pcap::{
+    PcapWriter, LINKTYPE_USER0, PCAP_DIRECTION_RECEIVED, PCAP_DIRECTION_SENT,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn frames_are_captured_in_both_directions() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("capture.pcap");
+    let controller = Arc::new(Mutex::new(VirtualController::default()));
+    let mut host = HostController::new(
+        Box::new(SimTransport::new(controller)),
+        0xAA,
+        Some(Duration::from_millis(20)),
+        Some(0),
+    );
+    host.add_tap(Box::new(PcapWriter::create(&path).unwrap()));
+    host.send(&ToggleRequest::new(13), 0xCA).unwrap();
+    drop(host);
+
+    let capture = std::fs::read(&path).unwrap();
+
+    // global header
+    assert_eq!(u32_at(&capture, 0), 0xa1b2c3d4);
+    assert_eq!(u32_at(&capture, 20), LINKTYPE_USER0);
+
+    // one record per frame, each prefixed with its direction
+    let mut records = Vec::new();
+    let mut offset = 24;
+    while offset < capture.len() {
+        let ts_usec = u32_at(&capture, offset + 4);
+        let incl_len = u32_at(&capture, offset + 8) as usize;
+        assert_eq!(u32_at(&capture, offset + 12) as usize, incl_len);
+        assert!(ts_usec < 1_000_000);
+
+        records.push(capture[offset + 16..offset + 16 + incl_len].to_vec());
+        offset += 16 + incl_len;
+    }
+    assert_eq!(offset, capture.len());
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0][0], PCAP_DIRECTION_SENT);
+    let request = sdsp::decode_packet(&records[0][1..]).unwrap();
+    assert_eq!(request.body, [0x03, 13]);
+
+    assert_eq!(records[1][0], PCAP_DIRECTION_RECEIVED);
+    let response = sdsp::decode_packet(&records[1][1..]).unwrap();
+    assert_eq!(response.sender_id, 0xCA);
+    assert_eq!(response.body, [0x83, 1]);
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use agpio::config::Board;
+use agpio::gpio::{
+    policy::{Access, Policy, Violation},
+    raw::RawRequest,
+    read::{ReadRequest, ReadResponse},
+    toggle::ToggleRequest,
+    write::WriteRequest,
+    Error, HostController,
+};
+use agpio::transport::mock::{Expectation, MockTransport};
+
+const POLICY: &str = r#"
+[pins.7]
+access = "output"
+max = 1
+
+[pins.9]
+min = 10
+max = 200
+
+[pins.A0]
+access = "input"
+
+[pins.2]
+access = "forbidden"
+"#;
+
+fn connect(mock: &MockTransport) -> HostController {
+    let mut host = common::connect(mock, 0);
+    host.set_policy(Policy::parse(POLICY, None).unwrap());
+    host
+}
+
+fn refused(pin: u8, violation: Violation) -> Result<(), Error> {
+    Err(Error::PolicyViolation { pin, violation })
+}
+
+#[test]
+fn parse_policy() {
+    let policy = Policy::parse(POLICY, None).unwrap();
+    assert_eq!(policy.pins[&7].access, Access::Output);
+    assert_eq!(
+        (policy.pins[&9].min, policy.pins[&9].max),
+        (Some(10), Some(200))
+    );
+    assert_eq!(policy.pins[&14].access, Access::Input);
+
+    // analog pins are numbered by the board
+    let mega = Policy::parse("[pins.A0]\naccess = \"input\"", Some(Board::Mega)).unwrap();
+    assert!(mega.pins.contains_key(&54));
+
+    let invalid = [
+        "[pins.led]\naccess = \"input\"",
+        "[pins.9]\nmin = 200\nmax = 10",
+        "[pins.14]\naccess = \"input\"\n[pins.A0]\naccess = \"input\"",
+        "[pins.9]\naccess = \"readonly\"",
+    ];
+    for text in invalid {
+        assert!(Policy::parse(text, None).is_err(), "accepted: {}", text);
+    }
+}
+
+#[test]
+fn violations_are_never_sent() {
+    let mock = MockTransport::new();
+    let mut host = connect(&mock);
+    let read = |pin, direct| ReadRequest::new(pin, false, false, false, false, direct);
+
+    let write = |host: &mut HostController, pin, value| {
+        host.send(&WriteRequest::new(pin, value, true, false), 0xCA)
+            .map(|_| ())
+    };
+    assert_eq!(
+        write(&mut host, 9, 201),
+        refused(
+            9,
+            Violation::OutOfRange {
+                value: 201,
+                min: 10,
+                max: 200
+            }
+        )
+    );
+    assert_eq!(write(&mut host, 14, 1), refused(14, Violation::InputOnly));
+    assert_eq!(
+        host.send(&ToggleRequest::new(9), 0xCA).map(|_| ()),
+        refused(
+            9,
+            Violation::OutOfRange {
+                value: 0,
+                min: 10,
+                max: 200
+            }
+        )
+    );
+    assert_eq!(
+        host.send(&read(2, true), 0xCA).map(|_| ()),
+        refused(2, Violation::Forbidden)
+    );
+
+    // a read that is not direct would make the output an input
+    assert_eq!(
+        host.send(&read(7, false), 0xCA).map(|_| ()),
+        refused(7, Violation::OutputOnly)
+    );
+
+    // raw requests are checked like the request they encode
+    assert_eq!(
+        host.send(&RawRequest::new(0x02, vec![9, 0x01, 0x00, 0x01]), 0xCA)
+            .map(|_| ()),
+        refused(
+            9,
+            Violation::OutOfRange {
+                value: 256,
+                min: 10,
+                max: 200
+            }
+        )
+    );
+
+    assert!(mock.frames().is_empty());
+    assert_eq!(host.stats().total.requests, 0);
+}
+
+#[test]
+fn allowed_requests_are_sent() {
+    let mock = MockTransport::new();
+    mock.expect(Expectation::request(&WriteRequest::new(9, 200, true, false)).reply_body([0x82]))
+        .expect(
+            Expectation::request(&ReadRequest::new(7, false, false, false, false, true))
+                .reply(&ReadResponse { value: 1 }),
+        )
+        .expect(
+            Expectation::request(&ReadRequest::new(14, false, false, true, false, false))
+                .reply(&ReadResponse { value: 512 }),
+        );
+
+    let mut host = connect(&mock);
+    host.send(&WriteRequest::new(9, 200, true, false), 0xCA)
+        .unwrap();
+    host.send(&ReadRequest::new(7, false, false, false, false, true), 0xCA)
+        .unwrap();
+    host.send(
+        &ReadRequest::new(14, false, false, true, false, false),
+        0xCA,
+    )
+    .unwrap();
+    mock.verify();
+}
+
+#[test]
+fn inverted_writes_are_checked_as_driven() {
+    let mock = MockTransport::new();
+    mock.expect(Expectation::request(&WriteRequest::new(7, 1, false, true)).reply_body([0x82]))
+        .expect(Expectation::request(&WriteRequest::new(9, 100, true, true)).reply_body([0x82]));
+    let mut host = common::connect(&mock, 0);
+    let policy = "[pins.7]\naccess = \"output\"\nmax = 0\n\n[pins.9]\nmin = 10\nmax = 200";
+    host.set_policy(Policy::parse(policy, None).unwrap());
+    let mut write = |pin, value, analog| {
+        host.send(&WriteRequest::new(pin, value, analog, true), 0xCA)
+            .map(|_| ())
+    };
+
+    // an inverted digital 0 drives the pin high
+    assert_eq!(
+        write(7, 0, false),
+        refused(
+            7,
+            Violation::OutOfRange {
+                value: 1,
+                min: 0,
+                max: 0
+            }
+        )
+    );
+    assert_eq!(write(7, 1, false), Ok(()));
+
+    // an inverted analog value drives 255 - value
+    assert_eq!(
+        write(9, 250, true),
+        refused(
+            9,
+            Violation::OutOfRange {
+                value: 5,
+                min: 10,
+                max: 200
+            }
+        )
+    );
+    assert_eq!(write(9, 100, true), Ok(()));
+    mock.verify();
+}
@@ -0,0 +1,138 @@
+use agpio::gpio::{toggle::ToggleRequest, write::WriteRequest, Error, HostController};
+use agpio::sim::{controller::VirtualController, SimTransport};
+use agpio::transport::{
+    record::{Recorder, Recording},
+    replay::{resend, ReplayTransport},
+    Direction, Transport,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const HOST_ID: u8 = 0xAA;
+const DEVICE_ID: u8 = 0xCA;
+
+fn recording_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.rec", name))
+}
+
+fn connect(port: Box<dyn Transport>) -> HostController {
+    HostController::new(port, HOST_ID, Some(Duration::from_millis(20)), Some(0))
+}
+
+/// record a short session against a fresh virtual controller
+fn record_session(name: &str) -> Recording {
+    let path = recording_path(name);
+    let controller = Arc::new(Mutex::new(VirtualController::default()));
+    let mut host = connect(Box::new(SimTransport::new(controller)));
+    host.add_tap(Box::new(Recorder::create(&path).unwrap()));
+
+    host.send(&WriteRequest::new(13, 1, false, false), DEVICE_ID)
+        .unwrap();
+    host.send(&ToggleRequest::new(13), DEVICE_ID).unwrap();
+    assert_eq!(
+        host.send(&ToggleRequest::new(1), DEVICE_ID),
+        Err(Error::InvalidPin)
+    );
+    drop(host);
+
+    Recording::load(&path).unwrap()
+}
+
+#[test]
+fn session_is_recorded() {
+    let recording = record_session("session_is_recorded");
+
+    let directions: Vec<Direction> = recording.frames.iter().map(|f| f.direction).collect();
+    assert_eq!(
+        directions,
+        [
+            Direction::Sent,
+            Direction::Received,
+            Direction::Sent,
+            Direction::Received,
+            Direction::Sent,
+            Direction::Received,
+        ]
+    );
+    assert_eq!(recording.sent().count(), 3);
+    assert_eq!(
+        recording.frames[1].frame,
+        [0x7b, 0xca, 0xaa, 0x00, 0x01, 0x82, 0x1a, 0xb4, 0x7d]
+    );
+    assert!(recording
+        .frames
+        .windows(2)
+        .all(|w| w[0].timestamp <= w[1].timestamp));
+}
+
+#[test]
+fn replay_reproduces_session() {
+    let recording = record_session("replay_reproduces_session");
+    let replay = ReplayTransport::new(recording);
+    let mut host = connect(Box::new(replay.clone()));
+
+    host.send(&WriteRequest::new(13, 1, false, false), DEVICE_ID)
+        .unwrap();
+    let toggled = host.send(&ToggleRequest::new(13), DEVICE_ID).unwrap();
+    assert_eq!(toggled.new_value, 0);
+    assert_eq!(
+        host.send(&ToggleRequest::new(1), DEVICE_ID),
+        Err(Error::InvalidPin)
+    );
+    assert_eq!(replay.remaining(), 0);
+    replay.verify();
+}
+
+#[test]
+#[should_panic(expected = "does not match recorded")]
+fn replay_detects_diverging_requests() {
+    let recording = record_session("replay_detects_diverging_requests");
+    let replay = ReplayTransport::new(recording);
+    let mut host = connect(Box::new(replay.clone()));
+
+    let _ = host.send(&WriteRequest::new(12, 1, false, false), DEVICE_ID);
+    replay.verify();
+}
+
+#[test]
+fn resend_compares_live_responses() {
+    let recording = record_session("resend_compares_live_responses");
+
+    // a device in the same state answers exactly like in the recording
+    let controller = Arc::new(Mutex::new(VirtualController::default()));
+    let mut port = SimTransport::new(controller.clone());
+    let outcomes = resend(&recording, &mut port, Duration::from_millis(20), false).unwrap();
+    assert_eq!(outcomes.len(), 3);
+    assert!(outcomes.iter().all(|outcome| outcome.matches()));
+
+    // a different device does not answer at all
+    let controller = Arc::new(Mutex::new(VirtualController::new(0xCB)));
+    let mut port = SimTransport::new(controller);
+    let outcomes = resend(&recording, &mut port, Duration::from_millis(20), false).unwrap();
+    assert!(outcomes.iter().all(|outcome| !outcome.matches()));
+    assert_eq!(outcomes[0].received, None);
+    assert_eq!(
+        outcomes[0].expected,
+        Some(recording.frames[1].frame.clone())
+    );
+}
+
+#[test]
+fn recording_parse_errors_name_the_line() {
+    let text = "# agpio recording v1\n\
+                1697040000.123456 tx 7b aa ff 00 03 01 0d 00 13 5b 7d\n\
+                1697040000.125012 up 7b ca aa 00 02 81 01 12 5c 7d\n";
+    let err = Recording::parse(text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "line 3: invalid direction 'up', expected tx or rx"
+    );
+
+    let recording = Recording::parse(&text.replace(" up ", " rx ")).unwrap();
+    assert_eq!(recording.frames.len(), 2);
+    assert_eq!(
+        recording.frames[1].timestamp,
+        Duration::new(1697040000, 125_012_000)
+    );
+}
@@ -0,0 +1,130 @@
+use agpio::sdsp::{
+    self,
+    scanner::{ScanEvent, Scanner},
+    Decoder, ReadError,
+};
+
+#[test]
+fn encode_matches_protocol_example() {
+    // read request for pin 13 from 0xAA to 0xCA
+    let mut frame = Vec::new();
+    sdsp::encode_packet(0xAA, 0xCA, &[0x01, 0x0d, 0x00], &mut frame);
+    assert_eq!(
+        frame,
+        [0x7b, 0xaa, 0xca, 0x00, 0x03, 0x01, 0x0d, 0x00, 0x0b, 0x8c, 0x7d]
+    );
+}
+
+#[test]
+fn decode_round_trip() {
+    let mut frame = Vec::new();
+    for len in [0usize, 1, 24, 300] {
+        let body: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        sdsp::encode_packet(0x12, 0x34, &body, &mut frame);
+
+        let packet = sdsp::decode_packet(&frame).unwrap();
+        assert_eq!(packet.sender_id, 0x12);
+        assert_eq!(packet.receiver_id, 0x34);
+        assert_eq!(packet.body, &body[..]);
+    }
+}
+
+#[test]
+fn decode_rejects_corruption() {
+    let mut frame = Vec::new();
+    sdsp::encode_packet(0xAA, 0xCA, &[0x82], &mut frame);
+
+    let mut corrupt = frame.clone();
+    corrupt[5] ^= 0x01;
+    assert_eq!(
+        sdsp::decode_packet(&corrupt),
+        Err(ReadError::ChecksumMismatch)
+    );
+
+    let mut no_end = frame.clone();
+    *no_end.last_mut().unwrap() = 0x00;
+    assert_eq!(sdsp::decode_packet(&no_end), Err(ReadError::InvalidPacket));
+
+    assert_eq!(
+        sdsp::decode_packet(&frame[..frame.len() - 1]),
+        Err(ReadError::InvalidPacket)
+    );
+}
+
+#[test]
+fn decoder_skips_garbage_and_is_reusable() {
+    let mut stream = vec![0x00, 0x7d, 0x55];
+    let mut frame = Vec::new();
+    sdsp::encode_packet(0xCA, 0xAA, &[0x81, 0x00, 0x01], &mut frame);
+    stream.extend_from_slice(&frame);
+    sdsp::encode_packet(0xCA, 0xAA, &[0x83, 0x01], &mut frame);
+    stream.extend_from_slice(&frame);
+
+    let mut decoder = Decoder::new();
+    let mut bodies = Vec::new();
+    for byte in stream {
+        if let Some(result) = decoder.push(byte) {
+            result.unwrap();
+            bodies.push(decoder.packet().body.to_vec());
+        }
+    }
+    assert_eq!(bodies, vec![vec![0x81, 0x00, 0x01], vec![0x83, 0x01]]);
+}
+
+fn scan_all(scanner: &mut Scanner) -> Vec<ScanEvent> {
+    std::iter::from_fn(|| scanner.next_event()).collect()
+}
+
+#[test]
+fn scanner_resynchronises_after_stray_start_byte() {
+    let mut request = Vec::new();
+    sdsp::encode_packet(0xAA, 0xCA, &[0x03, 0x0d], &mut request);
+    let mut response = Vec::new();
+    sdsp::encode_packet(0xCA, 0xAA, &[0x83, 0x01], &mut response);
+
+    // a stray start byte whose "length" would swallow both frames
+    let mut stream = vec![0x00, 0x7b, 0x01, 0x02, 0x00, 0x10];
+    stream.extend_from_slice(&request);
+    stream.extend_from_slice(&response);
+
+    let mut scanner = Scanner::new();
+    for chunk in stream.chunks(3) {
+        scanner.push(chunk);
+    }
+    assert_eq!(
+        scan_all(&mut scanner),
+        [
+            ScanEvent::Garbage(vec![0x00, 0x7b, 0x01, 0x02, 0x00, 0x10]),
+            ScanEvent::Frame(request),
+            ScanEvent::Frame(response),
+        ]
+    );
+    assert!(!scanner.is_pending());
+}
+
+#[test]
+fn scanner_reports_corrupt_and_truncated_frames() {
+    let mut frame = Vec::new();
+    sdsp::encode_packet(0xAA, 0xCA, &[0x03, 0x0d], &mut frame);
+    let mut corrupt = frame.clone();
+    corrupt[6] ^= 0x01;
+
+    let mut scanner = Scanner::new();
+    scanner.push(&corrupt);
+    scanner.push(&frame[..6]);
+    assert_eq!(
+        scan_all(&mut scanner),
+        [ScanEvent::Corrupt {
+            frame: corrupt,
+            error: ReadError::ChecksumMismatch
+        }]
+    );
+
+    // the truncated frame is only given up on flush
+    assert!(scanner.is_pending());
+    scanner.flush();
+    assert_eq!(
+        scan_all(&mut scanner),
+        [ScanEvent::Garbage(frame[..6].to_vec())]
+    );
+}
@@ -0,0 +1,295 @@
+use agpio::gpio::{
+    echo::EchoRequest,
+    iic::write::{IICResultCode, IICWriteRequest},
+    raw::RawRequest,
+    read::ReadRequest,
+    toggle::ToggleRequest,
+    write::WriteRequest,
+    Error, HostController,
+};
+use agpio::sdsp::{self, ReadError};
+use agpio::sim::{
+    controller::{PinMode, VirtualController, A0},
+    i2c::SimulatedI2CBus,
+    SimTransport,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const HOST_ID: u8 = 0xAA;
+const DEVICE_ID: u8 = 0xCA;
+
+fn connect(controller: VirtualController) -> (HostController, Arc<Mutex<VirtualController>>) {
+    let controller = Arc::new(Mutex::new(controller));
+    let host = HostController::new(
+        Box::new(SimTransport::new(controller.clone())),
+        HOST_ID,
+        Some(Duration::from_millis(20)),
+        Some(0),
+    );
+    (host, controller)
+}
+
+#[test]
+fn digital_write_and_read_back() {
+    let (mut host, controller) = connect(VirtualController::default());
+
+    host.send(&WriteRequest::new(13, 1, false, false), DEVICE_ID)
+        .unwrap();
+    let pin = controller.lock().unwrap().pin(13).unwrap().clone();
+    assert_eq!(pin.mode, PinMode::Output);
+    assert!(pin.output);
+
+    // direct read does not change the pin mode
+    let response = host
+        .send(
+            &ReadRequest::new(13, false, false, false, false, true),
+            DEVICE_ID,
+        )
+        .unwrap();
+    assert_eq!(response.value, 1);
+
+    // inverted write
+    host.send(&WriteRequest::new(13, 1, false, true), DEVICE_ID)
+        .unwrap();
+    assert!(!controller.lock().unwrap().pin(13).unwrap().output);
+}
+
+#[test]
+fn digital_read_inputs() {
+    let (mut host, controller) = connect(VirtualController::default());
+    let read = |host: &mut HostController, invert| {
+        host.send(
+            &ReadRequest::new(7, false, false, false, invert, false),
+            DEVICE_ID,
+        )
+        .unwrap()
+        .value
+    };
+
+    // floating pins read LOW
+    assert_eq!(read(&mut host, false), 0);
+
+    controller.lock().unwrap().set_input(7, Some(true));
+    assert_eq!(read(&mut host, false), 1);
+    assert_eq!(read(&mut host, true), 0);
+    assert_eq!(
+        controller.lock().unwrap().pin(7).unwrap().mode,
+        PinMode::Input
+    );
+}
+
+#[test]
+fn analog_read_and_write() {
+    let (mut host, controller) = connect(VirtualController::default());
+    controller.lock().unwrap().set_analog_input(A0, 700);
+
+    let value = host
+        .send(
+            &ReadRequest::new(A0, false, false, true, false, false),
+            DEVICE_ID,
+        )
+        .unwrap()
+        .value;
+    assert_eq!(value, 700);
+
+    let inverted = host
+        .send(
+            &ReadRequest::new(A0, false, false, true, true, false),
+            DEVICE_ID,
+        )
+        .unwrap()
+        .value;
+    assert_eq!(inverted, 1024 - 700);
+
+    host.send(&WriteRequest::new(9, 120, true, false), DEVICE_ID)
+        .unwrap();
+    assert_eq!(controller.lock().unwrap().pin(9).unwrap().pwm, Some(120));
+
+    host.send(&WriteRequest::new(9, 120, true, true), DEVICE_ID)
+        .unwrap();
+    assert_eq!(controller.lock().unwrap().pin(9).unwrap().pwm, Some(135));
+}
+
+#[test]
+fn toggle_flips_output() {
+    let (mut host, controller) = connect(VirtualController::default());
+
+    let first = host.send(&ToggleRequest::new(5), DEVICE_ID).unwrap();
+    let second = host.send(&ToggleRequest::new(5), DEVICE_ID).unwrap();
+    assert_eq!((first.new_value, second.new_value), (1, 0));
+    assert_eq!(
+        controller.lock().unwrap().pin(5).unwrap().mode,
+        PinMode::Output
+    );
+}
+
+#[test]
+fn analog_pins_are_valid_digital_pins() {
+    let (mut host, controller) = connect(VirtualController::default());
+
+    host.send(&WriteRequest::new(A0, 1, false, false), DEVICE_ID)
+        .unwrap();
+    assert!(controller.lock().unwrap().pin(A0).unwrap().output);
+    let response = host
+        .send(
+            &ReadRequest::new(A0, false, false, false, false, true),
+            DEVICE_ID,
+        )
+        .unwrap();
+    assert_eq!(response.value, 1);
+    let response = host.send(&ToggleRequest::new(A0), DEVICE_ID).unwrap();
+    assert_eq!(response.new_value, 0);
+
+    // but analog pins cannot do PWM
+    assert_eq!(
+        host.send(&WriteRequest::new(A0, 10, true, false), DEVICE_ID)
+            .unwrap_err(),
+        Error::InvalidPin
+    );
+}
+
+#[test]
+fn invalid_pins_are_rejected() {
+    let (mut host, _) = connect(VirtualController::default());
+
+    // pins 0 and 1 are used for serial
+    assert_eq!(
+        host.send(&WriteRequest::new(1, 1, false, false), DEVICE_ID)
+            .unwrap_err(),
+        Error::InvalidPin
+    );
+
+    // pin 13 cannot do PWM, pin 7 cannot do analog read
+    assert_eq!(
+        host.send(&WriteRequest::new(13, 10, true, false), DEVICE_ID)
+            .unwrap_err(),
+        Error::InvalidPin
+    );
+    assert_eq!(
+        host.send(
+            &ReadRequest::new(7, false, false, true, false, false),
+            DEVICE_ID
+        )
+        .unwrap_err(),
+        Error::InvalidPin
+    );
+
+    // direct mode cannot be combined with pullup
+    assert_eq!(
+        host.send(
+            &ReadRequest::new(7, true, false, false, false, true),
+            DEVICE_ID
+        )
+        .unwrap_err(),
+        Error::RemoteError { code: 0x02 }
+    );
+
+    // the trailing malformed packet error of the firmware does not affect the next request
+    assert!(host.send(&ToggleRequest::new(13), DEVICE_ID).is_ok());
+}
+
+#[test]
+fn i2c_write() {
+    let bus = SimulatedI2CBus::new();
+    bus.add_device(0x27);
+    let (mut host, _) = connect(VirtualController::default().with_i2c_bus(bus.clone()));
+
+    let present = host
+        .send(&IICWriteRequest::new(0x27, vec![1, 2, 3], true), DEVICE_ID)
+        .unwrap();
+    assert_eq!(present.result_code, IICResultCode::Success);
+
+    let absent = host
+        .send(&IICWriteRequest::new(0x28, vec![], true), DEVICE_ID)
+        .unwrap();
+    assert_eq!(absent.result_code, IICResultCode::NACKOnAddress);
+
+    let transactions = bus.transactions();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0].data, vec![1, 2, 3]);
+    assert!(transactions[0].stop);
+}
+
+#[test]
+fn addressing() {
+    let (mut host, _) = connect(VirtualController::default());
+
+    // broadcast is answered
+    assert!(host
+        .send(&ToggleRequest::new(13), sdsp::BROADCAST_ID)
+        .is_ok());
+
+    // requests for other controllers are ignored
+    assert_eq!(
+        host.send(&ToggleRequest::new(13), 0x42).unwrap_err(),
+        Error::SDSPError {
+            kind: ReadError::Timeout
+        }
+    );
+}
+
+#[test]
+fn malformed_frames() {
+    let mut controller = VirtualController::default();
+    let mut out = Vec::new();
+
+    // corrupted checksum
+    let mut frame = Vec::new();
+    sdsp::encode_packet(HOST_ID, DEVICE_ID, &[0x03, 13], &mut frame);
+    frame[6] ^= 0xff;
+    controller.receive(&frame, &mut out);
+    let response = sdsp::decode_packet(&out).unwrap();
+    assert_eq!(response.receiver_id, HOST_ID);
+    assert_eq!(response.body, &[0xff, 0x01]);
+
+    // unknown command type is answered with a single error
+    out.clear();
+    sdsp::encode_packet(HOST_ID, DEVICE_ID, &[0x10], &mut frame);
+    controller.receive(&frame, &mut out);
+    assert_eq!(sdsp::decode_packet(&out).unwrap().body, &[0xff, 0x02]);
+
+    // packets that do not fit into the firmware buffer are rejected
+    out.clear();
+    sdsp::encode_packet(HOST_ID, DEVICE_ID, &[0x04; 30], &mut frame);
+    controller.receive(&frame, &mut out);
+    assert_eq!(sdsp::decode_packet(&out).unwrap().body, &[0xff, 0x01]);
+}
+
+#[test]
+fn raw_requests() {
+    let (mut host, _) = connect(VirtualController::default());
+
+    // a known command, sent raw
+    let response = host
+        .send(&RawRequest::new(0x03, vec![13]), DEVICE_ID)
+        .unwrap();
+    assert_eq!(response.body, [1]);
+
+    // an unknown command is answered with an error response
+    assert_eq!(
+        host.send(&RawRequest::new(0x10, vec![1, 2, 3]), DEVICE_ID),
+        Err(Error::RemoteError { code: 0x02 })
+    );
+}
+
+#[test]
+fn echo_requests() {
+    let (mut host, _) = connect(VirtualController::default());
+
+    // start and end bytes in the body do not confuse the framing
+    let data = vec![0x7b, 0x7d, 0x7b, 0x00, 0x7d];
+    let response = host
+        .send(&EchoRequest::new(data.clone()), DEVICE_ID)
+        .unwrap();
+    assert_eq!(response.data, data);
+
+    // the firmware buffer holds 24 bytes of packet body
+    assert!(host
+        .send(&EchoRequest::new(vec![0x7b; 23]), DEVICE_ID)
+        .is_ok());
+    assert_eq!(
+        host.send(&EchoRequest::new(vec![0x7b; 24]), DEVICE_ID),
+        Err(Error::RemoteError { code: 0x01 })
+    );
+}
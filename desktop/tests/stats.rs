@@ -0,0 +1,82 @@
+use agpio::gpio::{stats::Histogram, toggle::ToggleRequest, write::WriteRequest, HostController};
+use agpio::transport::mock::{Expectation, MockTransport};
+use std::time::Duration;
+
+#[test]
+fn requests_are_counted_per_device_and_type() {
+    let mock = MockTransport::new();
+
+    // a timeout and a mismatching response before the real response
+    mock.expect(Expectation::body([0x03, 13]))
+        .expect(Expectation::body([0x03, 13]).reply_body([0x82]))
+        .expect(Expectation::body([0x03, 13]).reply_body([0x83, 0x01]))
+        .expect(Expectation::any().to(0xCB))
+        .expect(Expectation::any().to(0xCB))
+        .expect(Expectation::any().to(0xCB));
+
+    let mut host = HostController::new(
+        Box::new(mock.clone()),
+        0xAA,
+        Some(Duration::from_millis(10)),
+        Some(2),
+    );
+    host.send(&ToggleRequest::new(13), 0xCA).unwrap();
+    host.send(&WriteRequest::new(13, 1, false, false), 0xCB)
+        .unwrap_err();
+    mock.verify();
+
+    let stats = host.stats();
+    assert_eq!(stats.total.requests, 2);
+    assert_eq!(stats.total.successes, 1);
+    assert_eq!(stats.total.failures, 1);
+    assert_eq!(stats.total.retries, 4);
+    assert_eq!(stats.total.timeouts, 4);
+    assert_eq!(stats.total.mismatches, 1);
+
+    let toggle = &stats.types[&0x03];
+    assert_eq!(toggle, &stats.devices[&0xCA]);
+    assert_eq!(
+        (toggle.requests, toggle.successes, toggle.retries),
+        (1, 1, 2)
+    );
+
+    // only valid response frames have a latency
+    assert_eq!(toggle.latency.count(), 2);
+    assert_eq!(stats.devices[&0xCB].latency.count(), 0);
+    assert_eq!(stats.devices[&0xCB].failures, 1);
+
+    host.reset_stats();
+    assert_eq!(host.stats().total.requests, 0);
+}
+
+#[test]
+fn histogram_percentiles() {
+    let mut histogram = Histogram::default();
+    assert_eq!(histogram.percentile(50.0), None);
+
+    for ms in 1..=100 {
+        histogram.record(Duration::from_millis(ms));
+    }
+    assert_eq!(histogram.count(), 100);
+    assert_eq!(histogram.min(), Some(Duration::from_millis(1)));
+    assert_eq!(histogram.max(), Some(Duration::from_millis(100)));
+    assert_eq!(histogram.mean(), Some(Duration::from_micros(50_500)));
+
+    // percentiles are accurate to the bucket resolution of about 12%
+    let within = |actual: Option<Duration>, expected_ms: f64| {
+        let actual = actual.unwrap().as_secs_f64() * 1000.0;
+        assert!(
+            (actual - expected_ms).abs() <= expected_ms * 0.125,
+            "{} is not close to {}",
+            actual,
+            expected_ms
+        );
+    };
+    within(histogram.percentile(50.0), 50.0);
+    within(histogram.percentile(90.0), 90.0);
+    within(histogram.percentile(99.0), 99.0);
+    assert_eq!(
+        histogram.percentile(100.0),
+        Some(Duration::from_millis(100))
+    );
+}
@@ -0,0 +1,242 @@
+-- Wireshark dissector for SDSP captures written by `agpio --pcap`
+--
+-- installation: copy this file to the Wireshark personal plugins folder
+-- (see Help -> About Wireshark -> Folders), or load it for a single session:
+--
+--   wireshark -X lua_script:sdsp.lua capture.pcap
+--
+-- every capture record holds a direction byte (0x00 = sent by the host, 0x01 = received by the host)
+-- followed by a single SDSP frame. see SDSP.md and PROTOCOL.md for the frame and command layout.
+
+local sdsp = Proto("sdsp", "Small Device Serial Protocol")
+local agpio = Proto("agpio", "Arduino-as-GPIO Command Protocol")
+
+--
+-- SDSP fields
+--
+local directions = { [0x00] = "sent by host", [0x01] = "received by host" }
+
+local f_direction = ProtoField.uint8("sdsp.direction", "Direction", base.HEX, directions)
+local f_start = ProtoField.uint8("sdsp.start", "Start Byte", base.HEX)
+local f_sender = ProtoField.uint8("sdsp.sender", "Sender ID", base.HEX)
+local f_receiver = ProtoField.uint8("sdsp.receiver", "Receiver ID", base.HEX)
+local f_length = ProtoField.uint16("sdsp.length", "Body Length", base.DEC)
+local f_body = ProtoField.bytes("sdsp.body", "Body")
+local f_checksum = ProtoField.uint16("sdsp.checksum", "Checksum", base.HEX)
+local f_checksum_status = ProtoField.bool("sdsp.checksum.valid", "Checksum Valid")
+local f_end = ProtoField.uint8("sdsp.end", "End Byte", base.HEX)
+
+sdsp.fields = {
+    f_direction, f_start, f_sender, f_receiver, f_length, f_body, f_checksum, f_checksum_status, f_end,
+}
+
+local e_checksum = ProtoExpert.new("sdsp.checksum.bad", "Checksum mismatch", expert.group.CHECKSUM, expert.severity.ERROR)
+local e_malformed = ProtoExpert.new("sdsp.malformed", "Malformed frame", expert.group.MALFORMED, expert.severity.ERROR)
+sdsp.experts = { e_checksum, e_malformed }
+
+--
+-- command protocol fields
+--
+local packet_types = {
+    [0x01] = "read request",
+    [0x02] = "write request",
+    [0x03] = "toggle request",
+    [0x04] = "i2c write request",
+    [0x05] = "echo request",
+    [0x81] = "read response",
+    [0x82] = "write response",
+    [0x83] = "toggle response",
+    [0x84] = "i2c write response",
+    [0x85] = "echo response",
+    [0xff] = "error response",
+}
+local error_codes = {
+    [0x01] = "malformed packet",
+    [0x02] = "invalid packet type",
+    [0x03] = "invalid pin",
+}
+local i2c_results = {
+    [0x00] = "success",
+    [0x01] = "data too long",
+    [0x02] = "NACK on address",
+    [0x03] = "NACK on data",
+    [0x04] = "other error",
+    [0x05] = "timeout",
+}
+
+local f_type = ProtoField.uint8("agpio.type", "Packet Type", base.HEX, packet_types)
+local f_pin = ProtoField.uint8("agpio.pin", "Pin", base.DEC)
+local f_read_flags = ProtoField.uint8("agpio.read.flags", "Flags", base.HEX)
+local f_read_pullup = ProtoField.bool("agpio.read.pullup", "Pullup", 8, nil, 0x01)
+local f_read_pulldown = ProtoField.bool("agpio.read.pulldown", "Pulldown", 8, nil, 0x02)
+local f_read_analog = ProtoField.bool("agpio.read.analog", "Analog", 8, nil, 0x04)
+local f_read_invert = ProtoField.bool("agpio.read.invert", "Invert", 8, nil, 0x08)
+local f_read_direct = ProtoField.bool("agpio.read.direct", "Direct", 8, nil, 0x10)
+local f_write_flags = ProtoField.uint8("agpio.write.flags", "Flags", base.HEX)
+local f_write_analog = ProtoField.bool("agpio.write.analog", "Analog", 8, nil, 0x01)
+local f_write_invert = ProtoField.bool("agpio.write.invert", "Invert", 8, nil, 0x02)
+local f_value16 = ProtoField.uint16("agpio.value", "Value", base.DEC)
+local f_value8 = ProtoField.uint8("agpio.new_value", "New Value", base.DEC)
+local f_i2c_address = ProtoField.uint8("agpio.i2c.address", "Address", base.HEX)
+local f_i2c_flags = ProtoField.uint8("agpio.i2c.flags", "Flags", base.HEX)
+local f_i2c_stop = ProtoField.bool("agpio.i2c.stop", "Stop", 8, nil, 0x01)
+local f_i2c_data = ProtoField.bytes("agpio.i2c.data", "Data")
+local f_i2c_result = ProtoField.uint8("agpio.i2c.result", "Result", base.HEX, i2c_results)
+local f_echo_data = ProtoField.bytes("agpio.echo.data", "Data")
+local f_error = ProtoField.uint8("agpio.error", "Error Code", base.HEX, error_codes)
+
+agpio.fields = {
+    f_type, f_pin, f_read_flags, f_read_pullup, f_read_pulldown, f_read_analog, f_read_invert, f_read_direct,
+    f_write_flags, f_write_analog, f_write_invert, f_value16, f_value8,
+    f_i2c_address, f_i2c_flags, f_i2c_stop, f_i2c_data, f_i2c_result, f_echo_data, f_error,
+}
+
+--
+-- SDSP checksum: CRC-16, polynomial 0x1021 (reflected), initial value 0x0000,
+-- calculated over the entire frame with the checksum field set to 0
+--
+local function crc16(bytes)
+    local crc = 0
+    for i = 0, bytes:len() - 1 do
+        crc = bit.bxor(crc, bytes:get_index(i))
+        for _ = 1, 8 do
+            if bit.band(crc, 1) ~= 0 then
+                crc = bit.bxor(bit.rshift(crc, 1), 0x1021)
+            else
+                crc = bit.rshift(crc, 1)
+            end
+        end
+    end
+    return crc
+end
+
+--
+-- command protocol dissector
+--
+function agpio.dissector(buffer, pinfo, tree)
+    if buffer:len() < 1 then
+        return 0
+    end
+
+    local subtree = tree:add(agpio, buffer())
+    local packet_type = buffer(0, 1):uint()
+    subtree:add(f_type, buffer(0, 1))
+    local name = packet_types[packet_type] or string.format("unknown type 0x%02x", packet_type)
+    pinfo.cols.info:append(": " .. name)
+
+    local len = buffer:len()
+    if packet_type == 0x01 and len == 3 then
+        subtree:add(f_pin, buffer(1, 1))
+        local flags = subtree:add(f_read_flags, buffer(2, 1))
+        flags:add(f_read_pullup, buffer(2, 1))
+        flags:add(f_read_pulldown, buffer(2, 1))
+        flags:add(f_read_analog, buffer(2, 1))
+        flags:add(f_read_invert, buffer(2, 1))
+        flags:add(f_read_direct, buffer(2, 1))
+        pinfo.cols.info:append(string.format(" pin %d", buffer(1, 1):uint()))
+    elseif packet_type == 0x02 and len == 5 then
+        subtree:add(f_pin, buffer(1, 1))
+        subtree:add(f_value16, buffer(2, 2))
+        local flags = subtree:add(f_write_flags, buffer(4, 1))
+        flags:add(f_write_analog, buffer(4, 1))
+        flags:add(f_write_invert, buffer(4, 1))
+        pinfo.cols.info:append(string.format(" pin %d = %d", buffer(1, 1):uint(), buffer(2, 2):uint()))
+    elseif packet_type == 0x03 and len == 2 then
+        subtree:add(f_pin, buffer(1, 1))
+        pinfo.cols.info:append(string.format(" pin %d", buffer(1, 1):uint()))
+    elseif packet_type == 0x04 and len >= 3 then
+        subtree:add(f_i2c_address, buffer(1, 1))
+        local flags = subtree:add(f_i2c_flags, buffer(2, 1))
+        flags:add(f_i2c_stop, buffer(2, 1))
+        if len > 3 then
+            subtree:add(f_i2c_data, buffer(3))
+        end
+        pinfo.cols.info:append(string.format(" address 0x%02x, %d bytes", buffer(1, 1):uint(), len - 3))
+    elseif packet_type == 0x05 or packet_type == 0x85 then
+        if len > 1 then
+            subtree:add(f_echo_data, buffer(1))
+        end
+        pinfo.cols.info:append(string.format(" %d bytes", len - 1))
+    elseif packet_type == 0x81 and len == 3 then
+        subtree:add(f_value16, buffer(1, 2))
+        pinfo.cols.info:append(string.format(" = %d", buffer(1, 2):uint()))
+    elseif packet_type == 0x82 and len == 1 then
+        -- no data
+    elseif packet_type == 0x83 and len == 2 then
+        subtree:add(f_value8, buffer(1, 1))
+        pinfo.cols.info:append(string.format(" = %d", buffer(1, 1):uint()))
+    elseif packet_type == 0x84 and len == 2 then
+        subtree:add(f_i2c_result, buffer(1, 1))
+        pinfo.cols.info:append(" " .. (i2c_results[buffer(1, 1):uint()] or "unknown result"))
+    elseif packet_type == 0xff and len == 2 then
+        subtree:add(f_error, buffer(1, 1))
+        pinfo.cols.info:append(" " .. (error_codes[buffer(1, 1):uint()] or "unknown error"))
+    elseif packet_types[packet_type] ~= nil then
+        subtree:add_proto_expert_info(e_malformed, "unexpected body length for " .. name)
+    end
+
+    return len
+end
+
+--
+-- SDSP dissector
+--
+function sdsp.dissector(buffer, pinfo, tree)
+    local len = buffer:len()
+    if len < 1 then
+        return 0
+    end
+
+    pinfo.cols.protocol = "SDSP"
+    local subtree = tree:add(sdsp, buffer())
+    subtree:add(f_direction, buffer(0, 1))
+
+    -- the frame follows the direction byte
+    local frame = buffer(1):tvb()
+    local frame_len = frame:len()
+    if frame_len < 8 then
+        subtree:add_proto_expert_info(e_malformed, "frame too short")
+        return len
+    end
+
+    subtree:add(f_start, frame(0, 1))
+    subtree:add(f_sender, frame(1, 1))
+    subtree:add(f_receiver, frame(2, 1))
+    subtree:add(f_length, frame(3, 2))
+
+    local body_len = frame(3, 2):uint()
+    pinfo.cols.info = string.format("0x%02x -> 0x%02x", frame(1, 1):uint(), frame(2, 1):uint())
+    if frame(0, 1):uint() ~= 0x7b or frame_len ~= body_len + 8 then
+        subtree:add_proto_expert_info(e_malformed, "invalid start byte or length")
+        return len
+    end
+
+    if body_len > 0 then
+        subtree:add(f_body, frame(5, body_len))
+    end
+
+    -- validate the checksum
+    local checksum_item = subtree:add(f_checksum, frame(5 + body_len, 2))
+    local bytes = frame:bytes()
+    bytes:set_index(5 + body_len, 0)
+    bytes:set_index(6 + body_len, 0)
+    local valid = crc16(bytes) == frame(5 + body_len, 2):uint()
+    subtree:add(f_checksum_status, valid):set_generated()
+    if not valid then
+        checksum_item:add_proto_expert_info(e_checksum)
+    end
+
+    subtree:add(f_end, frame(7 + body_len, 1))
+    if frame(7 + body_len, 1):uint() ~= 0x7d then
+        subtree:add_proto_expert_info(e_malformed, "invalid end byte")
+    end
+
+    -- decode the command in the body
+    if body_len > 0 then
+        agpio.dissector:call(frame(5, body_len):tvb(), pinfo, tree)
+    end
+
+    return len
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, sdsp)